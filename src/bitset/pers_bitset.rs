@@ -0,0 +1,356 @@
+//! Persistent bitset.
+//!
+//! Bitset is a path-copied binary tree of 64-bit words, so every update writes O(lg(N)) nodes
+//! and old revisions share all untouched words. Besides ```set```/```clear```/```test``` it
+//! answers ```rank``` and ```select``` queries in O(lg(N)) time.
+
+use inner::bit_tree::*;
+use inner::persistent::*;
+use std::cell::RefCell;
+use std::cmp::max;
+use std::collections::BTreeMap as TreeMap;
+use std::rc::Rc;
+use std::vec::Vec;
+
+type Node = Rc<BitTree>;
+type SharedData = Rc<RefCell<SharedBitSetData>>;
+
+
+
+struct SharedBitSetData {
+    last_revision: Revision, // revision counter
+    roots:         TreeMap<Revision, (Node, uint)>, // root node and its height for each revision
+}
+
+/// Persistent bitset implementation.
+///
+/// # Examples
+///
+/// ```
+/// let mut bits = PersBitSet::new();
+/// bits.set(3);
+/// let rev = bits.set(100);
+/// bits.clear(3);
+///
+/// assert!(!bits.test(3));
+/// assert_eq!(bits.rank(101), 1);
+/// assert_eq!(bits.get_by_revision(rev).select(1), Some(100));
+/// ```
+///
+/// Bitwise operations take the other operand from any revision:
+///
+/// ```
+/// let mut bits = PersBitSet::new();
+/// bits.set(1);
+/// let rev = bits.set(2);
+/// bits.clear(1);
+/// bits.set(5);
+/// let old = bits.get_by_revision(rev);
+/// bits.and(&old);
+/// assert_eq!(bits.count(), 1);
+/// ```
+pub struct PersBitSet {
+    line_history:     Vec<Revision>, // branch of history for undo-redo
+    head_revision_id: uint, // id of the current verision in line_history vector
+    root:             Node, // root node for the current revision
+    height:           uint, // height of the root node, leaves have zero height
+    shared_data:      SharedData, // shared data between all revision
+}
+
+impl PersBitSet {
+    /// Constructs a new, empty persistent bitset.
+    pub fn new() -> PersBitSet {
+        let mut roots = TreeMap::new();
+        roots.insert(1, (BitTree::new_zero(), 0u));
+        let shdata = Rc::new(RefCell::new(SharedBitSetData{last_revision: 1,
+                                                           roots: roots}));
+        PersBitSet{line_history: vec![1],
+                   head_revision_id: 0,
+                   root: BitTree::new_zero(),
+                   height: 0,
+                   shared_data: shdata}
+    }
+
+    fn commit(&mut self, root: Node, height: uint) -> Revision {
+        let mut data = self.shared_data.borrow_mut();
+        let revision = data.last_revision + 1;
+        data.roots.insert(revision, (root.clone(), height));
+        data.last_revision = revision;
+
+        self.head_revision_id += 1;
+        self.line_history.truncate(self.head_revision_id);
+        self.line_history.push(revision);
+        self.root = root;
+        self.height = height;
+
+        revision
+    }
+
+    fn restore_head(&mut self) {
+        let rev = self.line_history[self.head_revision_id];
+        let (root, height) = self.shared_data.borrow().roots[rev].clone();
+        self.root = root;
+        self.height = height;
+    }
+
+    // root node lifted to the given height
+    fn lifted(&self, height: uint) -> Node {
+        let mut root = self.root.clone();
+        for _ in range(self.height, height) {
+            root = BitTree::grow(&root);
+        }
+        root
+    }
+
+    fn assign(&mut self, index: uint, value: bool) -> Revision {
+        assert!(index < capacity(MAX_HEIGHT), "bit index is out of range");
+
+        let mut height = self.height;
+        while index >= capacity(height) {
+            height += 1;
+        }
+        let root = BitTree::assign(&self.lifted(height), height, index, value);
+        self.commit(root, height)
+    }
+
+    fn combine(&mut self, other: &PersBitSet, op: BitOp) -> Revision {
+        let height = max(self.height, other.height);
+        let root = BitTree::combine(&self.lifted(height), &other.lifted(height), height, op);
+        self.commit(root, height)
+    }
+
+    /// Returns ```true``` if the bit is set in the current revision.
+    pub fn test(&self, index: uint) -> bool {
+        index < capacity(self.height) && self.root.test(self.height, index)
+    }
+
+    /// Set the bit.
+    ///
+    /// Returns new revision id.
+    ///
+    /// # Panics
+    /// Panics if ```index``` is not less than ```2^(uint::BITS - 1)```.
+    pub fn set(&mut self, index: uint) -> Revision {
+        self.assign(index, true)
+    }
+
+    /// Clear the bit.
+    ///
+    /// The tree does not grow for a bit beyond the current range, as it is already clear.
+    ///
+    /// Returns new revision id.
+    pub fn clear(&mut self, index: uint) -> Revision {
+        if index >= capacity(self.height) {
+            let (root, height) = (self.root.clone(), self.height);
+            return self.commit(root, height);
+        }
+        self.assign(index, false)
+    }
+
+    /// Returns the number of set bits.
+    pub fn count(&self) -> uint {
+        self.root.count()
+    }
+
+    /// Returns the number of set bits with positions less than ```index```.
+    pub fn rank(&self, index: uint) -> uint {
+        self.root.rank(self.height, index)
+    }
+
+    /// Returns the position of the ```k```-th set bit, counting from zero.
+    pub fn select(&self, k: uint) -> Option<uint> {
+        self.root.select(self.height, k)
+    }
+
+    /// Intersect with the ```other``` bitset, which may be any revision of this or another set.
+    ///
+    /// Returns new revision id.
+    pub fn and(&mut self, other: &PersBitSet) -> Revision {
+        self.combine(other, BitOp::And)
+    }
+
+    /// Unite with the ```other``` bitset.
+    ///
+    /// Returns new revision id.
+    pub fn or(&mut self, other: &PersBitSet) -> Revision {
+        self.combine(other, BitOp::Or)
+    }
+
+    /// Symmetric difference with the ```other``` bitset.
+    ///
+    /// Returns new revision id.
+    pub fn xor(&mut self, other: &PersBitSet) -> Revision {
+        self.combine(other, BitOp::Xor)
+    }
+
+    /// Returns iterator over positions of set bits in ascending order.
+    pub fn iter<'a>(&'a self) -> Ones<'a> {
+        Ones{set: self, next: 0}
+    }
+}
+
+impl Persistent<PersBitSet> for PersBitSet {
+    fn get_by_revision(&self, revision : Revision) -> PersBitSet {
+        assert!(self.shared_data.borrow().roots.contains_key(&revision));
+
+        let (root, height) = self.shared_data.borrow().roots[revision].clone();
+        PersBitSet{line_history: vec![revision],
+                   head_revision_id: 0,
+                   root: root,
+                   height: height,
+                   shared_data: self.shared_data.clone()}
+    }
+
+    fn current_revision_id(&self) -> Revision {
+        self.line_history[self.head_revision_id]
+    }
+}
+
+impl TryPersistent<PersBitSet> for PersBitSet {
+    fn contains_revision(&self, revision : Revision) -> bool {
+        self.shared_data.borrow().roots.contains_key(&revision)
+    }
+}
+
+impl Recall for PersBitSet {
+    fn undo(&mut self) -> Revision {
+        assert!(self.head_revision_id > 0u);
+
+        self.head_revision_id -= 1;
+        self.restore_head();
+        self.line_history[self.head_revision_id]
+    }
+
+    fn redo(&mut self) -> Revision {
+        assert!(self.head_revision_id + 1u < self.line_history.len());
+
+        self.head_revision_id += 1;
+        self.restore_head();
+        self.line_history[self.head_revision_id]
+    }
+}
+
+impl TryRecall for PersBitSet {
+    fn can_undo(&self) -> bool {
+        self.head_revision_id > 0u
+    }
+
+    fn can_redo(&self) -> bool {
+        self.head_revision_id + 1u < self.line_history.len()
+    }
+
+    fn line_revision(&self) -> Revision {
+        self.line_history[self.head_revision_id]
+    }
+}
+
+impl FullyPersistent<PersBitSet> for PersBitSet { }
+
+impl Clone for PersBitSet {
+    fn clone(&self) -> Self {
+        PersBitSet{line_history: self.line_history.clone(),
+                   head_revision_id: self.head_revision_id,
+                   root: self.root.clone(),
+                   height: self.height,
+                   shared_data: self.shared_data.clone()}
+    }
+}
+
+pub struct Ones<'a> {
+    set:  &'a PersBitSet,
+    next: uint, // rank of the next set bit
+}
+
+impl<'a> Iterator<uint> for Ones<'a> {
+    fn next(&mut self) -> Option<uint> {
+        let result = self.set.select(self.next);
+        if result.is_some() {
+            self.next += 1;
+        }
+        result
+    }
+
+    fn size_hint(&self) -> (uint, Option<uint>) {
+        let left = self.set.count() - self.next;
+        (left, Some(left))
+    }
+}
+
+#[test]
+fn bitset_set_clear_test() {
+    let mut bits = PersBitSet::new();
+    bits.set(1);
+    let rev_a = bits.set(1000);
+    bits.clear(1);
+    let rev_b = bits.set(5);
+
+    assert!(!bits.test(1));
+    assert!(bits.test(5));
+    assert!(bits.test(1000));
+    assert!(!bits.test(100000));
+    assert_eq!(bits.count(), 2u);
+
+    let bits_a = bits.get_by_revision(rev_a);
+    assert_eq!(bits_a.iter().collect::<Vec<uint>>(), vec![1, 1000]);
+    assert_eq!(bits.get_by_revision(rev_b).iter().collect::<Vec<uint>>(), vec![5, 1000]);
+
+    let mut ones = bits.iter();
+    assert_eq!(ones.by_ref().count(), 2u);
+    assert_eq!(ones.next(), None);
+    assert_eq!(ones.size_hint(), (0u, Some(0u)));
+}
+
+#[test]
+fn bitset_large_index_test() {
+    use std::uint;
+
+    let mut bits = PersBitSet::new();
+    bits.set(3);
+    bits.clear(uint::MAX);
+    assert_eq!(bits.height, 0u);
+    assert!(!bits.test(uint::MAX));
+
+    bits.set(uint::MAX / 2);
+    assert!(bits.test(uint::MAX / 2));
+    assert_eq!(bits.rank(uint::MAX), 2u);
+    assert_eq!(bits.select(1), Some(uint::MAX / 2));
+}
+
+#[test]
+fn bitset_rank_select_test() {
+    let mut bits = PersBitSet::new();
+    for i in range(0u, 100) {
+        bits.set(i * 7);
+    }
+    for k in range(0u, 100) {
+        assert_eq!(bits.select(k), Some(k * 7));
+        assert_eq!(bits.rank(k * 7), k);
+    }
+    assert_eq!(bits.select(100), None);
+    assert_eq!(bits.rank(1000000), 100u);
+}
+
+#[test]
+fn bitset_bitwise_between_revisions_test() {
+    let mut bits = PersBitSet::new();
+    bits.set(1);
+    bits.set(2);
+    let rev_old = bits.set(3);
+    bits.clear(2);
+    bits.set(4000);
+
+    let old = bits.get_by_revision(rev_old);
+    let mut and = bits.clone();
+    and.and(&old);
+    let mut or = bits.clone();
+    or.or(&old);
+    let mut xor = bits.clone();
+    xor.xor(&old);
+
+    assert_eq!(and.iter().collect::<Vec<uint>>(), vec![1, 3]);
+    assert_eq!(or.iter().collect::<Vec<uint>>(), vec![1, 2, 3, 4000]);
+    assert_eq!(xor.iter().collect::<Vec<uint>>(), vec![2, 4000]);
+
+    xor.undo();
+    assert_eq!(xor.count(), 3u);
+}
@@ -0,0 +1 @@
+pub mod pers_deque;
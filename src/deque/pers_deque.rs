@@ -0,0 +1,338 @@
+//! Persistent double-ended queue.
+//!
+//! Deque is built on top of a 2-3 finger tree and provides amortized O(1) push and pop at both
+//! ends, O(lg(N)) access by index, concatenation and split.
+
+use inner::finger_tree::*;
+use error::{PersError, PersResult};
+use inner::persistent::*;
+use std::cell::RefCell;
+use std::collections::BTreeMap as TreeMap;
+use std::rc::Rc;
+use std::vec::Vec;
+
+type Node<T> = Rc<FingerTree<T>>;
+type SharedData<T> = Rc<RefCell<SharedDequeData<T>>>;
+
+
+
+struct SharedDequeData<T> {
+    last_revision: Revision, // revision counter
+    roots:         TreeMap<Revision, Node<T>>, // root tree node for each revision
+}
+
+/// Persistent deque implementation.
+///
+/// # Examples
+///
+/// ```
+/// let mut deque = PersDeque::<int>::new();
+/// deque.push_back(2);
+/// deque.push_front(1);
+/// let rev = deque.push_back(3);
+/// deque.pop_front();
+/// assert_eq!(deque[0], 2);
+/// assert_eq!(deque.get_by_revision(rev)[0], 1);
+/// ```
+///
+/// Two deques are concatenated without copying elements:
+///
+/// ```
+/// let mut a = PersDeque::<int>::new();
+/// let mut b = PersDeque::<int>::new();
+/// a.push_back(1);
+/// b.push_back(2);
+/// a.append(&b);
+/// assert_eq!(a.len(), 2u);
+/// ```
+pub struct PersDeque<T> {
+    line_history:     Vec<Revision>, // branch of history for undo-redo
+    head_revision_id: uint, // id of the current verision in line_history vector
+    root:             Node<T>, // root tree node for the current revision
+    shared_data:      SharedData<T>, // shared data between all revision
+}
+
+impl<T: Clone> PersDeque<T> {
+    /// Constructs a new, empty persistent deque.
+    pub fn new() -> PersDeque<T> {
+        let mut roots = TreeMap::new();
+        roots.insert(1, FingerTree::new_empty());
+        let shdata = Rc::new(RefCell::new(SharedDequeData::<T>{last_revision: 1,
+                                                               roots: roots}));
+        PersDeque{line_history: vec![1],
+                  head_revision_id: 0,
+                  root: FingerTree::new_empty(),
+                  shared_data: shdata}
+    }
+
+    fn register(&self, root: Node<T>) -> Revision {
+        let mut data = self.shared_data.borrow_mut();
+        let revision = data.last_revision + 1;
+        data.roots.insert(revision, root);
+        data.last_revision = revision;
+        revision
+    }
+
+    fn commit(&mut self, root: Node<T>) -> Revision {
+        let revision = self.register(root.clone());
+
+        self.head_revision_id += 1;
+        self.line_history.truncate(self.head_revision_id);
+        self.line_history.push(revision);
+        self.root = root;
+
+        revision
+    }
+
+    /// Returns the number of elements in the current deque revision.
+    pub fn len(&self) -> uint {
+        self.root.size()
+    }
+
+    /// Returns ```true``` if the deque contains no elements and false otherwise.
+    pub fn is_empty(&self) -> bool {
+        self.root.is_empty()
+    }
+
+    /// Returns the first element of the current deque revision.
+    pub fn front<'a>(&'a self) -> Option<&'a T> {
+        self.root.front()
+    }
+
+    /// Returns the last element of the current deque revision.
+    pub fn back<'a>(&'a self) -> Option<&'a T> {
+        self.root.back()
+    }
+
+    /// Returns element by its index in O(lg(N)) time.
+    pub fn get<'a>(&'a self, id: uint) -> Option<&'a T> {
+        if id < self.len() {
+            Some(self.root.lookup(id))
+        } else {
+            None
+        }
+    }
+
+    /// Prepend an element to the deque.
+    ///
+    /// Returns new revision id.
+    pub fn push_front(&mut self, value: T) -> Revision {
+        let new_root = FingerTree::push_front(&self.root, FingerTree::new_leaf(value));
+        self.commit(new_root)
+    }
+
+    /// Append an element to the deque.
+    ///
+    /// Returns new revision id.
+    pub fn push_back(&mut self, value: T) -> Revision {
+        let new_root = FingerTree::push_back(&self.root, FingerTree::new_leaf(value));
+        self.commit(new_root)
+    }
+
+    /// Remove the first element of the deque.
+    ///
+    /// Returns new revision id.
+    ///
+    /// # Panics
+    /// Panics if the deque is empty.
+    pub fn pop_front(&mut self) -> Revision {
+        assert!(!self.is_empty());
+        let (_, new_root) = FingerTree::view_front(&self.root).unwrap();
+        self.commit(new_root)
+    }
+
+    /// Like ```pop_front```, but returns ```Empty``` error instead of panic.
+    pub fn try_pop_front(&mut self) -> PersResult<Revision> {
+        if self.is_empty() {
+            return Err(PersError::Empty);
+        }
+        Ok(self.pop_front())
+    }
+
+    /// Remove the last element of the deque.
+    ///
+    /// Returns new revision id.
+    ///
+    /// # Panics
+    /// Panics if the deque is empty.
+    pub fn pop_back(&mut self) -> Revision {
+        assert!(!self.is_empty());
+        let (new_root, _) = FingerTree::view_back(&self.root).unwrap();
+        self.commit(new_root)
+    }
+
+    /// Like ```pop_back```, but returns ```Empty``` error instead of panic.
+    pub fn try_pop_back(&mut self) -> PersResult<Revision> {
+        if self.is_empty() {
+            return Err(PersError::Empty);
+        }
+        Ok(self.pop_back())
+    }
+
+    /// Append all elements of the ```other``` current revision to the end of the deque.
+    ///
+    /// ```other``` may belong to a different history, its elements are shared, not copied.
+    ///
+    /// Returns new revision id.
+    pub fn append(&mut self, other: &PersDeque<T>) -> Revision {
+        let new_root = FingerTree::concat(&self.root, &other.root);
+        self.commit(new_root)
+    }
+
+    /// Split the deque into two at the given index.
+    ///
+    /// The deque keeps elements ```[0, at)``` in a new revision, and the returned deque holds
+    /// elements ```[at, len)```. Both revisions are stored in the shared history.
+    ///
+    /// # Panics
+    /// Panics if ```at > len```.
+    pub fn split_off(&mut self, at: uint) -> PersDeque<T> {
+        assert!(at <= self.len());
+
+        let (left, right) = FingerTree::split(&self.root, at);
+        self.commit(left);
+        let revision = self.register(right.clone());
+        PersDeque{line_history: vec![revision],
+                  head_revision_id: 0,
+                  root: right,
+                  shared_data: self.shared_data.clone()}
+    }
+
+    /// Returns iterator from the front to the back of the current deque revision.
+    pub fn iter<'a>(&'a self) -> FingerTreeIterator<'a, T> {
+        self.root.iter()
+    }
+}
+
+impl<T: Clone> Index<uint, T> for PersDeque<T> {
+    fn index<'a>(&'a self, id: &uint) -> &'a T {
+        self.root.lookup(*id)
+    }
+}
+
+impl<T: Clone> Extend<T> for PersDeque<T> {
+    fn extend<I: Iterator<T>>(&mut self, mut iterator: I) {
+        for element in iterator {
+            self.push_back(element);
+        }
+    }
+}
+
+impl<T: Clone> Persistent<PersDeque<T>> for PersDeque<T> {
+    fn get_by_revision(&self, revision : Revision) -> PersDeque<T> {
+        assert!(self.shared_data.borrow().roots.contains_key(&revision));
+
+        PersDeque{line_history: vec![revision],
+                  head_revision_id: 0,
+                  root: self.shared_data.borrow().roots[revision].clone(),
+                  shared_data: self.shared_data.clone()}
+    }
+
+    fn current_revision_id(&self) -> Revision {
+        self.line_history[self.head_revision_id]
+    }
+}
+
+impl<T: Clone> TryPersistent<PersDeque<T>> for PersDeque<T> {
+    fn contains_revision(&self, revision : Revision) -> bool {
+        self.shared_data.borrow().roots.contains_key(&revision)
+    }
+}
+
+impl<T: Clone> Recall for PersDeque<T> {
+    fn undo(&mut self) -> Revision {
+        assert!(self.head_revision_id > 0u);
+
+        self.head_revision_id -= 1;
+        let revision = self.line_history[self.head_revision_id];
+        self.root = self.shared_data.borrow().roots[revision].clone();
+        revision
+    }
+
+    fn redo(&mut self) -> Revision {
+        assert!(self.head_revision_id + 1u < self.line_history.len());
+
+        self.head_revision_id += 1;
+        let revision = self.line_history[self.head_revision_id];
+        self.root = self.shared_data.borrow().roots[revision].clone();
+        revision
+    }
+}
+
+impl<T: Clone> TryRecall for PersDeque<T> {
+    fn can_undo(&self) -> bool {
+        self.head_revision_id > 0u
+    }
+
+    fn can_redo(&self) -> bool {
+        self.head_revision_id + 1u < self.line_history.len()
+    }
+
+    fn line_revision(&self) -> Revision {
+        self.line_history[self.head_revision_id]
+    }
+}
+
+impl<T: Clone> FullyPersistent<PersDeque<T>> for PersDeque<T> { }
+
+impl<T: Clone> Clone for PersDeque<T> {
+    fn clone(&self) -> Self {
+        PersDeque{line_history: self.line_history.clone(),
+                  head_revision_id: self.head_revision_id,
+                  root: self.root.clone(),
+                  shared_data: self.shared_data.clone()}
+    }
+}
+
+#[test]
+fn deque_both_ends_test() {
+    let mut deque = PersDeque::<int>::new();
+    for i in range(0i, 50) {
+        deque.push_back(i);
+        deque.push_front(-i - 1);
+    }
+    assert_eq!(deque.len(), 100u);
+    assert_eq!(deque.front(), Some(&-50));
+    assert_eq!(deque.back(), Some(&49));
+    for i in range(0u, 100) {
+        assert_eq!(deque[i], i as int - 50);
+    }
+
+    deque.pop_front();
+    deque.pop_back();
+    assert_eq!(deque.iter().map(|x| *x).collect::<Vec<int>>(), range(-49i, 49).collect::<Vec<int>>());
+}
+
+#[test]
+fn deque_append_split_test() {
+    let mut a = PersDeque::<int>::new();
+    let mut b = PersDeque::<int>::new();
+    a.extend(range(0i, 10));
+    b.extend(range(10i, 25));
+
+    let rev_before = a.current_revision_id();
+    a.append(&b);
+    assert_eq!(a.iter().map(|x| *x).collect::<Vec<int>>(), range(0i, 25).collect::<Vec<int>>());
+
+    let tail = a.split_off(7);
+    assert_eq!(a.iter().map(|x| *x).collect::<Vec<int>>(), range(0i, 7).collect::<Vec<int>>());
+    assert_eq!(tail.iter().map(|x| *x).collect::<Vec<int>>(), range(7i, 25).collect::<Vec<int>>());
+    assert_eq!(a.get_by_revision(rev_before).len(), 10u);
+    assert_eq!(b.len(), 15u);
+}
+
+#[test]
+fn deque_undoredo_test() {
+    let mut deque = PersDeque::<&str>::new();
+    deque.push_back("two");
+    deque.push_front("one");
+    deque.pop_back();
+    assert_eq!(deque.back(), Some(&"one"));
+
+    deque.undo();
+    assert_eq!(deque.back(), Some(&"two"));
+    deque.undo_ntimes(2);
+    assert!(deque.is_empty());
+    deque.redo_ntimes(3);
+    assert_eq!(deque.len(), 1u);
+}
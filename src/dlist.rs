@@ -1,224 +1,865 @@
 use std::rc::Rc;
 use std::cell::*;
-use std::fmt::Show;
 use std::collections::HashMap;
+use error::{PersError, PersResult};
 use inner::persistent::*;
+use inner::lcg_random::{LCG, CoolLCG};
 use inner::revision_tree::*;
 
-type Link<A> = Option<Rc<RefCell<Node<A>>>>;
+// Appending links the other list at a merge revision, whose second parent is the head of
+// the other list, so only the boundary nodes are relinked. A node can then occur in a list
+// several times (e.g. in a list appended to itself), and every occurrence has its own
+// fields: an occurrence is a node with the path of merge revisions, nearest first, at
+// which lookups of its fields continue in the merged revision instead of the parent.
+type Path = Rc<Vec<Revision>>;
+
+struct Occurrence<A> {
+    node: Rc<RefCell<Node<A>>>,
+    path: Path,
+}
+
+impl<A> Clone for Occurrence<A> {
+    fn clone(&self) -> Occurrence<A> {
+        Occurrence {
+            node: self.node.clone(),
+            path: self.path.clone()
+        }
+    }
+}
+
+type Link<A> = Option<Occurrence<A>>;
+
+// values of a node field for every revision and path
+type Slots<B> = HashMap<Revision, Vec<(Path, B)>>;
 
 struct Node<A> {
-    value: Field<A>,
-    prev:  Field<Link<A>>,
-    next:  Field<Link<A>>,
+    value: Slots<Rc<A>>,
+    prev:  Slots<Link<A>>,
+    next:  Slots<Link<A>>,
+}
+
+impl<A> Node<A> {
+    fn new(r: Revision, v: A, p: Link<A>, n: Link<A>) -> Occurrence<A> {
+        let path = Rc::new(Vec::new());
+        let mut value = HashMap::new();
+        let mut prev = HashMap::new();
+        let mut next = HashMap::new();
+
+        value.insert(r, vec![(path.clone(), Rc::new(v))]);
+        prev.insert(r, vec![(path.clone(), p)]);
+        next.insert(r, vec![(path.clone(), n)]);
+
+        let node = Node {
+            value: value,
+            prev:  prev,
+            next:  next
+        };
+
+        Occurrence {
+            node: Rc::new(RefCell::new(node)),
+            path: path
+        }
+    }
+}
+
+// value of the field for the path at revision r, and the number of merges of the path
+// passed on the way to it
+fn find<'b, B, G: LCG>(tree: &Tree<G>, slots: &'b Slots<B>, r: Revision, path: &[Revision])
+        -> Option<(&'b B, uint)> {
+    let mut c = r;
+    let mut passed = 0u;
+    loop {
+        match slots.get(&c) {
+            None     => {},
+            Some(vs) => for &(ref p, ref v) in vs.iter() {
+                if p.as_slice() == path.slice_from(passed) {
+                    return Some((v, passed));
+                }
+            },
+        }
+        let step = if passed < path.len() && path[passed] == c {
+            passed += 1;
+            tree.graft(c)
+        } else {
+            tree.parent_of(c)
+        };
+        c = match step {
+            None    => return None,
+            Some(p) => p,
+        };
+    }
+}
+
+// a link found past the merges of the prefix continues their path
+fn rebase<A>(prefix: &[Revision], link: &Link<A>) -> Link<A> {
+    match *link {
+        None                            => None,
+        Some(ref o) if prefix.is_empty() => Some(o.clone()),
+        Some(ref o)                     => {
+            let mut path = prefix.to_vec();
+            path.push_all(o.path.as_slice());
+            Some(Occurrence { node: o.node.clone(), path: Rc::new(path) })
+        }
+    }
+}
+
+fn link_of<A, G: LCG>(tree: &Tree<G>, slots: &Slots<Link<A>>, r: Revision, path: &Path) -> Link<A> {
+    match find(tree, slots, r, path.as_slice()) {
+        None              => panic!("the node is not in the history of the revision"),
+        Some((l, passed)) => rebase(path.slice_to(passed), l),
+    }
+}
+
+fn prev_of<A, G: LCG>(tree: &Tree<G>, o: &Occurrence<A>, r: Revision) -> Link<A> {
+    link_of(tree, &o.node.borrow().prev, r, &o.path)
+}
+
+fn next_of<A, G: LCG>(tree: &Tree<G>, o: &Occurrence<A>, r: Revision) -> Link<A> {
+    link_of(tree, &o.node.borrow().next, r, &o.path)
+}
+
+fn value_of<A, G: LCG>(tree: &Tree<G>, o: &Occurrence<A>, r: Revision) -> Rc<A> {
+    match find(tree, &o.node.borrow().value, r, o.path.as_slice()) {
+        None         => panic!("the node is not in the history of the revision"),
+        Some((v, _)) => v.clone(),
+    }
+}
+
+fn write<B>(slots: &mut Slots<B>, r: Revision, path: &Path, v: B) {
+    if !slots.contains_key(&r) {
+        slots.insert(r, Vec::new());
+    }
+    let vs = slots.get_mut(&r).unwrap();
+    match vs.iter().position(|&(ref p, _)| p == path) {
+        Some(i) => vs[i] = (path.clone(), v),
+        None    => vs.push((path.clone(), v)),
+    }
 }
 
-pub struct DList<A> {
-    index: Rc<RefCell<uint>>,
+fn set_prev<A>(o: &Occurrence<A>, r: Revision, l: Link<A>) {
+    write(&mut o.node.borrow_mut().prev, r, &o.path, l);
+}
+
+fn set_next<A>(o: &Occurrence<A>, r: Revision, l: Link<A>) {
+    write(&mut o.node.borrow_mut().next, r, &o.path, l);
+}
+
+fn set_value<A>(o: &Occurrence<A>, r: Revision, v: Rc<A>) {
+    write(&mut o.node.borrow_mut().value, r, &o.path, v);
+}
+
+fn same<A>(a: &Occurrence<A>, b: &Occurrence<A>) -> bool {
+    &*a.node as *const RefCell<Node<A>> == &*b.node as *const RefCell<Node<A>> && a.path == b.path
+}
+
+fn copy_entries<B: Clone>(from: &Field<B>, to: &mut Field<B>, rs: &[Revision]) {
+    for r in rs.iter() {
+        match from.get(r) {
+            None    => {},
+            Some(v) => { to.insert(*r, v.clone()); },
+        }
+    }
+}
+
+// branch of history for undo-redo, shared by a list and its node references
+#[deriving(Clone)]
+struct Line {
+    revisions: Vec<Revision>,
+    head:      uint, // id of the current revision in revisions
+}
+
+impl Line {
+    fn new(r: Revision) -> Rc<RefCell<Line>> {
+        Rc::new(RefCell::new(Line { revisions: vec![r], head: 0 }))
+    }
+
+    fn current(&self) -> Revision {
+        self.revisions[self.head]
+    }
+
+    // the new revision becomes the head, revisions after the old head are dropped
+    fn push(&mut self, r: Revision) {
+        self.head += 1;
+        self.revisions.truncate(self.head);
+        self.revisions.push(r);
+    }
+}
+
+pub struct DList<A, G = CoolLCG> {
+    line:  Rc<RefCell<Line>>,
     front: Rc<RefCell<Field<Link<A>>>>,
     back:  Rc<RefCell<Field<Link<A>>>>,
-    tree:  Rc<RefCell<Tree>>
+    len:   Rc<RefCell<Field<Option<uint>>>>, // None until counted after a split
+    tree:  Rc<RefCell<Tree<G>>>
 }
 
-impl<A> DList<A> {
-    pub fn new() -> DList<A> {
-        let tree = Rc::new(RefCell::new(Tree::new()));
+impl<A, G: LCG> DList<A, G> {
+    pub fn new() -> DList<A, G> {
+        DList::with_generator(LCG::new())
+    }
+
+    // revision ids come from the generator, e.g. DebugLCG for sequential ones
+    pub fn with_generator(generator: G) -> DList<A, G> {
+        let tree: Rc<RefCell<Tree<G>>> = Rc::new(RefCell::new(Tree::with_generator(generator)));
         let head = tree.borrow().root();
 
-        let index = Rc::new(RefCell::new(0));
         let mut front = HashMap::new();
         let mut back = HashMap::new();
+        let mut len = HashMap::new();
 
         front.insert(head, None);
         back.insert(head, None);
+        len.insert(head, Some(0));
 
         DList {
-            index: index,
+            line:  Line::new(head),
             front: Rc::new(RefCell::new(front)),
             back:  Rc::new(RefCell::new(back)),
+            len:   Rc::new(RefCell::new(len)),
             tree:  tree
         }
     }
 
-    fn new_node(&self, r: Revision, v: A, p: Link<A>, n: Link<A>) -> Rc<RefCell<Node<A>>> {
-        let mut value = HashMap::new();
-        let mut prev = HashMap::new();
-        let mut next = HashMap::new();
+    pub fn len(&self) -> uint {
+        self.length(self.head())
+    }
 
-        value.insert(r, v);
-        prev.insert(r, p);
-        next.insert(r, n);
+    pub fn is_empty(&self) -> bool {
+        self.first(self.head()).is_none()
+    }
 
-        let node = Node {
-            value: value,
-            prev:  prev,
-            next:  next
-        };
+    fn known_len(&self, r: Revision) -> Option<uint> {
+        *self.tree.borrow()._get(self.len.borrow(), r).unwrap()
+    }
+
+    // counts the list and remembers its length if the length is unknown
+    fn length(&self, r: Revision) -> uint {
+        match self.known_len(r) {
+            Some(l) => l,
+            None    => {
+                let mut l = 0u;
+                let mut c = self.first(r);
+                loop {
+                    c = match c {
+                        None        => break,
+                        Some(ref x) => { l += 1; self.next(x, r) },
+                    };
+                }
+                self.len.borrow_mut().insert(r, Some(l));
+                l
+            }
+        }
+    }
 
-        Rc::new(RefCell::new(node))
+    fn resize(&self, h: Revision, r: Revision, delta: int) {
+        let l = self.known_len(h).map(|l| (l as int + delta) as uint);
+        self.len.borrow_mut().insert(r, l);
     }
 
     pub fn head(&self) -> Revision {
-        self.tree.borrow().revision(*self.index.borrow())
+        self.line.borrow().current()
     }
 
     pub fn push(&mut self, v: A) {
-        let h = self.head();
-        let r = self.tree.borrow_mut().fork(h);
-        let f = match *self.tree.borrow()._get(self.front.borrow(), h).unwrap() {
-            None => {
-                let n = self.new_node(r, v, None, None);
+        let (h, r) = self.fork();
+        let f = match self.first(h) {
+            None    => {
+                let n = Node::new(r, v, None, None);
                 self.back.borrow_mut().insert(r, Some(n.clone()));
                 n
             },
-            Some(ref f) => {
-                let n = self.new_node(r, v, None, Some(f.clone()));
-                f.borrow_mut().prev.insert(r, Some(n.clone()));
+            Some(f) => {
+                let n = Node::new(r, v, None, Some(f.clone()));
+                set_prev(&f, r, Some(n.clone()));
                 n
             }
         };
-        self.front.borrow_mut().insert(r, Some(f.clone()));
-        *self.index.borrow_mut() = self.tree.borrow().last_index();
+        self.front.borrow_mut().insert(r, Some(f));
+        self.resize(h, r, 1);
     }
 
     pub fn push_back(&mut self, v: A) {
-        let h = self.head();
-        let r = self.tree.borrow_mut().fork(h);
-        let b = match *self.tree.borrow()._get(self.back.borrow(), h).unwrap() {
-            None => {
-                let n = self.new_node(r, v, None, None);
+        let (h, r) = self.fork();
+        let b = match self.last(h) {
+            None    => {
+                let n = Node::new(r, v, None, None);
                 self.front.borrow_mut().insert(r, Some(n.clone()));
                 n
             },
-            Some(ref b) => {
-                let n = self.new_node(r, v, Some(b.clone()), None);
-                b.borrow_mut().next.insert(r, Some(n.clone()));
+            Some(b) => {
+                let n = Node::new(r, v, Some(b.clone()), None);
+                set_next(&b, r, Some(n.clone()));
                 n
             }
         };
-        self.back.borrow_mut().insert(r, Some(b.clone()));
-        *self.index.borrow_mut() = self.tree.borrow().last_index();
+        self.back.borrow_mut().insert(r, Some(b));
+        self.resize(h, r, 1);
+    }
+
+    // new revision from the head, which becomes the head
+    fn fork(&self) -> (Revision, Revision) {
+        let h = self.head();
+        let r = self.tree.borrow_mut().fork(h);
+        self.line.borrow_mut().push(r);
+        (h, r)
+    }
+
+    fn first(&self, r: Revision) -> Link<A> {
+        self.tree.borrow()._get(self.front.borrow(), r).unwrap().clone()
+    }
+
+    fn last(&self, r: Revision) -> Link<A> {
+        self.tree.borrow()._get(self.back.borrow(), r).unwrap().clone()
+    }
+
+    fn prev(&self, node: &Occurrence<A>, r: Revision) -> Link<A> {
+        prev_of(&*self.tree.borrow(), node, r)
+    }
+
+    fn next(&self, node: &Occurrence<A>, r: Revision) -> Link<A> {
+        next_of(&*self.tree.borrow(), node, r)
+    }
+
+    fn value(&self, node: &Occurrence<A>, r: Revision) -> Rc<A> {
+        value_of(&*self.tree.borrow(), node, r)
+    }
+
+    // true if the node is in the list at the revision
+    fn is_linked(&self, node: &Occurrence<A>, r: Revision) -> bool {
+        let link = match self.prev(node, r) {
+            None        => self.first(r),
+            Some(ref p) => self.next(p, r),
+        };
+        match link {
+            None        => false,
+            Some(ref n) => same(n, node),
+        }
     }
 
-    pub fn iter(&self, r: Revision) -> Items<A> {
-        let link = self.tree.borrow()._get(self.front.borrow(), r).unwrap();
+    // h is the revision to edit, r is the new revision forked from it
+    fn unlink(&self, h: Revision, r: Revision, node: &Occurrence<A>) {
+        let p = self.prev(node, h);
+        let n = self.next(node, h);
+        match p {
+            None        => { self.front.borrow_mut().insert(r, n.clone()); },
+            Some(ref p) => set_next(p, r, n.clone()),
+        }
+        match n {
+            None        => { self.back.borrow_mut().insert(r, p.clone()); },
+            Some(ref n) => set_prev(n, r, p.clone()),
+        }
+        self.resize(h, r, -1);
+    }
+
+    fn link_before(&self, h: Revision, r: Revision, node: &Occurrence<A>, v: A) {
+        let p = self.prev(node, h);
+        let m = Node::new(r, v, p.clone(), Some(node.clone()));
+        match p {
+            None        => { self.front.borrow_mut().insert(r, Some(m.clone())); },
+            Some(ref p) => set_next(p, r, Some(m.clone())),
+        }
+        set_prev(node, r, Some(m));
+        self.resize(h, r, 1);
+    }
+
+    fn link_after(&self, h: Revision, r: Revision, node: &Occurrence<A>, v: A) {
+        let n = self.next(node, h);
+        let m = Node::new(r, v, Some(node.clone()), n.clone());
+        match n {
+            None        => { self.back.borrow_mut().insert(r, Some(m.clone())); },
+            Some(ref n) => set_prev(n, r, Some(m.clone())),
+        }
+        set_next(node, r, Some(m));
+        self.resize(h, r, 1);
+    }
+
+    fn shares_tree(&self, other: &DList<A, G>) -> bool {
+        &*self.tree as *const RefCell<Tree<G>> == &*other.tree as *const RefCell<Tree<G>>
+    }
+
+    // makes the revision of the other list and its history visible in the tree of this one
+    fn import(&self, other: &DList<A, G>, r: Revision) {
+        let added = self.tree.borrow_mut().import(&*other.tree.borrow(), r);
+        copy_entries(&*other.front.borrow(), &mut *self.front.borrow_mut(), added.as_slice());
+        copy_entries(&*other.back.borrow(), &mut *self.back.borrow_mut(), added.as_slice());
+        copy_entries(&*other.len.borrow(), &mut *self.len.borrow_mut(), added.as_slice());
+    }
+
+    // links the elements of the other list before the node (at the back for None) in a
+    // merge revision of the head and the head of the other list
+    fn join(&self, other: &DList<A, G>, n: Link<A>) -> Revision {
+        let oh = other.head();
+        let (f, b) = (other.first(oh), other.last(oh));
+        if f.is_none() {
+            let (_, r) = self.fork();
+            return r;
+        }
+        if !self.shares_tree(other) {
+            self.import(other, oh);
+        }
+
+        let h = self.head();
+        let p = match n {
+            None        => self.last(h),
+            Some(ref n) => self.prev(n, h),
+        };
+        let r = self.tree.borrow_mut().merge(h, oh);
+        self.line.borrow_mut().push(r);
+
+        let first = rebase(&[r], &f).unwrap();
+        let last = rebase(&[r], &b).unwrap();
+        set_prev(&first, r, p.clone());
+        set_next(&last, r, n.clone());
+        match p {
+            None        => { self.front.borrow_mut().insert(r, Some(first)); },
+            Some(ref p) => set_next(p, r, Some(first)),
+        }
+        match n {
+            None        => { self.back.borrow_mut().insert(r, Some(last)); },
+            Some(ref n) => set_prev(n, r, Some(last)),
+        }
+
+        let l = match (self.known_len(h), other.known_len(oh)) {
+            (Some(x), Some(y)) => Some(x + y),
+            _                  => None,
+        };
+        self.len.borrow_mut().insert(r, l);
+        r
+    }
+
+    // the other list can be of another tree, its revisions are imported into this one
+    pub fn append(&mut self, other: &DList<A, G>) -> Revision {
+        self.join(other, None)
+    }
+
+    pub fn cursor(&mut self) -> Cursor<A, G> {
+        let node = self.first(self.head());
+        Cursor { list: self, node: node }
+    }
+
+    pub fn cursor_back(&mut self) -> Cursor<A, G> {
+        let node = self.last(self.head());
+        Cursor { list: self, node: node }
+    }
+
+    pub fn iter(&self, r: Revision) -> Items<A, G> {
+        let len = self.length(r);
         Items {
-            revision: r,
-            link:     link,
+            revision:  r,
+            first:     self.first(r),
+            last:      self.last(r),
+            remaining: len,
 
             head:  Rc::new(RefCell::new(r)),
-            index: self.index.clone(),
+            line:  self.line.clone(),
             tree:  self.tree.clone(),
 
             front: self.front.clone(),
-            back:  self.back.clone()
+            back:  self.back.clone(),
+            len:   self.len.clone()
         }
     }
 }
 
-impl<A> Recall for DList<A> {
-    fn undo(&mut self) -> Revision {
-        assert!(*self.index.borrow() > 0);
+impl<A: Clone, G: LCG> DList<A, G> {
+    pub fn pop_front(&mut self) -> Option<(A, Revision)> {
+        let h = self.head();
+        let f = match self.first(h) {
+            None    => return None,
+            Some(f) => f,
+        };
+        let v = self.value(&f, h).deref().clone();
+        let n = self.next(&f, h);
 
-        *self.index.borrow_mut() -= 1;
+        let (_, r) = self.fork();
+        match n {
+            None        => { self.back.borrow_mut().insert(r, None); },
+            Some(ref n) => set_prev(n, r, None),
+        }
+        self.front.borrow_mut().insert(r, n);
+        self.resize(h, r, -1);
+        Some((v, r))
+    }
+
+    pub fn pop_back(&mut self) -> Option<(A, Revision)> {
+        let h = self.head();
+        let b = match self.last(h) {
+            None    => return None,
+            Some(b) => b,
+        };
+        let v = self.value(&b, h).deref().clone();
+        let p = self.prev(&b, h);
+
+        let (_, r) = self.fork();
+        match p {
+            None        => { self.front.borrow_mut().insert(r, None); },
+            Some(ref p) => set_next(p, r, None),
+        }
+        self.back.borrow_mut().insert(r, p);
+        self.resize(h, r, -1);
+        Some((v, r))
+    }
+}
+
+impl<A, G: LCG> Persistent<DList<A, G>> for DList<A, G> {
+    fn get_by_revision(&self, revision : Revision) -> DList<A, G> {
+        assert!(self.tree.borrow().contains(revision));
+
+        DList {
+            line:  Line::new(revision),
+            front: self.front.clone(),
+            back:  self.back.clone(),
+            len:   self.len.clone(),
+            tree:  self.tree.clone()
+        }
+    }
+
+    fn current_revision_id(&self) -> Revision {
         self.head()
     }
+}
+
+impl<A, G: LCG> TryPersistent<DList<A, G>> for DList<A, G> {
+    fn contains_revision(&self, revision : Revision) -> bool {
+        self.tree.borrow().contains(revision)
+    }
+}
+
+impl<A, G: LCG> Recall for DList<A, G> {
+    fn undo(&mut self) -> Revision {
+        let mut line = self.line.borrow_mut();
+        assert!(line.head > 0);
+
+        line.head -= 1;
+        line.current()
+    }
 
     fn redo(&mut self) -> Revision {
-        assert!(*self.index.borrow() < self.tree.borrow().last_index());
+        let mut line = self.line.borrow_mut();
+        assert!(line.head + 1 < line.revisions.len());
+
+        line.head += 1;
+        line.current()
+    }
+}
+
+impl<A, G: LCG> TryRecall for DList<A, G> {
+    fn can_undo(&self) -> bool {
+        self.line.borrow().head > 0
+    }
 
-        *self.index.borrow_mut() += 1;
+    fn can_redo(&self) -> bool {
+        let line = self.line.borrow();
+        line.head + 1 < line.revisions.len()
+    }
+
+    fn line_revision(&self) -> Revision {
         self.head()
     }
 }
 
+impl<A, G: LCG> FullyPersistent<DList<A, G>> for DList<A, G> { }
+
+impl<A, G: LCG> Clone for DList<A, G> {
+    fn clone(&self) -> DList<A, G> {
+        DList {
+            line:  Rc::new(RefCell::new(self.line.borrow().clone())),
+            front: self.front.clone(),
+            back:  self.back.clone(),
+            len:   self.len.clone(),
+            tree:  self.tree.clone()
+        }
+    }
+}
+
 #[allow(dead_code)]
-pub struct Items<'a, A: 'a> {
-    revision: Revision,
-    link:     &'a Link<A>,
+pub struct Items<'a, A: 'a, G: 'a = CoolLCG> {
+    revision:  Revision,
+    first:     Link<A>,
+    last:      Link<A>,
+    remaining: uint,
 
     head:  Rc<RefCell<Revision>>,
-    index: Rc<RefCell<uint>>,
-    tree:  Rc<RefCell<Tree>>,
+    line:  Rc<RefCell<Line>>,
+    tree:  Rc<RefCell<Tree<G>>>,
 
     front: Rc<RefCell<Field<Link<A>>>>,
-    back:  Rc<RefCell<Field<Link<A>>>>
+    back:  Rc<RefCell<Field<Link<A>>>>,
+    len:   Rc<RefCell<Field<Option<uint>>>>
 }
 
+// the value is read when the reference is made and kept up to date by map
 #[allow(dead_code)]
-pub struct NodeRef<'a, A: 'a> {
-    node:  Rc<RefCell<Node<A>>>,
+pub struct NodeRef<'a, A: 'a, G: 'a = CoolLCG> {
+    node:  Occurrence<A>,
+    value: Rc<A>,
 
     head:  Rc<RefCell<Revision>>,
-    index: Rc<RefCell<uint>>,
-    tree:  Rc<RefCell<Tree>>,
+    line:  Rc<RefCell<Line>>,
+    tree:  Rc<RefCell<Tree<G>>>,
 
     front: Rc<RefCell<Field<Link<A>>>>,
-    back:  Rc<RefCell<Field<Link<A>>>>
+    back:  Rc<RefCell<Field<Link<A>>>>,
+    len:   Rc<RefCell<Field<Option<uint>>>>
 }
 
-impl<'a, A: 'a> NodeRef<'a, A> {
-    pub fn map(&self, f: |&'a A| -> A) {
+impl<'a, A: 'a, G: LCG + 'a> NodeRef<'a, A, G> {
+    // new revision from the head, which becomes the head
+    fn fork(&self) -> (Revision, Revision) {
         let h = *self.head.borrow();
         let r = self.tree.borrow_mut().fork(h);
         *self.head.borrow_mut() = r;
-        *self.index.borrow_mut() = self.tree.borrow().last_index();
-        let v = f(self.value());
-        self.node.borrow_mut().value.insert(r, v);
+        self.line.borrow_mut().push(r);
+        (h, r)
+    }
+
+    // the list this node belongs to, sharing its line of history
+    fn list(&self) -> DList<A, G> {
+        DList {
+            line:  self.line.clone(),
+            front: self.front.clone(),
+            back:  self.back.clone(),
+            len:   self.len.clone(),
+            tree:  self.tree.clone()
+        }
     }
 
-    pub fn remove(&self) {
-        panic!("not yet implemented");
+    fn is_linked(&self) -> bool {
+        self.list().is_linked(&self.node, *self.head.borrow())
     }
 
-    pub fn insert_before(&self) {
-        panic!("not yet implemented");
+    pub fn map(&mut self, f: |&A| -> A) {
+        let (h, r) = self.fork();
+        let v = Rc::new(f(&*value_of(&*self.tree.borrow(), &self.node, h)));
+        set_value(&self.node, r, v.clone());
+        self.value = v;
     }
 
-    pub fn insert_after(&self) {
-        panic!("not yet implemented");
+    pub fn remove(&self) -> Revision {
+        assert!(self.is_linked());
+
+        let (h, r) = self.fork();
+        self.list().unlink(h, r, &self.node);
+        r
     }
-    
-    pub fn value(&self) -> &'a A {
-        self.tree.borrow().get(&self.node.borrow().value, *self.head.borrow()).unwrap()
+
+    pub fn try_remove(&self) -> PersResult<Revision> {
+        if !self.is_linked() {
+            return Err(PersError::NodeRemoved);
+        }
+        Ok(self.remove())
+    }
+
+    pub fn insert_before(&self, v: A) -> Revision {
+        assert!(self.is_linked());
+
+        let (h, r) = self.fork();
+        self.list().link_before(h, r, &self.node, v);
+        r
+    }
+
+    pub fn insert_after(&self, v: A) -> Revision {
+        assert!(self.is_linked());
+
+        let (h, r) = self.fork();
+        self.list().link_after(h, r, &self.node, v);
+        r
+    }
+
+    pub fn value(&self) -> &A {
+        &*self.value
+    }
+
+    // the head keeps the nodes before this one, the returned list starts from this one;
+    // both revisions are forked from the head and share the tree, their lengths are
+    // counted when first needed
+    pub fn split_off(&self) -> DList<A, G> {
+        assert!(self.is_linked());
+
+        let list = self.list();
+        let h = *self.head.borrow();
+        let p = list.prev(&self.node, h);
+        let (k, rest) = match p {
+            None    => (Some(0), list.known_len(h)),
+            Some(_) => (None, None),
+        };
+
+        let r = self.tree.borrow_mut().fork(h);
+        match p {
+            None        => { self.front.borrow_mut().insert(r, None); },
+            Some(ref p) => set_next(p, r, None),
+        }
+        self.back.borrow_mut().insert(r, p);
+        self.len.borrow_mut().insert(r, k);
+
+        let s = self.tree.borrow_mut().fork(h);
+        set_prev(&self.node, s, None);
+        self.front.borrow_mut().insert(s, Some(self.node.clone()));
+        self.len.borrow_mut().insert(s, rest);
+
+        *self.head.borrow_mut() = r;
+        self.line.borrow_mut().push(r);
+        DList {
+            line:  Line::new(s),
+            front: self.front.clone(),
+            back:  self.back.clone(),
+            len:   self.len.clone(),
+            tree:  self.tree.clone()
+        }
     }
 }
 
-impl<'a, A: 'a> Deref<A> for NodeRef<'a, A> {
+impl<'a, A: 'a, G: LCG + 'a> Deref<A> for NodeRef<'a, A, G> {
     fn deref(&self) -> &A {
         self.value()
     }
 }
 
-impl<'a, A: Eq + Show> Iterator<NodeRef<'a, A>> for Items<'a, A> {
-    fn next(&mut self) -> Option<NodeRef<'a, A>> {
-        let revision = self.revision;
-        match *self.link {
-            None        => None,
-            Some(ref link) => {
-                self.link = self.tree.borrow().get(&link.borrow().next, revision).unwrap();
-                let node_ref = NodeRef {
-                    node:  link.clone(),
-
-                    head:  self.head.clone(),
-                    index: self.index.clone(),
-                    tree:  self.tree.clone(),
-
-                    front: self.front.clone(),
-                    back:  self.back.clone()
-                };
-                Some(node_ref)
+impl<'a, A: 'a, G: LCG + 'a> Items<'a, A, G> {
+    fn node_ref(&self, node: Occurrence<A>) -> NodeRef<'a, A, G> {
+        let value = value_of(&*self.tree.borrow(), &node, *self.head.borrow());
+        NodeRef {
+            node:  node,
+            value: value,
+
+            head:  self.head.clone(),
+            line:  self.line.clone(),
+            tree:  self.tree.clone(),
+
+            front: self.front.clone(),
+            back:  self.back.clone(),
+            len:   self.len.clone()
+        }
+    }
+}
+
+impl<'a, A: 'a, G: LCG + 'a> Iterator<NodeRef<'a, A, G>> for Items<'a, A, G> {
+    fn next(&mut self) -> Option<NodeRef<'a, A, G>> {
+        if self.remaining == 0 {
+            return None;
+        }
+        match self.first.take() {
+            None       => None,
+            Some(node) => {
+                self.first = next_of(&*self.tree.borrow(), &node, self.revision);
+                self.remaining -= 1;
+                Some(self.node_ref(node))
             }
         }
     }
 
     fn size_hint(&self) -> (uint, Option<uint>) {
-        (0, None)
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, A: 'a, G: LCG + 'a> DoubleEndedIterator<NodeRef<'a, A, G>> for Items<'a, A, G> {
+    fn next_back(&mut self) -> Option<NodeRef<'a, A, G>> {
+        if self.remaining == 0 {
+            return None;
+        }
+        match self.last.take() {
+            None       => None,
+            Some(node) => {
+                self.last = prev_of(&*self.tree.borrow(), &node, self.revision);
+                self.remaining -= 1;
+                Some(self.node_ref(node))
+            }
+        }
     }
 }
 
+impl<'a, A: 'a, G: LCG + 'a> ExactSizeIterator<NodeRef<'a, A, G>> for Items<'a, A, G> {}
+
+// None is the position past the back and before the front
+pub struct Cursor<'a, A: 'a, G: 'a = CoolLCG> {
+    list: &'a mut DList<A, G>,
+    node: Link<A>
+}
+
+impl<'a, A: 'a, G: LCG + 'a> Cursor<'a, A, G> {
+    pub fn move_next(&mut self) {
+        let h = self.list.head();
+        self.node = match self.node {
+            None        => self.list.first(h),
+            Some(ref n) => self.list.next(n, h),
+        };
+    }
+
+    pub fn move_prev(&mut self) {
+        let h = self.list.head();
+        self.node = match self.node {
+            None        => self.list.last(h),
+            Some(ref n) => self.list.prev(n, h),
+        };
+    }
+
+    pub fn value(&self) -> Option<Rc<A>> {
+        match self.node {
+            None        => None,
+            Some(ref n) => Some(self.list.value(n, self.list.head())),
+        }
+    }
+
+    // before the past-the-back position is at the back
+    pub fn insert_before(&mut self, v: A) -> Revision {
+        match self.node {
+            None        => self.list.push_back(v),
+            Some(ref n) => {
+                let (h, r) = self.list.fork();
+                self.list.link_before(h, r, n, v);
+            }
+        }
+        self.list.head()
+    }
+
+    // after the past-the-back position is at the front
+    pub fn insert_after(&mut self, v: A) -> Revision {
+        match self.node {
+            None        => self.list.push(v),
+            Some(ref n) => {
+                let (h, r) = self.list.fork();
+                self.list.link_after(h, r, n, v);
+            }
+        }
+        self.list.head()
+    }
+
+    // the cursor moves to the next node
+    pub fn remove(&mut self) -> Option<Revision> {
+        let node = match self.node {
+            None        => return None,
+            Some(ref n) => n.clone(),
+        };
+        let (h, r) = self.list.fork();
+        self.list.unlink(h, r, &node);
+        self.node = self.list.next(&node, h);
+        Some(r)
+    }
+
+    pub fn replace(&mut self, v: A) -> Option<Revision> {
+        match self.node {
+            None        => None,
+            Some(ref n) => {
+                let (_, r) = self.list.fork();
+                set_value(n, r, Rc::new(v));
+                Some(r)
+            }
+        }
+    }
+
+    // links elements of the other list before the cursor, like append
+    pub fn splice(&mut self, other: &DList<A, G>) -> Revision {
+        let node = self.node.clone();
+        self.list.join(other, node)
+    }
+}
+
+#[cfg(test)]
+use std::fmt::Show;
+
 #[cfg(test)]
-fn assert<A: Show + Eq>(mut xs: Items<A>, es: &[A]) {
+fn assert<'a, A: Show + Eq + 'a, G: LCG + 'a, I: Iterator<NodeRef<'a, A, G>>>(mut xs: I, es: &[A]) {
     let mut i = 0;
     for x in xs {
         assert_eq!(*x, es[i]);
@@ -272,12 +913,12 @@ fn iter() {
     xs.push(1);
     let b = xs.head();
 
-    for x in xs.iter(a) {
+    for mut x in xs.iter(a) {
         x.map(|_| 0);
     }
     let c = xs.head();
 
-    for x in xs.iter(a).filter(|x| **x > 4) {
+    for mut x in xs.iter(a).filter(|x| **x > 4) {
         x.map(|_| 0);
     }
     let d = xs.head();
@@ -287,3 +928,347 @@ fn iter() {
     assert(xs.iter(c), &[0, 0, 0, 0]);
     assert(xs.iter(d), &[3, 4, 0, 0]);
 }
+
+#[test]
+fn remove() {
+    let mut xs: DList<int> = DList::new();
+    for i in range(1i, 6).rev() {
+        xs.push(i);
+    }
+    let a = xs.head();
+
+    for x in xs.iter(a).filter(|x| **x % 2 == 1) {
+        x.remove();
+    }
+    let b = xs.head();
+
+    let mut ys = xs.iter(b);
+    let y = ys.next().unwrap();
+    y.remove();
+    assert_eq!(y.try_remove(), Err(PersError::NodeRemoved));
+    let c = xs.head();
+
+    assert(xs.iter(a), &[1, 2, 3, 4, 5]);
+    assert(xs.iter(b), &[2, 4]);
+    assert(xs.iter(c), &[4]);
+    xs.push_back(6);
+    assert(xs.iter(xs.head()), &[4, 6]);
+}
+
+#[test]
+fn insert() {
+    let mut xs: DList<int> = DList::new();
+    xs.push(3);
+    xs.push(1);
+    let a = xs.head();
+
+    for x in xs.iter(a) {
+        if *x == 1 {
+            x.insert_after(2);
+        } else {
+            x.insert_after(4);
+            x.insert_before(0);
+        }
+    }
+    let b = xs.head();
+
+    let first = xs.iter(b).next().unwrap();
+    first.insert_before(-1);
+    let c = xs.head();
+
+    assert(xs.iter(a), &[1, 3]);
+    assert(xs.iter(b), &[1, 2, 0, 3, 4]);
+    assert(xs.iter(c), &[-1, 1, 2, 0, 3, 4]);
+    xs.undo();
+    xs.push(7);
+    assert(xs.iter(xs.head()), &[7, 1, 2, 0, 3, 4]);
+}
+
+#[test]
+fn pop() {
+    let mut xs: DList<int> = DList::new();
+    assert_eq!(xs.pop_front(), None);
+    xs.push(2);
+    xs.push(1);
+    xs.push_back(3);
+    let a = xs.head();
+    assert_eq!(xs.len(), 3u);
+
+    let (v, b) = xs.pop_front().unwrap();
+    assert_eq!(v, 1);
+    let (w, c) = xs.pop_back().unwrap();
+    assert_eq!(w, 3);
+    assert_eq!(xs.len(), 1u);
+    assert_eq!(xs.head(), c);
+
+    xs.pop_back();
+    assert!(xs.is_empty());
+    assert_eq!(xs.pop_back(), None);
+    xs.push_back(4);
+
+    assert(xs.iter(a), &[1, 2, 3]);
+    assert(xs.iter(b), &[2, 3]);
+    assert(xs.iter(c), &[2]);
+    assert(xs.iter(xs.head()), &[4]);
+
+    xs.undo_ntimes(4);
+    assert_eq!(xs.len(), 3u);
+    for x in xs.iter(a).filter(|x| **x == 2) {
+        x.insert_before(0);
+        x.remove();
+    }
+    assert_eq!(xs.len(), 3u);
+    assert(xs.iter(xs.head()), &[1, 0, 3]);
+}
+
+#[test]
+fn rev() {
+    let mut xs: DList<int> = DList::new();
+    for i in range(1i, 5) {
+        xs.push_back(i);
+    }
+    let a = xs.head();
+    xs.pop_back();
+    let b = xs.head();
+
+    assert(xs.iter(a).rev(), &[4, 3, 2, 1]);
+    assert(xs.iter(b).rev(), &[3, 2, 1]);
+    assert_eq!(xs.iter(a).len(), 4u);
+
+    let mut ys = xs.iter(a);
+    assert_eq!(*ys.next().unwrap(), 1);
+    assert_eq!(*ys.next_back().unwrap(), 4);
+    assert_eq!(*ys.next_back().unwrap(), 3);
+    assert_eq!(*ys.next().unwrap(), 2);
+    assert!(ys.next().is_none());
+    assert!(ys.next_back().is_none());
+}
+
+#[test]
+fn persistent() {
+    let mut xs: DList<int> = DList::new();
+    xs.push(2);
+    xs.push(1);
+    let a = xs.current_revision_id();
+    xs.push_back(3);
+    let b = xs.current_revision_id();
+
+    let mut ys = xs.get_by_revision(a);
+    assert_eq!(ys.current_revision_id(), a);
+    assert_eq!(ys.len(), 2u);
+    ys.push_back(4);
+    let c = ys.head();
+
+    assert_eq!(xs.head(), b);
+    assert(xs.iter(b), &[1, 2, 3]);
+    assert(ys.iter(c), &[1, 2, 4]);
+    assert!(xs.contains_revision(c));
+    assert!(xs.try_get_by_revision(-1).is_err());
+
+    let zs = xs.clone();
+    xs.undo();
+    assert_eq!(zs.head(), b);
+    assert_eq!(xs.current().len(), 2u);
+
+    // every handle has its own line of history
+    xs.redo();
+    assert_eq!(xs.head(), b);
+    assert!(!xs.can_redo());
+    ys.undo();
+    assert_eq!(ys.head(), a);
+    assert!(!ys.can_undo());
+    assert_eq!(ys.redo(), c);
+}
+
+#[test]
+fn cursor() {
+    let mut xs: DList<int> = DList::new();
+    for i in range(1i, 4) {
+        xs.push_back(i);
+    }
+    let a = xs.head();
+
+    let (b, c, d, e) = {
+        let mut cur = xs.cursor();
+        assert_eq!(cur.value(), Some(Rc::new(1)));
+        cur.move_next();
+        let b = cur.replace(20).unwrap();
+        let c = cur.insert_after(25);
+        cur.move_next();
+        assert_eq!(cur.value(), Some(Rc::new(25)));
+        let d = cur.remove().unwrap();
+        assert_eq!(cur.value(), Some(Rc::new(3)));
+        cur.move_next();
+        assert_eq!(cur.value(), None);
+        let e = cur.insert_before(4);
+        cur.move_prev();
+        assert_eq!(cur.value(), Some(Rc::new(4)));
+        cur.move_prev();
+        cur.move_prev();
+        cur.move_prev();
+        assert_eq!(cur.value(), Some(Rc::new(1)));
+        (b, c, d, e)
+    };
+
+    assert(xs.iter(a), &[1, 2, 3]);
+    assert(xs.iter(b), &[1, 20, 3]);
+    assert(xs.iter(c), &[1, 20, 25, 3]);
+    assert(xs.iter(d), &[1, 20, 3]);
+    assert(xs.iter(e), &[1, 20, 3, 4]);
+    assert_eq!(xs.head(), e);
+
+    {
+        let mut cur = xs.cursor_back();
+        cur.remove();
+        assert_eq!(cur.value(), None);
+        assert!(cur.remove().is_none());
+        cur.insert_after(0);
+    }
+    assert(xs.iter(xs.head()), &[0, 1, 20, 3]);
+}
+
+#[test]
+fn split_append() {
+    let mut xs: DList<int> = DList::new();
+    for i in range(1i, 6) {
+        xs.push_back(i);
+    }
+    let a = xs.head();
+
+    let mut ys = xs.iter(a).nth(2).unwrap().split_off();
+    let b = xs.head();
+    assert_eq!(xs.len(), 2u);
+    assert_eq!(ys.len(), 3u);
+    assert(xs.iter(b), &[1, 2]);
+    assert(ys.iter(ys.head()).rev(), &[5, 4, 3]);
+
+    let c = xs.append(&ys);
+    ys.push_back(6);
+    assert(xs.iter(c), &[1, 2, 3, 4, 5]);
+    assert(xs.iter(c).rev(), &[5, 4, 3, 2, 1]);
+    assert(ys.iter(ys.head()), &[3, 4, 5, 6]);
+    assert(xs.iter(a), &[1, 2, 3, 4, 5]);
+
+    let mut zs: DList<int> = DList::new();
+    zs.push(8);
+    zs.push(7);
+    let d = {
+        let mut cur = xs.cursor();
+        cur.move_next();
+        cur.splice(&zs)
+    };
+    assert(xs.iter(d), &[1, 7, 8, 2, 3, 4, 5]);
+    assert_eq!(xs.len(), 7u);
+    assert(zs.iter(zs.head()), &[7, 8]);
+}
+
+#[test]
+fn append_itself() {
+    let mut xs: DList<int> = DList::new();
+    for i in range(1i, 4) {
+        xs.push_back(i);
+    }
+    let a = xs.head();
+
+    let ys = xs.current();
+    let b = xs.append(&ys);
+    assert_eq!(xs.len(), 6u);
+    assert(xs.iter(b), &[1, 2, 3, 1, 2, 3]);
+    assert(xs.iter(b).rev(), &[3, 2, 1, 3, 2, 1]);
+
+    for mut x in xs.iter(b).skip(3).filter(|x| **x == 2) {
+        x.map(|v| *v * 10);
+        assert_eq!(*x, 20);
+    }
+    let c = xs.head();
+    assert(xs.iter(c), &[1, 2, 3, 1, 20, 3]);
+
+    for x in xs.iter(c).filter(|x| **x == 3) {
+        x.remove();
+    }
+    assert(xs.iter(xs.head()), &[1, 2, 1, 20]);
+    assert_eq!(xs.len(), 4u);
+
+    let ys = xs.current();
+    let d = xs.append(&ys);
+    assert(xs.iter(d), &[1, 2, 1, 20, 1, 2, 1, 20]);
+    assert_eq!(xs.iter(d).len(), 8u);
+    assert(xs.iter(a), &[1, 2, 3]);
+    assert(xs.iter(b), &[1, 2, 3, 1, 2, 3]);
+}
+
+#[test]
+fn split_rejoin() {
+    let mut xs: DList<int> = DList::new();
+    for i in range(1i, 7) {
+        xs.push_back(i);
+    }
+    let a = xs.head();
+
+    let mut ys = xs.iter(a).nth(3).unwrap().split_off();
+    ys.push(0);
+    let zs = xs.iter(xs.head()).nth(1).unwrap().split_off();
+    assert(xs.iter(xs.head()), &[1]);
+    assert(zs.iter(zs.head()), &[2, 3]);
+    assert(ys.iter(ys.head()), &[0, 4, 5, 6]);
+
+    xs.append(&ys);
+    xs.append(&zs);
+    assert(xs.iter(xs.head()), &[1, 0, 4, 5, 6, 2, 3]);
+    assert(xs.iter(xs.head()).rev(), &[3, 2, 6, 5, 4, 0, 1]);
+    assert_eq!(xs.len(), 7u);
+
+    {
+        let mut cur = xs.cursor_back();
+        cur.splice(&ys);
+    }
+    assert(xs.iter(xs.head()), &[1, 0, 4, 5, 6, 2, 0, 4, 5, 6, 3]);
+    assert(xs.iter(a), &[1, 2, 3, 4, 5, 6]);
+}
+
+#[test]
+fn append_other_tree() {
+    let mut xs: DList<int> = DList::new();
+    xs.push_back(1);
+    xs.push_back(2);
+    let mut ys: DList<int> = DList::new();
+    ys.push_back(3);
+    let a = ys.head();
+    ys.push_back(4);
+    let b = ys.head();
+
+    let c = xs.append(&ys);
+    ys.push_back(5);
+    xs.push_back(6);
+    assert(xs.iter(c), &[1, 2, 3, 4]);
+    assert(xs.iter(xs.head()), &[1, 2, 3, 4, 6]);
+    assert(ys.iter(ys.head()), &[3, 4, 5]);
+
+    assert!(xs.contains_revision(b));
+    assert!(!xs.contains_revision(ys.head()));
+    let zs = xs.get_by_revision(a);
+    assert(zs.iter(a), &[3]);
+
+    xs.append(&ys);
+    assert(xs.iter(xs.head()), &[1, 2, 3, 4, 6, 3, 4, 5]);
+    xs.undo_ntimes(2);
+    assert_eq!(xs.head(), c);
+}
+
+#[test]
+fn with_generator() {
+    use inner::lcg_random::DebugLCG;
+
+    let mut xs: DList<int, DebugLCG> = DList::with_generator(LCG::with_seed(10));
+    xs.push(1);
+    let a = xs.head();
+    xs.push(2);
+    assert_eq!(a & 0xffff, 12);
+    assert_eq!(xs.head() & 0xffff, 13);
+
+    let mut ys: DList<int, DebugLCG> = DList::with_generator(LCG::with_seed(10));
+    ys.push(3);
+    assert!(ys.head() != a);
+    xs.append(&ys);
+    assert(xs.iter(xs.head()), &[2, 1, 3]);
+}
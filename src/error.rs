@@ -0,0 +1,86 @@
+//! Errors of persistent structures.
+//!
+//! Every operation which panics on misuse has a ```try_``` variant which returns
+//! ```PersResult``` instead, so the caller may recover, e.g. ignore one undo too many.
+
+use inner::persistent::Revision;
+use std::error::Error;
+use std::fmt;
+
+/// Error of an operation on a persistent structure.
+#[deriving(Clone, PartialEq, Eq)]
+pub enum PersError {
+    /// The revision does not exist in the history or is not a valid revision id.
+    UnknownRevision(Revision),
+    /// The revision already exists in the history.
+    DuplicateRevision(Revision),
+    /// Undo at the beginning of the history branch.
+    NothingToUndo,
+    /// Redo at the end of the history branch.
+    NothingToRedo,
+    /// Index is not less than the length, given as ```(index, len)```. For a range it is the
+    /// end beyond the length or the start beyond the end.
+    IndexOutOfBounds(uint, uint),
+    /// Removing from an empty structure, or updating an empty range.
+    Empty,
+    /// The key is not present.
+    KeyNotFound,
+    /// The list node is already removed.
+    NodeRemoved,
+    /// The byte offset is inside a char.
+    NotCharBoundary(uint),
+}
+
+impl Copy for PersError {}
+
+/// Result of an operation on a persistent structure.
+pub type PersResult<T> = Result<T, PersError>;
+
+impl Error for PersError {
+    fn description(&self) -> &str {
+        match *self {
+            PersError::UnknownRevision(_)     => "unknown revision",
+            PersError::DuplicateRevision(_)   => "duplicate revision",
+            PersError::NothingToUndo          => "nothing to undo",
+            PersError::NothingToRedo          => "nothing to redo",
+            PersError::IndexOutOfBounds(_, _) => "index out of bounds",
+            PersError::Empty                  => "structure is empty",
+            PersError::KeyNotFound            => "key not found",
+            PersError::NodeRemoved            => "node is removed",
+            PersError::NotCharBoundary(_)     => "offset is not on a char boundary",
+        }
+    }
+}
+
+impl fmt::Show for PersError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PersError::UnknownRevision(r)        => write!(f, "unknown revision {}", r),
+            PersError::DuplicateRevision(r)      => write!(f, "duplicate revision {}", r),
+            PersError::IndexOutOfBounds(id, len) => write!(f, "index {} out of bounds, len is {}", id, len),
+            PersError::NotCharBoundary(offset)   => write!(f, "offset {} is not on a char boundary", offset),
+            _                                    => write!(f, "{}", self.description()),
+        }
+    }
+}
+
+/// Checks that ```[from, to)``` is a range of a structure of the given length.
+pub fn check_range(from: uint, to: uint, len: uint) -> PersResult<()> {
+    if to > len {
+        Err(PersError::IndexOutOfBounds(to, len))
+    } else if from > to {
+        Err(PersError::IndexOutOfBounds(from, to))
+    } else {
+        Ok(())
+    }
+}
+
+#[test]
+fn error_show_test() {
+    assert_eq!(PersError::UnknownRevision(7).to_string().as_slice(), "unknown revision 7");
+    assert_eq!(PersError::IndexOutOfBounds(3, 2).to_string().as_slice(), "index 3 out of bounds, len is 2");
+    assert_eq!(PersError::NothingToUndo.to_string().as_slice(), "nothing to undo");
+    assert_eq!(check_range(2, 1, 5), Err(PersError::IndexOutOfBounds(2, 1)));
+    assert_eq!(check_range(0, 6, 5), Err(PersError::IndexOutOfBounds(6, 5)));
+    assert_eq!(check_range(5, 5, 5), Ok(()));
+}
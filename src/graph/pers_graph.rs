@@ -0,0 +1,577 @@
+//! Persistent directed graph.
+//!
+//! Both outgoing and incoming adjacency are kept in persistent hash tries, so adding or removing
+//! a node or an edge copies O(1) trie paths and creates a new revision. Neighbour lookup costs
+//! the same in every revision, and traversal algorithms run on any snapshot returned by
+//! ```get_by_revision```.
+
+use error::{PersError, PersResult};
+use inner::hamt::*;
+use inner::persistent::*;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::BTreeMap as TreeMap;
+use std::collections::{BinaryHeap, HashMap, HashSet, RingBuf};
+use std::hash::Hash;
+use std::rc::Rc;
+use std::vec::Vec;
+
+type Adjacency<N, V> = Rc<Hamt<N, Rc<Hamt<N, V>>>>;
+type SharedData<N, E> = Rc<RefCell<SharedGraphData<N, E>>>;
+
+
+
+fn put<K: Hash + Eq + Clone, V: Clone>(map: &Rc<Hamt<K, V>>, key: K, value: V) -> Rc<Hamt<K, V>> {
+    let hash = hash_key(&key);
+    Hamt::insert(map, 0, hash, key, value).0
+}
+
+fn without<K: Hash + Eq + Clone, V: Clone>(map: &Rc<Hamt<K, V>>, key: &K) -> Rc<Hamt<K, V>> {
+    Hamt::remove(map, 0, hash_key(key), key).0.unwrap()
+}
+
+fn neighbours<N: Hash + Eq + Clone, V: Clone>(adjacency: &Adjacency<N, V>, node: &N) -> Rc<Hamt<N, V>> {
+    adjacency.get(hash_key(node), node).unwrap().clone()
+}
+
+// one immutable state of the graph
+struct GraphNode<N, E> {
+    outgoing: Adjacency<N, E>,
+    incoming: Adjacency<N, ()>,
+    edges:    uint,
+}
+
+struct SharedGraphData<N, E> {
+    last_revision: Revision, // revision counter
+    roots:         TreeMap<Revision, Rc<GraphNode<N, E>>>, // graph state for each revision
+}
+
+/// Persistent directed graph implementation.
+///
+/// # Examples
+///
+/// ```
+/// let mut graph = PersGraph::<&str, uint>::new();
+/// graph.add_edge("a", "b", 1);
+/// graph.add_edge("b", "c", 1);
+/// let rev = graph.add_edge("a", "c", 5);
+/// graph.remove_edge(&"b", &"c");
+///
+/// let old = graph.get_by_revision(rev);
+/// assert_eq!(old.shortest_path(&"a", &"c", |w| *w), Some((2, vec!["a", "b", "c"])));
+/// assert_eq!(graph.shortest_path(&"a", &"c", |w| *w), Some((5, vec!["a", "c"])));
+/// ```
+pub struct PersGraph<N, E> {
+    line_history:     Vec<Revision>, // branch of history for undo-redo
+    head_revision_id: uint, // id of the current verision in line_history vector
+    root:             Rc<GraphNode<N, E>>, // graph state for the current revision
+    shared_data:      SharedData<N, E>, // shared data between all revision
+}
+
+impl<N: Hash + Eq + Clone, E: Clone> PersGraph<N, E> {
+    /// Constructs a new, empty persistent graph.
+    pub fn new() -> PersGraph<N, E> {
+        let root = Rc::new(GraphNode{outgoing: Hamt::new_empty(),
+                                     incoming: Hamt::new_empty(),
+                                     edges:    0});
+        let mut roots = TreeMap::new();
+        roots.insert(1, root.clone());
+        let shdata = Rc::new(RefCell::new(SharedGraphData::<N, E>{last_revision: 1,
+                                                                  roots: roots}));
+        PersGraph{line_history: vec![1],
+                  head_revision_id: 0,
+                  root: root,
+                  shared_data: shdata}
+    }
+
+    fn commit(&mut self, outgoing: Adjacency<N, E>, incoming: Adjacency<N, ()>, edges: uint) -> Revision {
+        let root = Rc::new(GraphNode{outgoing: outgoing, incoming: incoming, edges: edges});
+        let mut data = self.shared_data.borrow_mut();
+        let revision = data.last_revision + 1;
+        data.roots.insert(revision, root.clone());
+        data.last_revision = revision;
+
+        self.head_revision_id += 1;
+        self.line_history.truncate(self.head_revision_id);
+        self.line_history.push(revision);
+        self.root = root;
+
+        revision
+    }
+
+    // adjacency with the node added if it is absent
+    fn with_node(&self, node: &N) -> (Adjacency<N, E>, Adjacency<N, ()>) {
+        if self.contains_node(node) {
+            (self.root.outgoing.clone(), self.root.incoming.clone())
+        } else {
+            (put(&self.root.outgoing, node.clone(), Hamt::new_empty()),
+             put(&self.root.incoming, node.clone(), Hamt::new_empty()))
+        }
+    }
+
+    /// Returns the number of nodes.
+    pub fn node_count(&self) -> uint {
+        self.root.outgoing.iter().count()
+    }
+
+    /// Returns the number of edges.
+    pub fn edge_count(&self) -> uint {
+        self.root.edges
+    }
+
+    /// Returns ```true``` if the graph has the node.
+    pub fn contains_node(&self, node: &N) -> bool {
+        self.root.outgoing.get(hash_key(node), node).is_some()
+    }
+
+    /// Returns the value of the edge from ```from``` to ```to```.
+    pub fn edge<'a>(&'a self, from: &N, to: &N) -> Option<&'a E> {
+        match self.root.outgoing.get(hash_key(from), from) {
+            None      => None,
+            Some(out) => out.get(hash_key(to), to),
+        }
+    }
+
+    /// Returns iterator over all nodes, in no particular order.
+    pub fn nodes<'a>(&'a self) -> Nodes<'a, N, E> {
+        Nodes{iter: self.root.outgoing.iter()}
+    }
+
+    /// Returns iterator over ```(target, value)``` of edges which leave the node.
+    ///
+    /// # Panics
+    /// Panics if there is no such node.
+    pub fn successors<'a>(&'a self, node: &N) -> HamtIterator<'a, N, E> {
+        self.root.outgoing.get(hash_key(node), node).unwrap().iter()
+    }
+
+    /// Like ```successors```, but returns ```KeyNotFound``` error instead of panic.
+    pub fn try_successors<'a>(&'a self, node: &N) -> PersResult<HamtIterator<'a, N, E>> {
+        match self.root.outgoing.get(hash_key(node), node) {
+            None      => Err(PersError::KeyNotFound),
+            Some(out) => Ok(out.iter()),
+        }
+    }
+
+    /// Returns iterator over ```(source, ())``` of edges which enter the node.
+    ///
+    /// # Panics
+    /// Panics if there is no such node.
+    pub fn predecessors<'a>(&'a self, node: &N) -> HamtIterator<'a, N, ()> {
+        self.root.incoming.get(hash_key(node), node).unwrap().iter()
+    }
+
+    /// Like ```predecessors```, but returns ```KeyNotFound``` error instead of panic.
+    pub fn try_predecessors<'a>(&'a self, node: &N) -> PersResult<HamtIterator<'a, N, ()>> {
+        match self.root.incoming.get(hash_key(node), node) {
+            None      => Err(PersError::KeyNotFound),
+            Some(inc) => Ok(inc.iter()),
+        }
+    }
+
+    /// Add a node without edges.
+    ///
+    /// Returns new revision id.
+    pub fn add_node(&mut self, node: N) -> Revision {
+        let (outgoing, incoming) = self.with_node(&node);
+        let edges = self.root.edges;
+        self.commit(outgoing, incoming, edges)
+    }
+
+    /// Remove the node and all its edges.
+    ///
+    /// Returns new revision id.
+    pub fn remove_node(&mut self, node: &N) -> Revision {
+        if !self.contains_node(node) {
+            let (outgoing, incoming, edges) = (self.root.outgoing.clone(), self.root.incoming.clone(), self.root.edges);
+            return self.commit(outgoing, incoming, edges);
+        }
+
+        let out = neighbours(&self.root.outgoing, node);
+        let inc = neighbours(&self.root.incoming, node);
+        let mut outgoing = self.root.outgoing.clone();
+        let mut incoming = self.root.incoming.clone();
+        let mut edges = self.root.edges;
+        for (target, _) in out.iter() {
+            if target != node {
+                incoming = put(&incoming, target.clone(), without(&neighbours(&incoming, target), node));
+            }
+            edges -= 1;
+        }
+        for (source, _) in inc.iter() {
+            if source != node {
+                outgoing = put(&outgoing, source.clone(), without(&neighbours(&outgoing, source), node));
+                edges -= 1;
+            }
+        }
+        outgoing = without(&outgoing, node);
+        incoming = without(&incoming, node);
+        self.commit(outgoing, incoming, edges)
+    }
+
+    /// Add the edge, or replace its value. Missing ends are added as well.
+    ///
+    /// Returns new revision id.
+    pub fn add_edge(&mut self, from: N, to: N, value: E) -> Revision {
+        let added = self.edge(&from, &to).is_none();
+        let (outgoing, incoming) = self.with_node(&from);
+        let (outgoing, incoming) = if self.contains_node(&to) || from == to {
+            (outgoing, incoming)
+        } else {
+            (put(&outgoing, to.clone(), Hamt::new_empty()), put(&incoming, to.clone(), Hamt::new_empty()))
+        };
+
+        let out = put(&neighbours(&outgoing, &from), to.clone(), value);
+        let inc = put(&neighbours(&incoming, &to), from.clone(), ());
+        let edges = if added { self.root.edges + 1 } else { self.root.edges };
+        self.commit(put(&outgoing, from, out), put(&incoming, to, inc), edges)
+    }
+
+    /// Remove the edge from ```from``` to ```to```.
+    ///
+    /// Returns new revision id.
+    pub fn remove_edge(&mut self, from: &N, to: &N) -> Revision {
+        let (mut outgoing, mut incoming, mut edges) = (self.root.outgoing.clone(), self.root.incoming.clone(), self.root.edges);
+        if self.edge(from, to).is_some() {
+            outgoing = put(&outgoing, from.clone(), without(&neighbours(&outgoing, from), to));
+            incoming = put(&incoming, to.clone(), without(&neighbours(&incoming, to), from));
+            edges -= 1;
+        }
+        self.commit(outgoing, incoming, edges)
+    }
+
+    /// Returns nodes reachable from ```start``` in breadth-first order, or nothing if there is
+    /// no such node.
+    pub fn bfs(&self, start: &N) -> Vec<N> {
+        if !self.contains_node(start) {
+            return Vec::new();
+        }
+
+        let mut order = vec![start.clone()];
+        let mut visited = HashSet::new();
+        let mut queue = RingBuf::new();
+        visited.insert(start.clone());
+        queue.push_back(start.clone());
+        loop {
+            let node = match queue.pop_front() {
+                None       => break,
+                Some(node) => node,
+            };
+            for (next, _) in self.successors(&node) {
+                if visited.insert(next.clone()) {
+                    order.push(next.clone());
+                    queue.push_back(next.clone());
+                }
+            }
+        }
+        order
+    }
+
+    /// Like ```bfs```, but returns ```KeyNotFound``` error if there is no such node.
+    pub fn try_bfs(&self, start: &N) -> PersResult<Vec<N>> {
+        if !self.contains_node(start) {
+            return Err(PersError::KeyNotFound);
+        }
+        Ok(self.bfs(start))
+    }
+
+    /// Returns nodes reachable from ```start``` in depth-first preorder, or nothing if there is
+    /// no such node.
+    pub fn dfs(&self, start: &N) -> Vec<N> {
+        if !self.contains_node(start) {
+            return Vec::new();
+        }
+
+        let mut order = Vec::new();
+        let mut visited = HashSet::new();
+        let mut stack = vec![start.clone()];
+        loop {
+            let node = match stack.pop() {
+                None       => break,
+                Some(node) => node,
+            };
+            if !visited.insert(node.clone()) {
+                continue;
+            }
+            let mut next: Vec<N> = self.successors(&node)
+                                       .map(|(n, _)| n.clone())
+                                       .filter(|n| !visited.contains(n))
+                                       .collect();
+            next.reverse();
+            stack.extend(next.into_iter());
+            order.push(node);
+        }
+        order
+    }
+
+    /// Like ```dfs```, but returns ```KeyNotFound``` error if there is no such node.
+    pub fn try_dfs(&self, start: &N) -> PersResult<Vec<N>> {
+        if !self.contains_node(start) {
+            return Err(PersError::KeyNotFound);
+        }
+        Ok(self.dfs(start))
+    }
+
+    /// Returns all nodes so that every edge goes forward, or ```None``` if the graph has a cycle.
+    pub fn topological_sort(&self) -> Option<Vec<N>> {
+        let mut degree = HashMap::new();
+        let mut ready = Vec::new();
+        for node in self.nodes() {
+            let d = self.predecessors(node).count();
+            if d == 0 {
+                ready.push(node.clone());
+            }
+            degree.insert(node.clone(), d);
+        }
+
+        let mut order = Vec::new();
+        loop {
+            let node = match ready.pop() {
+                None       => break,
+                Some(node) => node,
+            };
+            for (next, _) in self.successors(&node) {
+                let d = *degree.get(next).unwrap() - 1;
+                degree.insert(next.clone(), d);
+                if d == 0 {
+                    ready.push(next.clone());
+                }
+            }
+            order.push(node);
+        }
+
+        if order.len() == self.node_count() { Some(order) } else { None }
+    }
+
+    /// Returns the length and the nodes of the shortest path from ```from``` to ```to```, with
+    /// edge lengths given by ```weight```, or ```None``` if there is no path or no such nodes.
+    pub fn shortest_path(&self, from: &N, to: &N, weight: |&E| -> uint) -> Option<(uint, Vec<N>)> {
+        if !self.contains_node(from) || !self.contains_node(to) {
+            return None;
+        }
+
+        let mut distance: HashMap<N, uint> = HashMap::new();
+        let mut parent: HashMap<N, N> = HashMap::new();
+        let mut nodes = vec![from.clone()]; // heap refers to nodes by index
+        let mut heap = BinaryHeap::new();
+        distance.insert(from.clone(), 0);
+        heap.push(State{cost: 0, node: 0});
+
+        loop {
+            let State{cost, node} = match heap.pop() {
+                None        => break,
+                Some(state) => state,
+            };
+            let current = nodes[node].clone();
+            if cost > *distance.get(&current).unwrap() {
+                continue;
+            }
+            if current == *to {
+                let mut path = vec![current.clone()];
+                loop {
+                    let p = match parent.get(&path[path.len() - 1]) {
+                        None    => break,
+                        Some(p) => p.clone(),
+                    };
+                    path.push(p);
+                }
+                path.reverse();
+                return Some((cost, path));
+            }
+            for (next, value) in self.successors(&current) {
+                let next_cost = cost + weight(value);
+                let better = match distance.get(next) {
+                    None    => true,
+                    Some(d) => next_cost < *d,
+                };
+                if better {
+                    distance.insert(next.clone(), next_cost);
+                    parent.insert(next.clone(), current.clone());
+                    nodes.push(next.clone());
+                    heap.push(State{cost: next_cost, node: nodes.len() - 1});
+                }
+            }
+        }
+        None
+    }
+}
+
+// min-heap entry for Dijkstra algorithm
+struct State {
+    cost: uint,
+    node: uint,
+}
+
+impl PartialEq for State {
+    fn eq(&self, other: &State) -> bool {
+        self.cost == other.cost
+    }
+}
+
+impl Eq for State {}
+
+impl PartialOrd for State {
+    fn partial_cmp(&self, other: &State) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for State {
+    fn cmp(&self, other: &State) -> Ordering {
+        other.cost.cmp(&self.cost)
+    }
+}
+
+pub struct Nodes<'a, N: 'a, E: 'a> {
+    iter: HamtIterator<'a, N, Rc<Hamt<N, E>>>,
+}
+
+impl<'a, N: 'a, E: 'a> Iterator<&'a N> for Nodes<'a, N, E> {
+    fn next(&mut self) -> Option<&'a N> {
+        self.iter.next().map(|(n, _)| n)
+    }
+}
+
+impl<N: Hash + Eq + Clone, E: Clone> Persistent<PersGraph<N, E>> for PersGraph<N, E> {
+    fn get_by_revision(&self, revision : Revision) -> PersGraph<N, E> {
+        assert!(self.shared_data.borrow().roots.contains_key(&revision));
+
+        PersGraph{line_history: vec![revision],
+                  head_revision_id: 0,
+                  root: self.shared_data.borrow().roots[revision].clone(),
+                  shared_data: self.shared_data.clone()}
+    }
+
+    fn current_revision_id(&self) -> Revision {
+        self.line_history[self.head_revision_id]
+    }
+}
+
+impl<N: Hash + Eq + Clone, E: Clone> TryPersistent<PersGraph<N, E>> for PersGraph<N, E> {
+    fn contains_revision(&self, revision : Revision) -> bool {
+        self.shared_data.borrow().roots.contains_key(&revision)
+    }
+}
+
+impl<N: Hash + Eq + Clone, E: Clone> Recall for PersGraph<N, E> {
+    fn undo(&mut self) -> Revision {
+        assert!(self.head_revision_id > 0u);
+
+        self.head_revision_id -= 1;
+        let revision = self.line_history[self.head_revision_id];
+        self.root = self.shared_data.borrow().roots[revision].clone();
+        revision
+    }
+
+    fn redo(&mut self) -> Revision {
+        assert!(self.head_revision_id + 1u < self.line_history.len());
+
+        self.head_revision_id += 1;
+        let revision = self.line_history[self.head_revision_id];
+        self.root = self.shared_data.borrow().roots[revision].clone();
+        revision
+    }
+}
+
+impl<N: Hash + Eq + Clone, E: Clone> TryRecall for PersGraph<N, E> {
+    fn can_undo(&self) -> bool {
+        self.head_revision_id > 0u
+    }
+
+    fn can_redo(&self) -> bool {
+        self.head_revision_id + 1u < self.line_history.len()
+    }
+
+    fn line_revision(&self) -> Revision {
+        self.line_history[self.head_revision_id]
+    }
+}
+
+impl<N: Hash + Eq + Clone, E: Clone> FullyPersistent<PersGraph<N, E>> for PersGraph<N, E> { }
+
+impl<N: Hash + Eq + Clone, E: Clone> Clone for PersGraph<N, E> {
+    fn clone(&self) -> Self {
+        PersGraph{line_history: self.line_history.clone(),
+                  head_revision_id: self.head_revision_id,
+                  root: self.root.clone(),
+                  shared_data: self.shared_data.clone()}
+    }
+}
+
+#[test]
+fn graph_edit_test() {
+    let mut graph = PersGraph::<int, ()>::new();
+    graph.add_edge(1, 2, ());
+    graph.add_edge(2, 3, ());
+    graph.add_edge(3, 1, ());
+    let rev_cycle = graph.add_edge(1, 4, ());
+    assert_eq!(graph.node_count(), 4u);
+    assert_eq!(graph.edge_count(), 4u);
+
+    graph.remove_node(&1);
+    assert_eq!(graph.node_count(), 3u);
+    assert_eq!(graph.edge_count(), 1u);
+    assert!(graph.edge(&2, &3).is_some());
+    assert_eq!(graph.predecessors(&3).count(), 1u);
+    assert_eq!(graph.predecessors(&4).count(), 0u);
+
+    let old = graph.get_by_revision(rev_cycle);
+    assert!(old.edge(&3, &1).is_some());
+    assert_eq!(old.successors(&1).count(), 2u);
+}
+
+#[test]
+fn graph_traversal_test() {
+    let mut graph = PersGraph::<int, ()>::new();
+    graph.add_edge(1, 2, ());
+    graph.add_edge(1, 3, ());
+    graph.add_edge(2, 4, ());
+    graph.add_edge(3, 4, ());
+    graph.add_node(5);
+
+    let bfs = graph.bfs(&1);
+    assert_eq!(bfs.len(), 4u);
+    assert_eq!(bfs[0], 1);
+    assert_eq!(bfs[3], 4);
+
+    let dfs = graph.dfs(&1);
+    assert_eq!(dfs.len(), 4u);
+    assert_eq!(dfs[0], 1);
+
+    assert!(graph.bfs(&6).is_empty());
+    assert!(graph.dfs(&6).is_empty());
+    assert_eq!(graph.shortest_path(&6, &1, |_| 1), None);
+    assert_eq!(graph.shortest_path(&1, &6, |_| 1), None);
+    assert_eq!(graph.try_bfs(&6), Err(PersError::KeyNotFound));
+    assert_eq!(graph.try_dfs(&1).unwrap(), dfs);
+    assert!(graph.try_successors(&6).is_err());
+    assert_eq!(graph.try_predecessors(&4).unwrap().count(), 2u);
+
+    let order = graph.topological_sort().unwrap();
+    let position = |n: int| order.iter().position(|&x| x == n).unwrap();
+    assert_eq!(order.len(), 5u);
+    assert!(position(1) < position(2) && position(1) < position(3));
+    assert!(position(2) < position(4) && position(3) < position(4));
+
+    graph.add_edge(4, 1, ());
+    assert_eq!(graph.topological_sort(), None);
+    graph.undo();
+    assert!(graph.topological_sort().is_some());
+}
+
+#[test]
+fn graph_shortest_path_what_if_test() {
+    let mut graph = PersGraph::<&str, uint>::new();
+    graph.add_edge("a", "b", 1);
+    graph.add_edge("b", "d", 1);
+    graph.add_edge("a", "c", 2);
+    graph.add_edge("c", "d", 2);
+    assert_eq!(graph.shortest_path(&"a", &"d", |w| *w), Some((2, vec!["a", "b", "d"])));
+
+    let mut branch = graph.clone();
+    branch.remove_edge(&"b", &"d");
+    assert_eq!(branch.shortest_path(&"a", &"d", |w| *w), Some((4, vec!["a", "c", "d"])));
+    branch.remove_edge(&"c", &"d");
+    assert_eq!(branch.shortest_path(&"a", &"d", |w| *w), None);
+    assert_eq!(graph.shortest_path(&"a", &"d", |w| *w), Some((2, vec!["a", "b", "d"])));
+}
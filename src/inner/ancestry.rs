@@ -0,0 +1,175 @@
+/*
+ *  This file contains ancestor queries for revision trees.
+ *
+ *  Revisions are only added as leaves, so every revision keeps its depth and
+ *  the list of its 2^k-th ancestors (binary lifting), which is built in
+ *  O(lg(N)) time from the lists of its ancestors. Ancestor at a given depth,
+ *  ancestor test and lowest common ancestor take O(lg(N)) time and no
+ *  allocations. A forest is allowed: revisions with no parent are roots.
+ */
+
+use std::collections::HashMap;
+use std::vec::Vec;
+use inner::persistent::Revision;
+
+pub struct Ancestry {
+    depth: HashMap<Revision, uint>,
+    jump:  HashMap<Revision, Vec<Revision>>, // 2^k-th ancestors for k = 0, 1, ...
+}
+
+impl Ancestry {
+    pub fn new() -> Ancestry {
+        Ancestry {
+            depth: HashMap::new(),
+            jump:  HashMap::new()
+        }
+    }
+
+    pub fn contains(&self, r: Revision) -> bool {
+        self.depth.contains_key(&r)
+    }
+
+    // new leaf revision, or a new root if there is no parent
+    pub fn insert(&mut self, r: Revision, parent: Option<Revision>) {
+        assert!(!self.contains(r));
+
+        match parent {
+            None => {
+                self.depth.insert(r, 0);
+                self.jump.insert(r, Vec::new());
+            },
+            Some(p) => {
+                assert!(self.contains(p));
+
+                let mut up = vec![p];
+                loop {
+                    let k = up.len() - 1;
+                    let ups = &self.jump[up[k]];
+                    if ups.len() <= k {
+                        break;
+                    }
+                    up.push(ups[k]);
+                }
+                let depth = self.depth[p] + 1;
+                self.depth.insert(r, depth);
+                self.jump.insert(r, up);
+            }
+        }
+    }
+
+    pub fn depth(&self, r: Revision) -> uint {
+        assert!(self.contains(r));
+
+        self.depth[r]
+    }
+
+    pub fn ancestor_at_depth(&self, r: Revision, d: uint) -> Revision {
+        assert!(d <= self.depth(r));
+
+        let mut c = r;
+        let mut diff = self.depth(r) - d;
+        let mut k = 0;
+        while diff > 0 {
+            if diff & 1 == 1 {
+                c = self.jump[c][k];
+            }
+            diff >>= 1;
+            k += 1;
+        }
+        c
+    }
+
+    // true if a is b or an ancestor of b
+    pub fn is_ancestor(&self, a: Revision, b: Revision) -> bool {
+        let d = self.depth(a);
+        d <= self.depth(b) && self.ancestor_at_depth(b, d) == a
+    }
+
+    // None if the revisions are in different trees of the forest
+    pub fn lca(&self, a: Revision, b: Revision) -> Option<Revision> {
+        let d = if self.depth(a) < self.depth(b) { self.depth(a) } else { self.depth(b) };
+        let mut a = self.ancestor_at_depth(a, d);
+        let mut b = self.ancestor_at_depth(b, d);
+        if a == b {
+            return Some(a);
+        }
+
+        for k in range(0, self.jump[a].len()).rev() {
+            let (ja, jb) = (&self.jump[a], &self.jump[b]);
+            if k < ja.len() && ja[k] != jb[k] {
+                a = ja[k];
+                b = jb[k];
+            }
+        }
+        match self.jump[a].len() {
+            0 => None,
+            _ => Some(self.jump[a][0]),
+        }
+    }
+}
+
+#[cfg(test)]
+fn naive_branch(parent: &HashMap<Revision, Revision>, r: Revision) -> Vec<Revision> {
+    let mut branch = vec![r];
+    let mut c = r;
+    while parent.contains_key(&c) {
+        c = parent[c];
+        branch.push(c);
+    }
+    branch
+}
+
+#[test]
+fn ancestry_test() {
+    /*
+     *  1--2--4--6   7--8
+     *      \
+     *       3--5
+     */
+    let mut a = Ancestry::new();
+    a.insert(1, None);
+    a.insert(2, Some(1));
+    a.insert(3, Some(2));
+    a.insert(4, Some(2));
+    a.insert(5, Some(3));
+    a.insert(6, Some(4));
+    a.insert(7, None);
+    a.insert(8, Some(7));
+
+    assert_eq!(a.depth(1), 0u);
+    assert_eq!(a.depth(6), 3u);
+    assert_eq!(a.ancestor_at_depth(6, 1), 2);
+    assert_eq!(a.ancestor_at_depth(6, 3), 6);
+    assert!(a.is_ancestor(2, 5));
+    assert!(a.is_ancestor(5, 5));
+    assert!(!a.is_ancestor(4, 5));
+    assert_eq!(a.lca(5, 6), Some(2));
+    assert_eq!(a.lca(4, 6), Some(4));
+    assert_eq!(a.lca(5, 8), None);
+}
+
+#[test]
+fn deep_ancestry_test() {
+    use inner::lcg_random::*;
+
+    let mut a = Ancestry::new();
+    let mut parent = HashMap::new();
+    let mut rnd: CoolLCG = LCG::new();
+    a.insert(1, None);
+    for rev in range(2i, 2000) {
+        let p = if rnd.next() % 8 == 0 { rnd.next() % (rev - 1) + 1 } else { rev - 1 };
+        a.insert(rev, Some(p));
+        parent.insert(rev, p);
+    }
+
+    for _ in range(0u, 500) {
+        let x = rnd.next() % 1999 + 1;
+        let y = rnd.next() % 1999 + 1;
+        let bx = naive_branch(&parent, x);
+        let by = naive_branch(&parent, y);
+        assert_eq!(a.depth(x), bx.len() - 1);
+        assert_eq!(a.is_ancestor(y, x), bx.contains(&y));
+        let lca = *bx.iter().filter(|r| by.contains(r)).next().unwrap();
+        assert_eq!(a.lca(x, y), Some(lca));
+    }
+}
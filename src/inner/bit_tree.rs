@@ -0,0 +1,253 @@
+/*
+ *  This file contains generic persistent bit tree implementation.
+ *
+ *  Tree is a complete binary tree with 64-bit words in the leaves. Every
+ *  branch caches the number of set bits below it, so test, set, rank and
+ *  select take O(lg(N)) time. All-zero subtrees of any height are shared
+ *  as one Zero node, so sparse sets cost memory only for non-zero words.
+ *  Updates use path-copying approach.
+ */
+
+use std::num::Int;
+use std::rc::Rc;
+use std::uint;
+
+pub const WORD_BITS: uint = 64;
+
+// highest tree, it covers 2^(uint::BITS - 1) bits as 64 = 2^6 bits words
+pub const MAX_HEIGHT: uint = uint::BITS - 7;
+
+pub enum BitTree {
+    Zero,
+    Word(u64),
+    Branch(uint          /* popcount */,
+           Rc<BitTree>   /* left     */,
+           Rc<BitTree>   /* right    */,)
+}
+
+pub enum BitOp {
+    And,
+    Or,
+    Xor,
+}
+
+// number of bits covered by tree of the given height
+pub fn capacity(height: uint) -> uint {
+    assert!(height <= MAX_HEIGHT);
+
+    WORD_BITS << height
+}
+
+fn branch(left: Rc<BitTree>, right: Rc<BitTree>) -> Rc<BitTree> {
+    if left.is_zero() && right.is_zero() {
+        return BitTree::new_zero();
+    }
+    let count = left.count() + right.count();
+    Rc::new(BitTree::Branch(count, left, right))
+}
+
+fn word(w: u64) -> Rc<BitTree> {
+    if w == 0 {
+        BitTree::new_zero()
+    } else {
+        Rc::new(BitTree::Word(w))
+    }
+}
+
+impl BitTree {
+    pub fn new_zero() -> Rc<BitTree> {
+        Rc::new(BitTree::Zero)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        match *self {
+            BitTree::Zero => true,
+            _             => false,
+        }
+    }
+
+    pub fn count(&self) -> uint {
+        match *self {
+            BitTree::Zero                => 0,
+            BitTree::Word(w)             => w.count_ones(),
+            BitTree::Branch(count, _, _) => count,
+        }
+    }
+
+    fn children(node: &Rc<BitTree>) -> (Rc<BitTree>, Rc<BitTree>) {
+        match **node {
+            BitTree::Branch(_, ref left, ref right) => (left.clone(), right.clone()),
+            BitTree::Zero                           => (BitTree::new_zero(), BitTree::new_zero()),
+            BitTree::Word(_)                        => panic!("word has no children"),
+        }
+    }
+
+    fn to_word(&self) -> u64 {
+        match *self {
+            BitTree::Zero    => 0,
+            BitTree::Word(w) => w,
+            _                => panic!("branch is not a word"),
+        }
+    }
+
+    // tree of height + 1 which holds this one in the left half
+    pub fn grow(node: &Rc<BitTree>) -> Rc<BitTree> {
+        branch(node.clone(), BitTree::new_zero())
+    }
+
+    pub fn test(&self, height: uint, index: uint) -> bool {
+        match *self {
+            BitTree::Zero =>
+                false,
+            BitTree::Word(w) =>
+                (w >> index) & 1 == 1,
+            BitTree::Branch(_, ref left, ref right) => {
+                let half = capacity(height - 1);
+                if index < half {
+                    left.test(height - 1, index)
+                } else {
+                    right.test(height - 1, index - half)
+                }
+            }
+        }
+    }
+
+    pub fn assign(node: &Rc<BitTree>, height: uint, index: uint, value: bool) -> Rc<BitTree> {
+        if height == 0 {
+            let w = node.to_word();
+            let mask = 1u64 << index;
+            return word(if value { w | mask } else { w & !mask });
+        }
+        if node.is_zero() && !value {
+            return node.clone();
+        }
+        let half = capacity(height - 1);
+        let (left, right) = BitTree::children(node);
+        if index < half {
+            branch(BitTree::assign(&left, height - 1, index, value), right)
+        } else {
+            branch(left, BitTree::assign(&right, height - 1, index - half, value))
+        }
+    }
+
+    // number of set bits in [0; index)
+    pub fn rank(&self, height: uint, index: uint) -> uint {
+        match *self {
+            BitTree::Zero =>
+                0,
+            BitTree::Word(w) =>
+                if index >= WORD_BITS { w.count_ones() } else { (w & ((1u64 << index) - 1)).count_ones() },
+            BitTree::Branch(count, ref left, ref right) => {
+                let half = capacity(height - 1);
+                if index >= capacity(height) {
+                    count
+                } else if index <= half {
+                    left.rank(height - 1, index)
+                } else {
+                    left.count() + right.rank(height - 1, index - half)
+                }
+            }
+        }
+    }
+
+    // position of the k-th (from zero) set bit
+    pub fn select(&self, height: uint, k: uint) -> Option<uint> {
+        if k >= self.count() {
+            return None;
+        }
+        match *self {
+            BitTree::Zero =>
+                None,
+            BitTree::Word(w) => {
+                let mut rest = w;
+                for _ in range(0u, k) {
+                    rest &= rest - 1; // drop the lowest set bit
+                }
+                Some(rest.trailing_zeros())
+            },
+            BitTree::Branch(_, ref left, ref right) =>
+                if k < left.count() {
+                    left.select(height - 1, k)
+                } else {
+                    right.select(height - 1, k - left.count()).map(|i| i + capacity(height - 1))
+                },
+        }
+    }
+
+    // combine two trees of the same height
+    pub fn combine(a: &Rc<BitTree>, b: &Rc<BitTree>, height: uint, op: BitOp) -> Rc<BitTree> {
+        let same = &**a as *const BitTree == &**b as *const BitTree;
+        match op {
+            BitOp::And if a.is_zero() || b.is_zero() => return BitTree::new_zero(),
+            BitOp::And | BitOp::Or if same           => return a.clone(),
+            BitOp::Xor if same                       => return BitTree::new_zero(),
+            BitOp::Or | BitOp::Xor if a.is_zero()    => return b.clone(),
+            BitOp::Or | BitOp::Xor if b.is_zero()    => return a.clone(),
+            _                                        => {},
+        }
+        if height == 0 {
+            let (x, y) = (a.to_word(), b.to_word());
+            return word(match op {
+                BitOp::And => x & y,
+                BitOp::Or  => x | y,
+                BitOp::Xor => x ^ y,
+            });
+        }
+        let (a_left, a_right) = BitTree::children(a);
+        let (b_left, b_right) = BitTree::children(b);
+        branch(BitTree::combine(&a_left, &b_left, height - 1, op),
+               BitTree::combine(&a_right, &b_right, height - 1, op))
+    }
+}
+
+impl Copy for BitOp {}
+
+#[cfg(test)]
+fn build(height: uint, bits: &[uint]) -> Rc<BitTree> {
+    bits.iter().fold(BitTree::new_zero(), |node, &i| BitTree::assign(&node, height, i, true))
+}
+
+#[test]
+fn bit_tree_assign_test() {
+    let a = build(3, &[0, 5, 64, 200, 511]);
+    assert_eq!(a.count(), 5u);
+    assert!(a.test(3, 200));
+    assert!(!a.test(3, 201));
+
+    let b = BitTree::assign(&a, 3, 200, false);
+    assert!(a.test(3, 200));
+    assert!(!b.test(3, 200));
+    assert_eq!(b.count(), 4u);
+
+    let c = BitTree::assign(&BitTree::assign(&b, 3, 0, false), 3, 5, false);
+    let d = BitTree::assign(&BitTree::assign(&c, 3, 64, false), 3, 511, false);
+    assert!(d.is_zero());
+}
+
+#[test]
+fn bit_tree_rank_select_test() {
+    let bits = [1u, 3, 63, 64, 100, 300, 301];
+    let a = build(3, &bits);
+    for (k, &i) in bits.iter().enumerate() {
+        assert_eq!(a.select(3, k), Some(i));
+        assert_eq!(a.rank(3, i), k);
+        assert_eq!(a.rank(3, i + 1), k + 1);
+    }
+    assert_eq!(a.select(3, bits.len()), None);
+    assert_eq!(a.rank(3, capacity(3)), bits.len());
+}
+
+#[test]
+fn bit_tree_combine_test() {
+    let a = build(2, &[1, 2, 100, 200]);
+    let b = build(2, &[2, 100, 150]);
+
+    let and = BitTree::combine(&a, &b, 2, BitOp::And);
+    let or = BitTree::combine(&a, &b, 2, BitOp::Or);
+    let xor = BitTree::combine(&a, &b, 2, BitOp::Xor);
+
+    assert_eq!(range(0u, 256).filter(|&i| and.test(2, i)).collect::<Vec<uint>>(), vec![2, 100]);
+    assert_eq!(range(0u, 256).filter(|&i| or.test(2, i)).collect::<Vec<uint>>(), vec![1, 2, 100, 150, 200]);
+    assert_eq!(range(0u, 256).filter(|&i| xor.test(2, i)).collect::<Vec<uint>>(), vec![1, 150, 200]);
+    assert!(BitTree::combine(&a, &a, 2, BitOp::Xor).is_zero());
+}
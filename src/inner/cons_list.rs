@@ -0,0 +1,83 @@
+/*
+ *  This file contains generic persistent singly linked list implementation.
+ *
+ *  List is never modified in place: push returns a new head which shares
+ *  its tail with the old list, so push, pop and access to the first element
+ *  take O(1) time and every old version stays valid.
+ */
+
+use std::rc::Rc;
+
+pub enum ConsList<T> {
+    Nil,
+    Cons(T                  /* value */,
+         Rc<ConsList<T>>    /* tail  */,)
+}
+
+impl<T: Clone> ConsList<T> {
+    pub fn new_empty() -> Rc<ConsList<T>> {
+        Rc::new(ConsList::Nil)
+    }
+
+    pub fn push(list: &Rc<ConsList<T>>, value: T) -> Rc<ConsList<T>> {
+        Rc::new(ConsList::Cons(value, list.clone()))
+    }
+
+    pub fn is_nil(&self) -> bool {
+        match *self {
+            ConsList::Nil => true,
+            _             => false,
+        }
+    }
+
+    pub fn head<'a>(&'a self) -> Option<&'a T> {
+        match *self {
+            ConsList::Nil                => None,
+            ConsList::Cons(ref value, _) => Some(value),
+        }
+    }
+
+    // return list without the first element, Nil stays Nil
+    pub fn tail(list: &Rc<ConsList<T>>) -> Rc<ConsList<T>> {
+        match **list {
+            ConsList::Nil               => list.clone(),
+            ConsList::Cons(_, ref tail) => tail.clone(),
+        }
+    }
+
+    pub fn iter<'a>(&'a self) -> ConsListIterator<'a, T> {
+        ConsListIterator{node: self}
+    }
+}
+
+pub struct ConsListIterator<'a, T: 'a> {
+    node: &'a ConsList<T>,
+}
+
+impl<'a, T: 'a> Iterator<&'a T> for ConsListIterator<'a, T> {
+    fn next(&mut self) -> Option<&'a T> {
+        let node = self.node;
+        match *node {
+            ConsList::Nil                       => None,
+            ConsList::Cons(ref value, ref tail) => {
+                self.node = &**tail;
+                Some(value)
+            }
+        }
+    }
+}
+
+#[test]
+fn cons_list_share_tail_test() {
+    let empty = ConsList::<int>::new_empty();
+    let a = ConsList::push(&empty, 1);
+    let b = ConsList::push(&a, 2);
+    let c = ConsList::push(&a, 3);
+
+    assert!(empty.is_nil());
+    assert_eq!(a.iter().map(|x| *x).collect::<Vec<int>>(), vec![1]);
+    assert_eq!(b.iter().map(|x| *x).collect::<Vec<int>>(), vec![2, 1]);
+    assert_eq!(c.iter().map(|x| *x).collect::<Vec<int>>(), vec![3, 1]);
+    assert_eq!(ConsList::tail(&c).head(), Some(&1));
+    assert!(ConsList::tail(&empty).is_nil());
+}
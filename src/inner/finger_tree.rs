@@ -0,0 +1,447 @@
+/*
+ *  This file contains generic 2-3 finger tree implementation.
+ *
+ *  Finger tree structure and operations are described in:
+ *      R. Hinze, R. Paterson, "Finger trees: a simple general-purpose data structure"
+ *      http://www.staff.city.ac.uk/~ross/papers/FingerTree.html
+ *
+ *  Tree gives amortized O(1) access and push/pop at both ends and
+ *  O(lg(N)) concatenation, split and access by index. Every node caches
+ *  the number of elements below it, which is the measure used for indexing.
+ *
+ *  Rust can not express the nested type FingerTree<Node<T>> of the paper,
+ *  so elements and 2-3 nodes share one Item type and the depth is implied.
+ */
+
+use std::rc::Rc;
+use std::vec::Vec;
+
+pub enum Item<T> {
+    Leaf(T),
+    Node2(uint /* size */, Rc<Item<T>>, Rc<Item<T>>),
+    Node3(uint /* size */, Rc<Item<T>>, Rc<Item<T>>, Rc<Item<T>>),
+}
+
+pub type Digit<T> = Vec<Rc<Item<T>>>; // from one to four items
+
+pub enum FingerTree<T> {
+    Empty,
+    Single(Rc<Item<T>>),
+    Deep(uint              /* size   */,
+         Digit<T>          /* prefix */,
+         Rc<FingerTree<T>> /* middle */,
+         Digit<T>          /* suffix */,)
+}
+
+impl<T: Clone> Item<T> {
+    pub fn size(&self) -> uint {
+        match *self {
+            Item::Leaf(_)              => 1,
+            Item::Node2(size, _, _)    => size,
+            Item::Node3(size, _, _, _) => size,
+        }
+    }
+
+    fn node2(a: Rc<Item<T>>, b: Rc<Item<T>>) -> Rc<Item<T>> {
+        let size = a.size() + b.size();
+        Rc::new(Item::Node2(size, a, b))
+    }
+
+    fn node3(a: Rc<Item<T>>, b: Rc<Item<T>>, c: Rc<Item<T>>) -> Rc<Item<T>> {
+        let size = a.size() + b.size() + c.size();
+        Rc::new(Item::Node3(size, a, b, c))
+    }
+
+    fn to_digit(&self) -> Digit<T> {
+        match *self {
+            Item::Leaf(_)                       => panic!("leaf can not be unpacked"),
+            Item::Node2(_, ref a, ref b)        => vec![a.clone(), b.clone()],
+            Item::Node3(_, ref a, ref b, ref c) => vec![a.clone(), b.clone(), c.clone()],
+        }
+    }
+
+    fn lookup<'a>(&'a self, index: uint) -> &'a T {
+        match *self {
+            Item::Leaf(ref value) =>
+                value,
+            Item::Node2(_, ref a, ref b) =>
+                if index < a.size() {
+                    a.lookup(index)
+                } else {
+                    b.lookup(index - a.size())
+                },
+            Item::Node3(_, ref a, ref b, ref c) =>
+                if index < a.size() {
+                    a.lookup(index)
+                } else if index < a.size() + b.size() {
+                    b.lookup(index - a.size())
+                } else {
+                    c.lookup(index - a.size() - b.size())
+                },
+        }
+    }
+}
+
+fn digit_size<T: Clone>(digit: &[Rc<Item<T>>]) -> uint {
+    digit.iter().fold(0, |acc, item| acc + item.size())
+}
+
+// find item of the digit which contains index-th element
+fn find_in_digit<T: Clone>(digit: &[Rc<Item<T>>], index: uint) -> (uint /* position */, uint /* offset */) {
+    let mut offset = index;
+    for (pos, item) in digit.iter().enumerate() {
+        if offset < item.size() || pos + 1 == digit.len() {
+            return (pos, offset);
+        }
+        offset -= item.size();
+    }
+    panic!("empty digit");
+}
+
+impl<T: Clone> FingerTree<T> {
+    pub fn new_empty() -> Rc<FingerTree<T>> {
+        Rc::new(FingerTree::Empty)
+    }
+
+    pub fn new_leaf(value: T) -> Rc<Item<T>> {
+        Rc::new(Item::Leaf(value))
+    }
+
+    pub fn size(&self) -> uint {
+        match *self {
+            FingerTree::Empty                 => 0,
+            FingerTree::Single(ref item)      => item.size(),
+            FingerTree::Deep(size, _, _, _)   => size,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        match *self {
+            FingerTree::Empty => true,
+            _                 => false,
+        }
+    }
+
+    fn deep(prefix: Digit<T>, middle: Rc<FingerTree<T>>, suffix: Digit<T>) -> Rc<FingerTree<T>> {
+        let size = digit_size(prefix.as_slice()) + middle.size() + digit_size(suffix.as_slice());
+        Rc::new(FingerTree::Deep(size, prefix, middle, suffix))
+    }
+
+    fn from_digit(digit: &[Rc<Item<T>>]) -> Rc<FingerTree<T>> {
+        digit.iter().fold(FingerTree::new_empty(), |tree, item| FingerTree::push_back(&tree, item.clone()))
+    }
+
+    // deep node with possibly empty prefix
+    fn deep_left(prefix: Digit<T>, middle: Rc<FingerTree<T>>, suffix: Digit<T>) -> Rc<FingerTree<T>> {
+        if !prefix.is_empty() {
+            return FingerTree::deep(prefix, middle, suffix);
+        }
+        match FingerTree::view_front(&middle) {
+            None                      => FingerTree::from_digit(suffix.as_slice()),
+            Some((node, new_middle))  => FingerTree::deep(node.to_digit(), new_middle, suffix),
+        }
+    }
+
+    // deep node with possibly empty suffix
+    fn deep_right(prefix: Digit<T>, middle: Rc<FingerTree<T>>, suffix: Digit<T>) -> Rc<FingerTree<T>> {
+        if !suffix.is_empty() {
+            return FingerTree::deep(prefix, middle, suffix);
+        }
+        match FingerTree::view_back(&middle) {
+            None                      => FingerTree::from_digit(prefix.as_slice()),
+            Some((new_middle, node))  => FingerTree::deep(prefix, new_middle, node.to_digit()),
+        }
+    }
+
+    pub fn push_front(tree: &Rc<FingerTree<T>>, item: Rc<Item<T>>) -> Rc<FingerTree<T>> {
+        match **tree {
+            FingerTree::Empty =>
+                Rc::new(FingerTree::Single(item)),
+            FingerTree::Single(ref other) =>
+                FingerTree::deep(vec![item], FingerTree::new_empty(), vec![other.clone()]),
+            FingerTree::Deep(_, ref prefix, ref middle, ref suffix) =>
+                if prefix.len() == 4 {
+                    let node = Item::node3(prefix[1].clone(), prefix[2].clone(), prefix[3].clone());
+                    FingerTree::deep(vec![item, prefix[0].clone()],
+                                     FingerTree::push_front(middle, node),
+                                     suffix.clone())
+                } else {
+                    let mut new_prefix = vec![item];
+                    new_prefix.push_all(prefix.as_slice());
+                    FingerTree::deep(new_prefix, middle.clone(), suffix.clone())
+                }
+        }
+    }
+
+    pub fn push_back(tree: &Rc<FingerTree<T>>, item: Rc<Item<T>>) -> Rc<FingerTree<T>> {
+        match **tree {
+            FingerTree::Empty =>
+                Rc::new(FingerTree::Single(item)),
+            FingerTree::Single(ref other) =>
+                FingerTree::deep(vec![other.clone()], FingerTree::new_empty(), vec![item]),
+            FingerTree::Deep(_, ref prefix, ref middle, ref suffix) =>
+                if suffix.len() == 4 {
+                    let node = Item::node3(suffix[0].clone(), suffix[1].clone(), suffix[2].clone());
+                    FingerTree::deep(prefix.clone(),
+                                     FingerTree::push_back(middle, node),
+                                     vec![suffix[3].clone(), item])
+                } else {
+                    let mut new_suffix = suffix.clone();
+                    new_suffix.push(item);
+                    FingerTree::deep(prefix.clone(), middle.clone(), new_suffix)
+                }
+        }
+    }
+
+    // return the first item and the rest of the tree
+    pub fn view_front(tree: &Rc<FingerTree<T>>) -> Option<(Rc<Item<T>>, Rc<FingerTree<T>>)> {
+        match **tree {
+            FingerTree::Empty =>
+                None,
+            FingerTree::Single(ref item) =>
+                Some((item.clone(), FingerTree::new_empty())),
+            FingerTree::Deep(_, ref prefix, ref middle, ref suffix) =>
+                Some((prefix[0].clone(),
+                      FingerTree::deep_left(prefix.slice_from(1).to_vec(), middle.clone(), suffix.clone()))),
+        }
+    }
+
+    // return the rest of the tree and the last item
+    pub fn view_back(tree: &Rc<FingerTree<T>>) -> Option<(Rc<FingerTree<T>>, Rc<Item<T>>)> {
+        match **tree {
+            FingerTree::Empty =>
+                None,
+            FingerTree::Single(ref item) =>
+                Some((FingerTree::new_empty(), item.clone())),
+            FingerTree::Deep(_, ref prefix, ref middle, ref suffix) => {
+                let last = suffix.len() - 1;
+                Some((FingerTree::deep_right(prefix.clone(), middle.clone(), suffix.slice_to(last).to_vec()),
+                      suffix[last].clone()))
+            }
+        }
+    }
+
+    pub fn front<'a>(&'a self) -> Option<&'a T> {
+        if self.is_empty() {
+            None
+        } else {
+            Some(self.lookup(0))
+        }
+    }
+
+    pub fn back<'a>(&'a self) -> Option<&'a T> {
+        if self.is_empty() {
+            None
+        } else {
+            Some(self.lookup(self.size() - 1))
+        }
+    }
+
+    // pack a sequence of 2..12 items into 2-3 nodes
+    fn nodes(items: &[Rc<Item<T>>]) -> Digit<T> {
+        let mut result = Vec::new();
+        let mut rest = items;
+        loop {
+            match rest.len() {
+                2 => { result.push(Item::node2(rest[0].clone(), rest[1].clone())); break; },
+                3 => { result.push(Item::node3(rest[0].clone(), rest[1].clone(), rest[2].clone())); break; },
+                4 => {
+                    result.push(Item::node2(rest[0].clone(), rest[1].clone()));
+                    result.push(Item::node2(rest[2].clone(), rest[3].clone()));
+                    break;
+                },
+                _ => {
+                    result.push(Item::node3(rest[0].clone(), rest[1].clone(), rest[2].clone()));
+                    rest = rest.slice_from(3);
+                }
+            }
+        }
+        result
+    }
+
+    fn app3(left: &Rc<FingerTree<T>>, items: &[Rc<Item<T>>], right: &Rc<FingerTree<T>>) -> Rc<FingerTree<T>> {
+        match (&**left, &**right) {
+            (&FingerTree::Empty, _) =>
+                items.iter().rev().fold(right.clone(), |tree, item| FingerTree::push_front(&tree, item.clone())),
+            (_, &FingerTree::Empty) =>
+                items.iter().fold(left.clone(), |tree, item| FingerTree::push_back(&tree, item.clone())),
+            (&FingerTree::Single(ref item), _) =>
+                FingerTree::push_front(&FingerTree::app3(&FingerTree::new_empty(), items, right), item.clone()),
+            (_, &FingerTree::Single(ref item)) =>
+                FingerTree::push_back(&FingerTree::app3(left, items, &FingerTree::new_empty()), item.clone()),
+            (&FingerTree::Deep(_, ref prefix1, ref middle1, ref suffix1),
+             &FingerTree::Deep(_, ref prefix2, ref middle2, ref suffix2)) => {
+                let mut glue = suffix1.clone();
+                glue.push_all(items);
+                glue.push_all(prefix2.as_slice());
+                let middle = FingerTree::app3(middle1, FingerTree::nodes(glue.as_slice()).as_slice(), middle2);
+                FingerTree::deep(prefix1.clone(), middle, suffix2.clone())
+            }
+        }
+    }
+
+    pub fn concat(left: &Rc<FingerTree<T>>, right: &Rc<FingerTree<T>>) -> Rc<FingerTree<T>> {
+        FingerTree::app3(left, &[], right)
+    }
+
+    // return trees [0; pos), item containing index and (pos + item.size(); size)
+    fn split_tree(tree: &Rc<FingerTree<T>>, index: uint) -> (Rc<FingerTree<T>>, Rc<Item<T>>, Rc<FingerTree<T>>) {
+        match **tree {
+            FingerTree::Empty =>
+                panic!("can not split empty tree"),
+            FingerTree::Single(ref item) =>
+                (FingerTree::new_empty(), item.clone(), FingerTree::new_empty()),
+            FingerTree::Deep(_, ref prefix, ref middle, ref suffix) => {
+                let prefix_size = digit_size(prefix.as_slice());
+                if index < prefix_size {
+                    let (pos, _) = find_in_digit(prefix.as_slice(), index);
+                    (FingerTree::from_digit(prefix.slice_to(pos)),
+                     prefix[pos].clone(),
+                     FingerTree::deep_left(prefix.slice_from(pos + 1).to_vec(), middle.clone(), suffix.clone()))
+                } else if index < prefix_size + middle.size() {
+                    let (middle_left, node, middle_right) = FingerTree::split_tree(middle, index - prefix_size);
+                    let digit = node.to_digit();
+                    let (pos, _) = find_in_digit(digit.as_slice(), index - prefix_size - middle_left.size());
+                    (FingerTree::deep_right(prefix.clone(), middle_left, digit.slice_to(pos).to_vec()),
+                     digit[pos].clone(),
+                     FingerTree::deep_left(digit.slice_from(pos + 1).to_vec(), middle_right, suffix.clone()))
+                } else {
+                    let (pos, _) = find_in_digit(suffix.as_slice(), index - prefix_size - middle.size());
+                    (FingerTree::deep_right(prefix.clone(), middle.clone(), suffix.slice_to(pos).to_vec()),
+                     suffix[pos].clone(),
+                     FingerTree::from_digit(suffix.slice_from(pos + 1)))
+                }
+            }
+        }
+    }
+
+    // return trees with elements [0; index) and [index; size)
+    pub fn split(tree: &Rc<FingerTree<T>>, index: uint) -> (Rc<FingerTree<T>>, Rc<FingerTree<T>>) {
+        if index >= tree.size() {
+            return (tree.clone(), FingerTree::new_empty());
+        }
+        let (left, item, right) = FingerTree::split_tree(tree, index);
+        (left, FingerTree::push_front(&right, item))
+    }
+
+    pub fn lookup<'a>(&'a self, index: uint) -> &'a T {
+        assert!(index < self.size());
+
+        match *self {
+            FingerTree::Empty =>
+                panic!("can not lookup in empty tree"),
+            FingerTree::Single(ref item) =>
+                item.lookup(index),
+            FingerTree::Deep(_, ref prefix, ref middle, ref suffix) => {
+                let prefix_size = digit_size(prefix.as_slice());
+                if index < prefix_size {
+                    let (pos, offset) = find_in_digit(prefix.as_slice(), index);
+                    prefix[pos].lookup(offset)
+                } else if index < prefix_size + middle.size() {
+                    middle.lookup(index - prefix_size)
+                } else {
+                    let (pos, offset) = find_in_digit(suffix.as_slice(), index - prefix_size - middle.size());
+                    suffix[pos].lookup(offset)
+                }
+            }
+        }
+    }
+
+    pub fn iter<'a>(&'a self) -> FingerTreeIterator<'a, T> {
+        FingerTreeIterator{stack: vec![Frame::Tree(self)]}
+    }
+}
+
+enum Frame<'a, T: 'a> {
+    Tree(&'a FingerTree<T>),
+    Part(&'a Item<T>),
+}
+
+pub struct FingerTreeIterator<'a, T: 'a> {
+    stack: Vec<Frame<'a, T>>,
+}
+
+impl<'a, T: 'a> Iterator<&'a T> for FingerTreeIterator<'a, T> {
+    fn next(&mut self) -> Option<&'a T> {
+        loop {
+            match self.stack.pop() {
+                None => return None,
+                Some(Frame::Tree(tree)) => match *tree {
+                    FingerTree::Empty => continue,
+                    FingerTree::Single(ref item) => self.stack.push(Frame::Part(&**item)),
+                    FingerTree::Deep(_, ref prefix, ref middle, ref suffix) => {
+                        for item in suffix.iter().rev() {
+                            self.stack.push(Frame::Part(&**item));
+                        }
+                        self.stack.push(Frame::Tree(&**middle));
+                        for item in prefix.iter().rev() {
+                            self.stack.push(Frame::Part(&**item));
+                        }
+                    }
+                },
+                Some(Frame::Part(item)) => match *item {
+                    Item::Leaf(ref value) => return Some(value),
+                    Item::Node2(_, ref a, ref b) => {
+                        self.stack.push(Frame::Part(&**b));
+                        self.stack.push(Frame::Part(&**a));
+                    },
+                    Item::Node3(_, ref a, ref b, ref c) => {
+                        self.stack.push(Frame::Part(&**c));
+                        self.stack.push(Frame::Part(&**b));
+                        self.stack.push(Frame::Part(&**a));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+fn build_tree(from: int, to: int) -> Rc<FingerTree<int>> {
+    range(from, to).fold(FingerTree::new_empty(), |tree, i| FingerTree::push_back(&tree, FingerTree::new_leaf(i)))
+}
+
+#[cfg(test)]
+fn to_vec(tree: &Rc<FingerTree<int>>) -> Vec<int> {
+    tree.iter().map(|x| *x).collect()
+}
+
+#[test]
+fn finger_tree_push_view_test() {
+    let tree = build_tree(0, 100);
+    let tree = FingerTree::push_front(&tree, FingerTree::new_leaf(-1));
+    assert_eq!(tree.size(), 101u);
+    assert_eq!(to_vec(&tree), range(-1i, 100).collect::<Vec<int>>());
+
+    let (first, rest) = FingerTree::view_front(&tree).unwrap();
+    let (rest, last) = FingerTree::view_back(&rest).unwrap();
+    assert_eq!(*first.lookup(0), -1);
+    assert_eq!(*last.lookup(0), 99);
+    assert_eq!(to_vec(&rest), range(0i, 99).collect::<Vec<int>>());
+    assert_eq!(to_vec(&tree).len(), 101u);
+}
+
+#[test]
+fn finger_tree_lookup_test() {
+    let tree = build_tree(0, 300);
+    for i in range(0u, 300) {
+        assert_eq!(*tree.lookup(i), i as int);
+    }
+}
+
+#[test]
+fn finger_tree_concat_split_test() {
+    for n in range(0i, 40) {
+        let left = build_tree(0, n);
+        let right = build_tree(n, 2 * n + 7);
+        let joined = FingerTree::concat(&left, &right);
+        assert_eq!(to_vec(&joined), range(0i, 2 * n + 7).collect::<Vec<int>>());
+
+        for at in range(0u, joined.size() + 1) {
+            let (a, b) = FingerTree::split(&joined, at);
+            assert_eq!(a.size(), at);
+            assert_eq!(to_vec(&a), range(0i, at as int).collect::<Vec<int>>());
+            assert_eq!(to_vec(&b), range(at as int, 2 * n + 7).collect::<Vec<int>>());
+        }
+    }
+}
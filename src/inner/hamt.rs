@@ -0,0 +1,351 @@
+/*
+ *  This file contains generic hash array mapped trie implementation.
+ *
+ *  HAMT structure is described in:
+ *      P. Bagwell, "Ideal Hash Trees"
+ *      http://infoscience.epfl.ch/record/64398
+ *
+ *  Every branch consumes 5 bits of the 64-bit key hash and keeps only
+ *  existing children, addressed by a 32-bit bitmap. Trie depth is at most
+ *  13, so access, insert and remove take O(1) time in practice. Like the
+ *  treap, trie is converted to persistent one using path-copying approach.
+ */
+
+use std::hash::{hash, Hash};
+use std::num::Int;
+use std::rc::Rc;
+use std::vec::Vec;
+
+const BITS: uint = 5;
+const MASK: u64 = 31;
+
+pub enum Hamt<K, V> {
+    Branch(u32                  /* bitmap   */,
+           Vec<Rc<Hamt<K, V>>>  /* children */,),
+    Leaf(u64                    /* hash     */,
+         K                      /* key      */,
+         V                      /* value    */,),
+    Collision(u64               /* hash     */,
+              Vec<(K, V)>       /* entries  */,)
+}
+
+pub enum Change<'a, K: 'a, V: 'a> {
+    Added(&'a K, &'a V),
+    Removed(&'a K, &'a V),
+    Modified(&'a K, &'a V /* old */, &'a V /* new */),
+}
+
+pub fn hash_key<K: Hash>(key: &K) -> u64 {
+    hash(key)
+}
+
+fn bit_of(hash: u64, shift: uint) -> u32 {
+    1u32 << ((hash >> shift) & MASK) as uint
+}
+
+fn position(bitmap: u32, bit: u32) -> uint {
+    (bitmap & (bit - 1)).count_ones()
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> Hamt<K, V> {
+    pub fn new_empty() -> Rc<Hamt<K, V>> {
+        Rc::new(Hamt::Branch(0, Vec::new()))
+    }
+
+    fn node_hash(&self) -> u64 {
+        match *self {
+            Hamt::Branch(_, _)       => panic!("branch has no hash"),
+            Hamt::Leaf(hash, _, _)   => hash,
+            Hamt::Collision(hash, _) => hash,
+        }
+    }
+
+    // build the smallest branch which holds both leaves
+    fn join(a: Rc<Hamt<K, V>>, b: Rc<Hamt<K, V>>, shift: uint) -> Rc<Hamt<K, V>> {
+        let bit_a = bit_of(a.node_hash(), shift);
+        let bit_b = bit_of(b.node_hash(), shift);
+        if bit_a == bit_b {
+            Rc::new(Hamt::Branch(bit_a, vec![Hamt::join(a, b, shift + BITS)]))
+        } else if bit_a < bit_b {
+            Rc::new(Hamt::Branch(bit_a | bit_b, vec![a, b]))
+        } else {
+            Rc::new(Hamt::Branch(bit_a | bit_b, vec![b, a]))
+        }
+    }
+
+    pub fn get<'a>(&'a self, hash: u64, key: &K) -> Option<&'a V> {
+        let mut node = self;
+        let mut shift = 0;
+        loop {
+            match *node {
+                Hamt::Branch(bitmap, ref children) => {
+                    let bit = bit_of(hash, shift);
+                    if bitmap & bit == 0 {
+                        return None;
+                    }
+                    node = &*children[position(bitmap, bit)];
+                    shift += BITS;
+                },
+                Hamt::Leaf(h, ref k, ref v) =>
+                    return if h == hash && *k == *key { Some(v) } else { None },
+                Hamt::Collision(h, ref entries) => {
+                    if h != hash {
+                        return None;
+                    }
+                    return entries.iter().find(|&&(ref k, _)| *k == *key).map(|&(_, ref v)| v);
+                }
+            }
+        }
+    }
+
+    // return new node and true if the key was not present before
+    pub fn insert(node: &Rc<Hamt<K, V>>, shift: uint, hash: u64, key: K, value: V) -> (Rc<Hamt<K, V>>, bool) {
+        match **node {
+            Hamt::Branch(bitmap, ref children) => {
+                let bit = bit_of(hash, shift);
+                let pos = position(bitmap, bit);
+                let mut new_children = children.clone();
+                if bitmap & bit == 0 {
+                    new_children.insert(pos, Rc::new(Hamt::Leaf(hash, key, value)));
+                    (Rc::new(Hamt::Branch(bitmap | bit, new_children)), true)
+                } else {
+                    let (child, added) = Hamt::insert(&children[pos], shift + BITS, hash, key, value);
+                    new_children[pos] = child;
+                    (Rc::new(Hamt::Branch(bitmap, new_children)), added)
+                }
+            },
+            Hamt::Leaf(h, ref k, ref v) =>
+                if h != hash {
+                    (Hamt::join(node.clone(), Rc::new(Hamt::Leaf(hash, key, value)), shift), true)
+                } else if *k == key {
+                    (Rc::new(Hamt::Leaf(hash, key, value)), false)
+                } else {
+                    (Rc::new(Hamt::Collision(hash, vec![(k.clone(), v.clone()), (key, value)])), true)
+                },
+            Hamt::Collision(h, ref entries) =>
+                if h != hash {
+                    (Hamt::join(node.clone(), Rc::new(Hamt::Leaf(hash, key, value)), shift), true)
+                } else {
+                    let mut new_entries: Vec<(K, V)> = entries.iter()
+                                                              .filter(|&&(ref k, _)| *k != key)
+                                                              .map(|e| e.clone())
+                                                              .collect();
+                    let added = new_entries.len() == entries.len();
+                    new_entries.push((key, value));
+                    (Rc::new(Hamt::Collision(hash, new_entries)), added)
+                },
+        }
+    }
+
+    // return new node (None if it became empty), or the same node if key is absent
+    pub fn remove(node: &Rc<Hamt<K, V>>, shift: uint, hash: u64, key: &K) -> (Option<Rc<Hamt<K, V>>>, bool) {
+        match **node {
+            Hamt::Branch(bitmap, ref children) => {
+                let bit = bit_of(hash, shift);
+                if bitmap & bit == 0 {
+                    return (Some(node.clone()), false);
+                }
+                let pos = position(bitmap, bit);
+                let (child, removed) = Hamt::remove(&children[pos], shift + BITS, hash, key);
+                if !removed {
+                    return (Some(node.clone()), false);
+                }
+                let mut new_children = children.clone();
+                let new_bitmap = match child {
+                    None => {
+                        new_children.remove(pos);
+                        bitmap & !bit
+                    },
+                    Some(child) => {
+                        new_children[pos] = child;
+                        bitmap
+                    }
+                };
+                let new_node = match new_children.len() {
+                    0 if shift > 0 =>
+                        None,
+                    // a lone leaf is pulled up, so the trie stays as shallow as possible
+                    1 if shift > 0 && !new_children[0].is_branch() =>
+                        Some(new_children[0].clone()),
+                    _ =>
+                        Some(Rc::new(Hamt::Branch(new_bitmap, new_children))),
+                };
+                (new_node, true)
+            },
+            Hamt::Leaf(h, ref k, _) =>
+                if h == hash && *k == *key {
+                    (None, true)
+                } else {
+                    (Some(node.clone()), false)
+                },
+            Hamt::Collision(h, ref entries) => {
+                if h != hash {
+                    return (Some(node.clone()), false);
+                }
+                let rest: Vec<(K, V)> = entries.iter()
+                                               .filter(|&&(ref k, _)| *k != *key)
+                                               .map(|e| e.clone())
+                                               .collect();
+                if rest.len() == entries.len() {
+                    (Some(node.clone()), false)
+                } else if rest.len() == 1 {
+                    let (k, v) = rest.into_iter().next().unwrap();
+                    (Some(Rc::new(Hamt::Leaf(hash, k, v))), true)
+                } else {
+                    (Some(Rc::new(Hamt::Collision(hash, rest))), true)
+                }
+            }
+        }
+    }
+
+    fn is_branch(&self) -> bool {
+        match *self {
+            Hamt::Branch(_, _) => true,
+            _                  => false,
+        }
+    }
+
+    pub fn iter<'a>(&'a self) -> HamtIterator<'a, K, V> {
+        HamtIterator{stack: vec![self], entries: None}
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone + PartialEq> Hamt<K, V> {
+    // append the difference between two subtrees, shared subtrees are skipped
+    pub fn diff<'a>(old: &'a Rc<Hamt<K, V>>, new: &'a Rc<Hamt<K, V>>, out: &mut Vec<Change<'a, K, V>>) {
+        if &**old as *const Hamt<K, V> == &**new as *const Hamt<K, V> {
+            return;
+        }
+        match (&**old, &**new) {
+            (&Hamt::Branch(old_bitmap, ref old_children), &Hamt::Branch(new_bitmap, ref new_children)) => {
+                for i in range(0u, 32) {
+                    let bit = 1u32 << i;
+                    let in_old = old_bitmap & bit != 0;
+                    let in_new = new_bitmap & bit != 0;
+                    if in_old && in_new {
+                        Hamt::diff(&old_children[position(old_bitmap, bit)],
+                                   &new_children[position(new_bitmap, bit)],
+                                   out);
+                    } else if in_old {
+                        for (k, v) in old_children[position(old_bitmap, bit)].iter() {
+                            out.push(Change::Removed(k, v));
+                        }
+                    } else if in_new {
+                        for (k, v) in new_children[position(new_bitmap, bit)].iter() {
+                            out.push(Change::Added(k, v));
+                        }
+                    }
+                }
+            },
+            (old_node, new_node) => {
+                let old_entries: Vec<(&'a K, &'a V)> = old_node.iter().collect();
+                let new_entries: Vec<(&'a K, &'a V)> = new_node.iter().collect();
+                for &(k, v) in old_entries.iter() {
+                    match new_entries.iter().find(|&&(nk, _)| *nk == *k) {
+                        None                              => out.push(Change::Removed(k, v)),
+                        Some(&(_, nv)) if *nv != *v       => out.push(Change::Modified(k, v, nv)),
+                        Some(_)                           => {},
+                    }
+                }
+                for &(k, v) in new_entries.iter() {
+                    if !old_entries.iter().any(|&(ok, _)| *ok == *k) {
+                        out.push(Change::Added(k, v));
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub struct HamtIterator<'a, K: 'a, V: 'a> {
+    stack:   Vec<&'a Hamt<K, V>>,
+    entries: Option<(&'a Vec<(K, V)>, uint)>,
+}
+
+impl<'a, K: 'a, V: 'a> Iterator<(&'a K, &'a V)> for HamtIterator<'a, K, V> {
+    fn next(&mut self) -> Option<(&'a K, &'a V)> {
+        loop {
+            match self.entries {
+                Some((entries, i)) if i < entries.len() => {
+                    self.entries = Some((entries, i + 1));
+                    let (ref k, ref v) = entries[i];
+                    return Some((k, v));
+                },
+                _ => self.entries = None,
+            }
+            let node = match self.stack.pop() {
+                None       => return None,
+                Some(node) => node,
+            };
+            match *node {
+                Hamt::Branch(_, ref children) =>
+                    for child in children.iter().rev() {
+                        self.stack.push(&**child);
+                    },
+                Hamt::Leaf(_, ref k, ref v) =>
+                    return Some((k, v)),
+                Hamt::Collision(_, ref entries) =>
+                    self.entries = Some((entries, 0)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+fn insert_all(root: &Rc<Hamt<u64, int>>, keys: &[u64]) -> Rc<Hamt<u64, int>> {
+    keys.iter().fold(root.clone(), |node, &k| Hamt::insert(&node, 0, k, k, k as int).0)
+}
+
+#[test]
+fn hamt_insert_get_test() {
+    // use keys as their own hashes to get deep and colliding paths
+    let keys = [0u64, 1, 32, 33, 1024, 1 << 40, (1 << 40) + 1, 7];
+    let root = insert_all(&Hamt::new_empty(), &keys);
+    for &k in keys.iter() {
+        assert_eq!(root.get(k, &k), Some(&(k as int)));
+    }
+    assert_eq!(root.get(2, &2), None);
+    assert_eq!(root.iter().count(), keys.len());
+}
+
+#[test]
+fn hamt_collision_test() {
+    let root = Hamt::<&str, int>::new_empty();
+    let (root, _) = Hamt::insert(&root, 0, 42, "a", 1);
+    let (root, added_b) = Hamt::insert(&root, 0, 42, "b", 2);
+    let (root, added_a) = Hamt::insert(&root, 0, 42, "a", 3);
+    assert!(added_b);
+    assert!(!added_a);
+    assert_eq!(root.get(42, &"a"), Some(&3));
+    assert_eq!(root.get(42, &"b"), Some(&2));
+
+    let (root, removed) = Hamt::remove(&root, 0, 42, &"a");
+    let root = root.unwrap();
+    assert!(removed);
+    assert_eq!(root.get(42, &"a"), None);
+    assert_eq!(root.get(42, &"b"), Some(&2));
+}
+
+#[test]
+fn hamt_remove_diff_test() {
+    let keys = [3u64, 35, 67, 1 << 20, 99];
+    let old = insert_all(&Hamt::new_empty(), &keys);
+    let (new, removed) = Hamt::remove(&old, 0, 35, &35);
+    let new = new.unwrap();
+    let (new, _) = Hamt::insert(&new, 0, 3, 3, 300);
+    let (new, _) = Hamt::insert(&new, 0, 5, 5, 5);
+    assert!(removed);
+    assert_eq!(new.get(35, &35), None);
+    assert_eq!(old.get(35, &35), Some(&35));
+
+    let mut changes = Vec::new();
+    Hamt::diff(&old, &new, &mut changes);
+    assert_eq!(changes.len(), 3u);
+    for change in changes.iter() {
+        match *change {
+            Change::Added(k, v)       => assert_eq!((*k, *v), (5, 5)),
+            Change::Removed(k, v)     => assert_eq!((*k, *v), (35, 35)),
+            Change::Modified(k, a, b) => assert_eq!((*k, *a, *b), (3, 3, 300)),
+        }
+    }
+}
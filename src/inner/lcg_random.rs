@@ -6,6 +6,7 @@
 
 pub trait LCG {
     fn new() -> Self;
+    fn with_seed(seed: u64) -> Self;
     fn next(&mut self) -> int;
 }
 
@@ -18,6 +19,10 @@ impl LCG for CoolLCG {
         CoolLCG{x: 1807}
     }
 
+    fn with_seed(seed: u64) -> CoolLCG {
+        CoolLCG{x: seed % 0x80000000u64}
+    }
+
     fn next(&mut self) -> int {
         let a = 1103515245u64;
         let c = 12345u64;
@@ -35,6 +40,9 @@ fn cool_lcg() {
     let one = rnd.next();
     let two = rnd.next();
     assert!(one != two);
+
+    let mut same: CoolLCG = LCG::with_seed(1807);
+    assert_eq!(same.next(), one);
 }
 
 pub struct DebugLCG {
@@ -46,6 +54,10 @@ impl LCG for DebugLCG {
         DebugLCG{x: 0}
     }
 
+    fn with_seed(seed: u64) -> DebugLCG {
+        DebugLCG{x: seed}
+    }
+
     fn next(&mut self) -> int {
         self.x += 1;
         self.x as int
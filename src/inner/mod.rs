@@ -1,6 +1,16 @@
+pub mod ancestry;
+pub mod bit_tree;
+pub mod cons_list;
 pub mod fat_node;
+pub mod finger_tree;
+pub mod hamt;
 pub mod kuchevo;
 pub mod lcg_random;
+pub mod pers_array;
 pub mod persistent;
+pub mod radix_tree;
 pub mod revision_tree;
+pub mod rrb_tree;
+pub mod rope;
+pub mod segment_tree;
 pub mod versioned_fat_node;
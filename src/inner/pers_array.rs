@@ -0,0 +1,114 @@
+/*
+ *  This file contains generic persistent array implementation.
+ *
+ *  Array uses Baker's rerooting trick as described in:
+ *      S. Conchon, J.-C. Filliatre, "A Persistent Union-Find Data Structure"
+ *      https://www.lri.fr/~filliatr/ftp/publis/puf-wml07.pdf
+ *
+ *  Only one version owns the real vector, every other version is a chain
+ *  of diffs leading to it. Access to the owner takes O(1) time; access to
+ *  another version first moves the vector there, inverting diffs on the
+ *  way, so backtracking costs time proportional to the distance only.
+ */
+
+use std::cell::RefCell;
+use std::mem;
+use std::rc::Rc;
+use std::vec::Vec;
+
+enum Data<T> {
+    Arr(Vec<T>),
+    Diff(uint /* index */, T /* value */, PArray<T> /* next version */),
+}
+
+pub struct PArray<T> {
+    data: Rc<RefCell<Data<T>>>,
+}
+
+impl<T: Clone> PArray<T> {
+    pub fn from_vec(values: Vec<T>) -> PArray<T> {
+        PArray{data: Rc::new(RefCell::new(Data::Arr(values)))}
+    }
+
+    fn take_vec(cell: &Rc<RefCell<Data<T>>>) -> Vec<T> {
+        match mem::replace(&mut *cell.borrow_mut(), Data::Arr(Vec::new())) {
+            Data::Arr(values) => values,
+            Data::Diff(_, _, _) => panic!("version does not own the array"),
+        }
+    }
+
+    // move the real vector to this version
+    fn reroot(&self) {
+        let mut path = vec![self.data.clone()];
+        loop {
+            let next = match *path[path.len() - 1].borrow() {
+                Data::Arr(_)               => None,
+                Data::Diff(_, _, ref next) => Some(next.data.clone()),
+            };
+            match next {
+                None       => break,
+                Some(node) => path.push(node),
+            }
+        }
+
+        for k in range(1, path.len()).rev() {
+            let (index, value) = match mem::replace(&mut *path[k - 1].borrow_mut(), Data::Arr(Vec::new())) {
+                Data::Diff(index, value, _) => (index, value),
+                Data::Arr(_)                => panic!("diff chain is broken"),
+            };
+            let mut values = PArray::take_vec(&path[k]);
+            let old = mem::replace(&mut values[index], value);
+            *path[k - 1].borrow_mut() = Data::Arr(values);
+            *path[k].borrow_mut() = Data::Diff(index, old, PArray{data: path[k - 1].clone()});
+        }
+    }
+
+    pub fn len(&self) -> uint {
+        self.reroot();
+        match *self.data.borrow() {
+            Data::Arr(ref values) => values.len(),
+            Data::Diff(_, _, _)   => panic!("reroot failed"),
+        }
+    }
+
+    pub fn get(&self, index: uint) -> T {
+        self.reroot();
+        match *self.data.borrow() {
+            Data::Arr(ref values) => values[index].clone(),
+            Data::Diff(_, _, _)   => panic!("reroot failed"),
+        }
+    }
+
+    // return new version, this one stays valid
+    pub fn set(&self, index: uint, value: T) -> PArray<T> {
+        self.reroot();
+        let mut values = PArray::take_vec(&self.data);
+        let old = mem::replace(&mut values[index], value);
+        let result = PArray::from_vec(values);
+        *self.data.borrow_mut() = Data::Diff(index, old, result.clone());
+        result
+    }
+}
+
+impl<T> Clone for PArray<T> {
+    fn clone(&self) -> PArray<T> {
+        PArray{data: self.data.clone()}
+    }
+}
+
+#[test]
+fn pers_array_versions_test() {
+    let a = PArray::from_vec(vec![0i, 1, 2, 3]);
+    let b = a.set(1, 10);
+    let c = b.set(2, 20);
+    let d = b.set(3, 30);
+
+    assert_eq!(c.get(1), 10);
+    assert_eq!(c.get(2), 20);
+    assert_eq!(a.get(1), 1);
+    assert_eq!(d.get(3), 30);
+    assert_eq!(d.get(2), 2);
+    assert_eq!(c.get(3), 3);
+    assert_eq!(b.get(2), 2);
+    assert_eq!(a.len(), 4u);
+}
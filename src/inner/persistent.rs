@@ -1,4 +1,6 @@
-pub type Revision = int;
+use error::{PersError, PersResult};
+
+pub type Revision = i64;
 
 pub trait Persistent<T> {
     fn get_by_revision(&self, revision : Revision) -> T;
@@ -9,6 +11,19 @@ pub trait Persistent<T> {
     }
 }
 
+// checks are kept apart, so implementors of Persistent and Recall need no new methods
+pub trait TryPersistent<T>: Persistent<T> {
+    fn contains_revision(&self, revision : Revision) -> bool;
+
+    fn try_get_by_revision(&self, revision : Revision) -> PersResult<T> {
+        if self.contains_revision(revision) {
+            Ok(self.get_by_revision(revision))
+        } else {
+            Err(PersError::UnknownRevision(revision))
+        }
+    }
+}
+
 pub trait Recall {
     fn undo(&mut self) -> Revision;
     fn redo(&mut self) -> Revision;
@@ -32,4 +47,48 @@ pub trait Recall {
     }
 }
 
+pub trait TryRecall: Recall {
+    fn can_undo(&self) -> bool;
+    fn can_redo(&self) -> bool;
+    // revision at the current position of the history line
+    fn line_revision(&self) -> Revision;
+
+    fn try_undo(&mut self) -> PersResult<Revision> {
+        if self.can_undo() { Ok(self.undo()) } else { Err(PersError::NothingToUndo) }
+    }
+
+    fn try_redo(&mut self) -> PersResult<Revision> {
+        if self.can_redo() { Ok(self.redo()) } else { Err(PersError::NothingToRedo) }
+    }
+
+    // zero times returns the current revision; on error the position in history is not changed
+    fn try_undo_ntimes(&mut self, times: int) -> PersResult<Revision> {
+        let mut revision = self.line_revision();
+        for done in range(0, times) {
+            if !self.can_undo() {
+                for _ in range(0, done) {
+                    self.redo();
+                }
+                return Err(PersError::NothingToUndo);
+            }
+            revision = self.undo();
+        }
+        Ok(revision)
+    }
+
+    fn try_redo_ntimes(&mut self, times: int) -> PersResult<Revision> {
+        let mut revision = self.line_revision();
+        for done in range(0, times) {
+            if !self.can_redo() {
+                for _ in range(0, done) {
+                    self.undo();
+                }
+                return Err(PersError::NothingToRedo);
+            }
+            revision = self.redo();
+        }
+        Ok(revision)
+    }
+}
+
 pub trait FullyPersistent<T>: Persistent<T> + Recall { }
@@ -0,0 +1,258 @@
+/*
+ *  This file contains generic radix tree (compressed trie) implementation.
+ *
+ *  Every edge is labelled with a non-empty byte string and children of a
+ *  node are sorted by the first byte of their labels, so walking a key of
+ *  length L takes O(L) time regardless of the number of keys. Every node
+ *  caches the number of values in its subtree for prefix counting.
+ *  Tree is converted to persistent one using path-copying approach.
+ */
+
+use std::rc::Rc;
+use std::vec::Vec;
+
+pub struct RadixNode<V> {
+    value:    Option<V>,
+    count:    uint, // number of values in the subtree
+    children: Vec<(Vec<u8> /* label */, Rc<RadixNode<V>>)>,
+}
+
+fn common_prefix(a: &[u8], b: &[u8]) -> uint {
+    a.iter().zip(b.iter()).take_while(|&(x, y)| x == y).count()
+}
+
+impl<V: Clone> RadixNode<V> {
+    pub fn new_empty() -> Rc<RadixNode<V>> {
+        RadixNode::make(None, Vec::new())
+    }
+
+    fn make(value: Option<V>, children: Vec<(Vec<u8>, Rc<RadixNode<V>>)>) -> Rc<RadixNode<V>> {
+        let own = if value.is_some() { 1 } else { 0 };
+        let count = children.iter().fold(own, |acc, &(_, ref child)| acc + child.count);
+        Rc::new(RadixNode{value: value, count: count, children: children})
+    }
+
+    pub fn count(&self) -> uint {
+        self.count
+    }
+
+    // position of the child which label starts with byte, or where it should be inserted
+    fn find(&self, byte: u8) -> (bool, uint) {
+        let pos = self.children.iter().take_while(|&&(ref label, _)| label[0] < byte).count();
+        let found = pos < self.children.len() && self.children[pos].0[0] == byte;
+        (found, pos)
+    }
+
+    pub fn get<'a>(&'a self, key: &[u8]) -> Option<&'a V> {
+        match self.find_prefix(key) {
+            Some((node, ref path)) if path.len() == key.len() => node.value.as_ref(),
+            _                                                 => None,
+        }
+    }
+
+    // return new node and true if the key was not present before
+    pub fn insert(node: &Rc<RadixNode<V>>, key: &[u8], value: V) -> (Rc<RadixNode<V>>, bool) {
+        if key.is_empty() {
+            let added = node.value.is_none();
+            return (RadixNode::make(Some(value), node.children.clone()), added);
+        }
+
+        let mut children = node.children.clone();
+        let (found, pos) = node.find(key[0]);
+        let added = if !found {
+            children.insert(pos, (key.to_vec(), RadixNode::make(Some(value), Vec::new())));
+            true
+        } else {
+            let (ref label, ref child) = node.children[pos];
+            let common = common_prefix(label.as_slice(), key);
+            if common == label.len() {
+                let (new_child, added) = RadixNode::insert(child, key.slice_from(common), value);
+                children[pos] = (label.clone(), new_child);
+                added
+            } else {
+                // split the edge: label[0; common) leads to a new node
+                let middle = RadixNode::make(None, vec![(label.slice_from(common).to_vec(), child.clone())]);
+                let (new_middle, added) = RadixNode::insert(&middle, key.slice_from(common), value);
+                children[pos] = (label.slice_to(common).to_vec(), new_middle);
+                added
+            }
+        };
+        (RadixNode::make(node.value.clone(), children), added)
+    }
+
+    // return new node and true if the key was present
+    pub fn remove(node: &Rc<RadixNode<V>>, key: &[u8]) -> (Rc<RadixNode<V>>, bool) {
+        if key.is_empty() {
+            return match node.value {
+                None    => (node.clone(), false),
+                Some(_) => (RadixNode::make(None, node.children.clone()), true),
+            };
+        }
+
+        let (found, pos) = node.find(key[0]);
+        if !found {
+            return (node.clone(), false);
+        }
+        let (ref label, ref child) = node.children[pos];
+        if !key.starts_with(label.as_slice()) {
+            return (node.clone(), false);
+        }
+        let (new_child, removed) = RadixNode::remove(child, key.slice_from(label.len()));
+        if !removed {
+            return (node.clone(), false);
+        }
+
+        // keep the tree compressed: no empty leaves and no valueless nodes with one child
+        let mut children = node.children.clone();
+        if new_child.value.is_none() && new_child.children.is_empty() {
+            children.remove(pos);
+        } else if new_child.value.is_none() && new_child.children.len() == 1 {
+            let (ref grand_label, ref grand_child) = new_child.children[0];
+            let mut merged = label.clone();
+            merged.push_all(grand_label.as_slice());
+            children[pos] = (merged, grand_child.clone());
+        } else {
+            children[pos] = (label.clone(), new_child.clone());
+        }
+        (RadixNode::make(node.value.clone(), children), true)
+    }
+
+    // node which subtree holds exactly the keys with the prefix, and the key of that node
+    pub fn find_prefix<'a>(&'a self, prefix: &[u8]) -> Option<(&'a RadixNode<V>, Vec<u8>)> {
+        let mut node = self;
+        let mut rest = prefix;
+        let mut path = Vec::new();
+        loop {
+            if rest.is_empty() {
+                return Some((node, path));
+            }
+            let (found, pos) = node.find(rest[0]);
+            if !found {
+                return None;
+            }
+            let (ref label, ref child) = node.children[pos];
+            let common = common_prefix(label.as_slice(), rest);
+            if common < rest.len() && common < label.len() {
+                return None;
+            }
+            path.push_all(label.as_slice());
+            if common == rest.len() {
+                return Some((&**child, path));
+            }
+            rest = rest.slice_from(common);
+            node = &**child;
+        }
+    }
+
+    // length and value of the longest key which is a prefix of the given one
+    pub fn longest_prefix<'a>(&'a self, key: &[u8]) -> Option<(uint, &'a V)> {
+        let mut node = self;
+        let mut depth = 0;
+        let mut best = None;
+        loop {
+            match node.value {
+                Some(ref value) => best = Some((depth, value)),
+                None            => {},
+            }
+            if depth == key.len() {
+                return best;
+            }
+            let (found, pos) = node.find(key[depth]);
+            if !found {
+                return best;
+            }
+            let (ref label, ref child) = node.children[pos];
+            if !key.slice_from(depth).starts_with(label.as_slice()) {
+                return best;
+            }
+            depth += label.len();
+            node = &**child;
+        }
+    }
+
+    // iterator over the subtree, key is the key of this node
+    pub fn iter<'a>(&'a self, key: Vec<u8>) -> RadixIterator<'a, V> {
+        RadixIterator{stack: vec![(self, key)]}
+    }
+}
+
+pub struct RadixIterator<'a, V: 'a> {
+    stack: Vec<(&'a RadixNode<V>, Vec<u8>)>,
+}
+
+impl<'a, V: 'a> RadixIterator<'a, V> {
+    pub fn empty() -> RadixIterator<'a, V> {
+        RadixIterator{stack: Vec::new()}
+    }
+}
+
+impl<'a, V: 'a> Iterator<(Vec<u8>, &'a V)> for RadixIterator<'a, V> {
+    fn next(&mut self) -> Option<(Vec<u8>, &'a V)> {
+        loop {
+            let (node, key) = match self.stack.pop() {
+                None       => return None,
+                Some(item) => item,
+            };
+            for &(ref label, ref child) in node.children.iter().rev() {
+                let mut child_key = key.clone();
+                child_key.push_all(label.as_slice());
+                self.stack.push((&**child, child_key));
+            }
+            match node.value {
+                Some(ref value) => return Some((key, value)),
+                None            => continue,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+fn build(keys: &[&str]) -> Rc<RadixNode<uint>> {
+    keys.iter().enumerate().fold(RadixNode::new_empty(), |node, (i, key)| RadixNode::insert(&node, key.as_bytes(), i).0)
+}
+
+#[test]
+fn radix_insert_get_test() {
+    let root = build(&["romane", "romanus", "romulus", "rubens", "ruber", "rubicon", "rubicundus", "rom"]);
+    assert_eq!(root.count(), 8u);
+    assert_eq!(root.get(b"rubens"), Some(&3));
+    assert_eq!(root.get(b"rom"), Some(&7));
+    assert_eq!(root.get(b"ro"), None);
+    assert_eq!(root.get(b"romanes"), None);
+
+    let keys: Vec<Vec<u8>> = root.iter(Vec::new()).map(|(k, _)| k).collect();
+    let mut sorted = keys.clone();
+    sorted.sort();
+    assert_eq!(keys, sorted);
+}
+
+#[test]
+fn radix_remove_test() {
+    let root = build(&["test", "team", "toast"]);
+    let (removed, found) = RadixNode::remove(&root, b"team");
+    let (same, not_found) = RadixNode::remove(&removed, b"te");
+    assert!(found);
+    assert!(!not_found);
+    assert_eq!(same.count(), 2u);
+    assert_eq!(removed.get(b"team"), None);
+    assert_eq!(removed.get(b"test"), Some(&0));
+    assert_eq!(root.get(b"team"), Some(&1));
+
+    // "test" is now the only key under "t" + "e..." and the edge is merged back
+    let (_, path) = removed.find_prefix(b"te").unwrap();
+    assert_eq!(path.as_slice(), b"test");
+}
+
+#[test]
+fn radix_prefix_test() {
+    let root = build(&["a", "ab", "abc", "abd", "b"]);
+    let (node, path) = root.find_prefix(b"ab").unwrap();
+    assert_eq!(node.count(), 3u);
+    assert_eq!(node.iter(path).map(|(k, _)| k).collect::<Vec<Vec<u8>>>(),
+               vec![b"ab".to_vec(), b"abc".to_vec(), b"abd".to_vec()]);
+    assert!(root.find_prefix(b"ac").is_none());
+
+    assert_eq!(root.longest_prefix(b"abcde"), Some((3, &2)));
+    assert_eq!(root.longest_prefix(b"abx"), Some((2, &1)));
+    assert_eq!(root.longest_prefix(b"x"), None);
+}
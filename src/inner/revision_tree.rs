@@ -1,29 +1,74 @@
 use std::collections::BTreeMap as TreeMap;
 use std::collections::HashMap;
 use std::vec::Vec;
+use inner::ancestry::Ancestry;
 use inner::persistent::Revision;
 use inner::lcg_random::*;
 use std::cell::*;
+use std::sync::atomic::{AtomicUint, INIT_ATOMIC_UINT, Ordering};
 
 pub type Field<A> = HashMap<Revision, A>;
 
-pub struct Tree {
-    generator: CoolLCG,
+// revision id is tag | epoch | generator value, so ids of different trees differ,
+// and after the generator period wraps ids continue in the next epoch;
+// the tag is the serial number of the tree wrapped to TAG_BITS, a tree remembers the
+// serials behind the tags it knows and refuses to import revisions of a colliding tree
+const VALUE_BITS: uint = 31;
+const EPOCH_BITS: uint = 8;
+const TAG_BITS:   uint = 24;
+
+static NEXT_SERIAL: AtomicUint = INIT_ATOMIC_UINT;
+
+fn compose(tag: u64, epoch: u64, value: int) -> Revision {
+    let value = value as u64 & ((1 << VALUE_BITS) - 1);
+    ((tag << EPOCH_BITS | epoch) << VALUE_BITS | value) as Revision
+}
+
+// tag of the tree which minted the revision
+pub fn tag_of(r: Revision) -> u64 {
+    r as u64 >> (EPOCH_BITS + VALUE_BITS)
+}
+
+pub struct Tree<G> {
+    generator: G,
+    tag:       u64,
+    tags:      HashMap<u64, uint>, // tag -> serial of the tree which minted it
+    epoch:     u64,
     root:      Revision,
     parent:    TreeMap<Revision, Revision>,
-    history:   Vec<Revision>
+    graft:     HashMap<Revision, Revision>, // merge revision -> merged revision
+    history:   Vec<Revision>,
+    ancestry:  Ancestry
 }
 
-impl Tree {
-    pub fn new() -> Tree {
-        let mut generator: CoolLCG = LCG::new();
-        let root = generator.next();
+impl<G: LCG> Tree<G> {
+    pub fn new() -> Tree<G> {
+        Tree::with_generator(LCG::new())
+    }
+
+    // e.g. DebugLCG for sequential ids or a seeded generator for reproducible ones
+    pub fn with_generator(generator: G) -> Tree<G> {
+        Tree::with_serial(generator, NEXT_SERIAL.fetch_add(1, Ordering::SeqCst))
+    }
+
+    fn with_serial(mut generator: G, serial: uint) -> Tree<G> {
+        let tag = serial as u64 & ((1 << TAG_BITS) - 1);
+        let root = compose(tag, 0, generator.next());
         let history = vec![root];
+        let mut ancestry = Ancestry::new();
+        ancestry.insert(root, None);
+        let mut tags = HashMap::new();
+        tags.insert(tag, serial);
         Tree {
             generator: generator,
+            tag:       tag,
+            tags:      tags,
+            epoch:     0,
             root:      root,
             parent:    TreeMap::new(),
-            history:   history
+            graft:     HashMap::new(),
+            history:   history,
+            ancestry:  ancestry
         }
     }
 
@@ -33,12 +78,23 @@ impl Tree {
         self.parent[r]
     }
 
+    // None for the root and for roots of imported trees
+    pub fn parent_of(&self, r: Revision) -> Option<Revision> {
+        self.parent.get(&r).map(|&p| p)
+    }
+
+    // the second parent of a merge revision
+    pub fn graft(&self, r: Revision) -> Option<Revision> {
+        self.graft.get(&r).map(|&q| q)
+    }
+
+    #[cfg(test)]
     fn ancestors(&self, r: Revision) -> Vec<Revision> {
-        assert!(r == self.root || self.parent.contains_key(&r));
+        assert!(self.contains(r));
 
         let mut b = vec!(r);
         let mut c = r;
-        while c != self.root {
+        while self.parent.contains_key(&c) {
             c = self.parent(c);
             b.push(c);
         }
@@ -49,6 +105,30 @@ impl Tree {
         self.root
     }
 
+    fn next_id(&mut self) -> Revision {
+        loop {
+            let c = compose(self.tag, self.epoch, self.generator.next());
+            if !self.contains(c) {
+                return c;
+            }
+            self.epoch += 1;
+            assert!(self.epoch < 1 << EPOCH_BITS, "revision ids are exhausted");
+        }
+    }
+
+    pub fn tag(&self) -> u64 {
+        self.tag
+    }
+
+    // true if the revision was minted by this tree
+    pub fn owns(&self, r: Revision) -> bool {
+        tag_of(r) == self.tag
+    }
+
+    pub fn contains(&self, r: Revision) -> bool {
+        self.ancestry.contains(r)
+    }
+
     pub fn revision(&self, i: uint) -> Revision {
         self.history[i]
     }
@@ -58,37 +138,129 @@ impl Tree {
     }
 
     pub fn fork(&mut self, p: Revision) -> Revision {
-        assert!(p == self.root || self.parent.contains_key(&p));
+        assert!(self.contains(p));
 
-        let c = self.generator.next();
+        let c = self.next_id();
         self.parent.insert(c, p);
         self.history.push(c);
+        self.ancestry.insert(c, Some(p));
+        c
+    }
+
+    // new revision forked from p which also continues the history of q;
+    // field lookups keep to the parent, callers decide when to follow the graft
+    pub fn merge(&mut self, p: Revision, q: Revision) -> Revision {
+        assert!(self.contains(q));
+
+        let c = self.fork(p);
+        self.graft.insert(c, q);
         c
     }
 
+    // adds the revision of another tree with its ancestors and merged revisions,
+    // ids are kept as tags make them unique; returns the revisions added
+    //
+    // panics if a tag of the added revisions belongs to another tree here
+    pub fn import<H: LCG>(&mut self, other: &Tree<H>, r: Revision) -> Vec<Revision> {
+        assert!(other.contains(r));
+
+        let mut added = Vec::new();
+        let mut pending = vec![r];
+        loop {
+            let c = match pending.pop() {
+                None    => break,
+                Some(c) => c,
+            };
+            if self.contains(c) {
+                continue;
+            }
+            let p = other.parent_of(c);
+            let q = other.graft(c);
+            let missing: Vec<Revision> = p.iter().chain(q.iter())
+                .filter(|&&x| !self.contains(x)).map(|&x| x).collect();
+            if !missing.is_empty() {
+                pending.push(c);
+                pending.push_all(missing.as_slice());
+                continue;
+            }
+
+            match p {
+                None    => {},
+                Some(p) => { self.parent.insert(c, p); },
+            }
+            match q {
+                None    => {},
+                Some(q) => { self.graft.insert(c, q); },
+            }
+            self.adopt_tag(tag_of(c), other.tags[tag_of(c)]);
+            self.ancestry.insert(c, p);
+            self.history.push(c);
+            added.push(c);
+        }
+        added
+    }
+
+    fn adopt_tag(&mut self, tag: u64, serial: uint) {
+        match self.tags.get(&tag) {
+            None                    => {},
+            Some(&s) if s == serial => return,
+            Some(_)                 => panic!("revision tree tags collide"),
+        }
+        self.tags.insert(tag, serial);
+    }
+
+    pub fn depth(&self, r: Revision) -> uint {
+        self.ancestry.depth(r)
+    }
+
+    // true if a is r or an ancestor of r
+    pub fn is_ancestor(&self, a: Revision, r: Revision) -> bool {
+        self.ancestry.is_ancestor(a, r)
+    }
+
+    pub fn lca(&self, a: Revision, b: Revision) -> Revision {
+        self.ancestry.lca(a, b).unwrap()
+    }
+
+    pub fn ancestor_at_depth(&self, r: Revision, d: uint) -> Revision {
+        self.ancestry.ancestor_at_depth(r, d)
+    }
+
     pub fn _get<'a, A>(&self, m: Ref<'a, Field<A>>, r: Revision) -> Option<&'a A> {
-        for c in self.ancestors(r).iter() {
-            match m.get(c) {
-                None         => continue,
+        assert!(self.contains(r));
+
+        let mut c = r;
+        loop {
+            match m.get(&c) {
                 Some(&ref v) => return Some(v),
+                None         => {},
             }
+            c = match self.parent.get(&c) {
+                None     => return None,
+                Some(&p) => p,
+            };
         }
-        None
     }
 
     pub fn get<'a, A>(&self, m: &Field<A>, r: Revision) -> Option<&'a A> {
-        for c in self.ancestors(r).iter() {
-            match m.get(c) {
-                None         => continue,
+        assert!(self.contains(r));
+
+        let mut c = r;
+        loop {
+            match m.get(&c) {
                 Some(&ref v) => return Some(v),
+                None         => {},
             }
+            c = match self.parent.get(&c) {
+                None     => return None,
+                Some(&p) => p,
+            };
         }
-        None
     }
 }
 
 #[cfg(test)]
-fn magic_tree() -> Tree {
+fn magic_tree() -> Tree<CoolLCG> {
     let mut t = Tree::new();
 
     //           6
@@ -118,6 +290,22 @@ fn revision_tree() {
     assert_eq!(t.ancestors(h[6]), vec![h[6], h[3], h[1], h[0]]);
 }
 
+#[test]
+fn ancestor_queries() {
+    let t = magic_tree();
+    let h = &t.history;
+
+    assert_eq!(t.depth(h[0]), 0u);
+    assert_eq!(t.depth(h[7]), 4u);
+    assert_eq!(t.ancestor_at_depth(h[7], 1), h[1]);
+    assert_eq!(t.ancestor_at_depth(h[7], 4), h[7]);
+    assert!(t.is_ancestor(h[2], h[7]));
+    assert!(!t.is_ancestor(h[3], h[7]));
+    assert_eq!(t.lca(h[7], h[5]), h[1]);
+    assert_eq!(t.lca(h[5], h[6]), h[3]);
+    assert_eq!(t.lca(h[4], h[7]), h[4]);
+}
+
 #[test]
 fn fat_field() {
     let t = magic_tree();
@@ -168,3 +356,79 @@ fn multiple_fat_fields() {
     assert_eq!(t.get(&b, h[5]), Some(&"5"));
     assert_eq!(t.get(&c, h[5]), Some(&"3"));
 }
+
+#[test]
+fn revision_ids() {
+    let mut a: Tree<DebugLCG> = Tree::new();
+    let mut b: Tree<DebugLCG> = Tree::with_generator(LCG::with_seed(0));
+    let (ra, rb) = (a.root(), b.root());
+    let (ca, cb) = (a.fork(ra), b.fork(rb));
+
+    // same local ids, different trees
+    assert_eq!(ca & 0xffff, 2);
+    assert_eq!(cb & 0xffff, 2);
+    assert!(ca != cb);
+    assert!(a.owns(ca) && !a.owns(cb));
+    assert_eq!(tag_of(cb), b.tag());
+    assert!(ca > 0 && cb > 0);
+}
+
+#[test]
+fn revision_id_collision() {
+    let mut t: Tree<DebugLCG> = Tree::new();
+    let mut r = t.root();
+    for _ in range(0u, 6) {
+        // the generator wraps and repeats ids
+        t.generator = LCG::with_seed(0);
+        r = t.fork(r);
+    }
+    let h = t.history.clone();
+    for i in range(0u, h.len()) {
+        for j in range(0u, i) {
+            assert!(h[i] != h[j]);
+        }
+    }
+    assert_eq!(t.epoch, 3);
+    assert_eq!(t.depth(r), 6u);
+}
+
+#[test]
+fn merge_import() {
+    let mut a: Tree<CoolLCG> = Tree::new();
+    let mut b: Tree<CoolLCG> = Tree::new();
+    let (ra, rb) = (a.root(), b.root());
+    let x = b.fork(rb);
+    let y = b.fork(rb);
+    let m = b.merge(x, y);
+
+    let added = a.import(&b, m);
+    assert_eq!(added.len(), 4u);
+    assert!(a.contains(rb) && a.contains(y) && a.contains(m));
+    assert_eq!(a.parent_of(m), Some(x));
+    assert_eq!(a.graft(m), Some(y));
+    assert_eq!(a.parent_of(rb), None);
+    assert_eq!(a.ancestors(m), vec![m, x, rb]);
+    assert!(a.import(&b, y).is_empty());
+
+    let n = a.merge(ra, m);
+    assert_eq!(a.ancestors(n), vec![n, ra]);
+    assert_eq!(a.graft(n), Some(m));
+    assert_eq!(a.graft(ra), None);
+
+    // tags wrap instead of running out
+    let mut c: Tree<CoolLCG> = Tree::with_serial(LCG::new(), 5);
+    let d: Tree<CoolLCG> = Tree::with_serial(LCG::new(), 6 + (1 << TAG_BITS));
+    let rd = d.root();
+    assert_eq!(d.tag(), 6);
+    assert_eq!(c.import(&d, rd), vec![rd]);
+    assert!(!c.owns(rd));
+}
+
+#[test]
+#[should_fail]
+fn tag_collision() {
+    let mut a: Tree<CoolLCG> = Tree::with_serial(LCG::new(), 3);
+    let b: Tree<CoolLCG> = Tree::with_serial(LCG::new(), 3 + (1 << TAG_BITS));
+    let rb = b.root();
+    a.import(&b, rb);
+}
@@ -0,0 +1,305 @@
+/*
+ *  This file contains persistent rope implementation.
+ *
+ *  Rope is an AVL tree with string chunks in the leaves. Every branch
+ *  caches byte, char and newline counts of its subtree, so conversion
+ *  between byte, char and line offsets takes O(lg(N)) time. Editing is
+ *  done with split and join, which copy only O(lg(N)) nodes on the path,
+ *  and small neighbouring chunks are glued together on join.
+ */
+
+use std::cmp::max;
+use std::rc::Rc;
+use std::string::String;
+
+pub const CHUNK: uint = 64; // maximal length of a chunk in bytes, unless a char is longer
+
+pub struct Metrics {
+    pub bytes:    uint,
+    pub chars:    uint,
+    pub newlines: uint,
+}
+
+impl Copy for Metrics {}
+
+impl Metrics {
+    fn of(s: &str) -> Metrics {
+        Metrics{bytes:    s.len(),
+                chars:    s.chars().count(),
+                newlines: s.bytes().filter(|&b| b == b'\n').count()}
+    }
+
+    fn add(&self, other: &Metrics) -> Metrics {
+        Metrics{bytes:    self.bytes + other.bytes,
+                chars:    self.chars + other.chars,
+                newlines: self.newlines + other.newlines}
+    }
+}
+
+pub enum Rope {
+    Leaf(Metrics, String),
+    Node(Metrics        /* metrics */,
+         uint           /* height  */,
+         Rc<Rope>       /* left    */,
+         Rc<Rope>       /* right   */,)
+}
+
+impl Rope {
+    pub fn new_empty() -> Rc<Rope> {
+        Rope::leaf(String::new())
+    }
+
+    fn leaf(s: String) -> Rc<Rope> {
+        Rc::new(Rope::Leaf(Metrics::of(s.as_slice()), s))
+    }
+
+    // balanced rope from the string cut into chunks
+    pub fn from_str(s: &str) -> Rc<Rope> {
+        if s.len() <= CHUNK {
+            return Rope::leaf(s.to_string());
+        }
+        let mut mid = s.len() / 2;
+        while !s.is_char_boundary(mid) {
+            mid += 1;
+        }
+        Rope::join(&Rope::from_str(s.slice_to(mid)), &Rope::from_str(s.slice_from(mid)))
+    }
+
+    pub fn metrics(&self) -> Metrics {
+        match *self {
+            Rope::Leaf(m, _)       => m,
+            Rope::Node(m, _, _, _) => m,
+        }
+    }
+
+    fn height(&self) -> uint {
+        match *self {
+            Rope::Leaf(_, _)       => 0,
+            Rope::Node(_, h, _, _) => h,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.metrics().bytes == 0
+    }
+
+    fn node(left: Rc<Rope>, right: Rc<Rope>) -> Rc<Rope> {
+        let metrics = left.metrics().add(&right.metrics());
+        let height = max(left.height(), right.height()) + 1;
+        Rc::new(Rope::Node(metrics, height, left, right))
+    }
+
+    fn children(node: &Rc<Rope>) -> (Rc<Rope>, Rc<Rope>) {
+        match **node {
+            Rope::Node(_, _, ref left, ref right) => (left.clone(), right.clone()),
+            Rope::Leaf(_, _)                      => panic!("leaf has no children"),
+        }
+    }
+
+    // node from subtrees which heights differ by at most two
+    fn balance(left: Rc<Rope>, right: Rc<Rope>) -> Rc<Rope> {
+        if left.height() > right.height() + 1 {
+            let (ll, lr) = Rope::children(&left);
+            if ll.height() >= lr.height() {
+                Rope::node(ll, Rope::node(lr, right))
+            } else {
+                let (lrl, lrr) = Rope::children(&lr);
+                Rope::node(Rope::node(ll, lrl), Rope::node(lrr, right))
+            }
+        } else if right.height() > left.height() + 1 {
+            let (rl, rr) = Rope::children(&right);
+            if rr.height() >= rl.height() {
+                Rope::node(Rope::node(left, rl), rr)
+            } else {
+                let (rll, rlr) = Rope::children(&rl);
+                Rope::node(Rope::node(left, rll), Rope::node(rlr, rr))
+            }
+        } else {
+            Rope::node(left, right)
+        }
+    }
+
+    pub fn join(left: &Rc<Rope>, right: &Rc<Rope>) -> Rc<Rope> {
+        if left.is_empty() {
+            return right.clone();
+        }
+        if right.is_empty() {
+            return left.clone();
+        }
+        match (&**left, &**right) {
+            (&Rope::Leaf(_, ref a), &Rope::Leaf(_, ref b)) if a.len() + b.len() <= CHUNK => {
+                let mut glued = a.clone();
+                glued.push_str(b.as_slice());
+                return Rope::leaf(glued);
+            },
+            _ => {}
+        }
+
+        if left.height() > right.height() + 1 {
+            let (ll, lr) = Rope::children(left);
+            Rope::balance(ll, Rope::join(&lr, right))
+        } else if right.height() > left.height() + 1 {
+            let (rl, rr) = Rope::children(right);
+            Rope::balance(Rope::join(left, &rl), rr)
+        } else {
+            Rope::node(left.clone(), right.clone())
+        }
+    }
+
+    // return ropes with bytes [0; at) and [at; len)
+    pub fn split(node: &Rc<Rope>, at: uint) -> (Rc<Rope>, Rc<Rope>) {
+        match **node {
+            Rope::Leaf(_, ref s) => {
+                assert!(s.as_slice().is_char_boundary(at));
+                (Rope::leaf(s.as_slice().slice_to(at).to_string()),
+                 Rope::leaf(s.as_slice().slice_from(at).to_string()))
+            },
+            Rope::Node(_, _, ref left, ref right) => {
+                let left_bytes = left.metrics().bytes;
+                if at < left_bytes {
+                    let (a, b) = Rope::split(left, at);
+                    (a, Rope::join(&b, right))
+                } else if at > left_bytes {
+                    let (a, b) = Rope::split(right, at - left_bytes);
+                    (Rope::join(left, &a), b)
+                } else {
+                    (left.clone(), right.clone())
+                }
+            }
+        }
+    }
+
+    pub fn char_to_byte(&self, char_offset: uint) -> uint {
+        match *self {
+            Rope::Leaf(_, ref s) =>
+                match s.as_slice().char_indices().nth(char_offset) {
+                    Some((byte, _)) => byte,
+                    None            => s.len(),
+                },
+            Rope::Node(_, _, ref left, ref right) => {
+                let m = left.metrics();
+                if char_offset < m.chars {
+                    left.char_to_byte(char_offset)
+                } else {
+                    m.bytes + right.char_to_byte(char_offset - m.chars)
+                }
+            }
+        }
+    }
+
+    // number of chars, bytes or newlines in [0; byte_offset)
+    pub fn prefix_metrics(&self, byte_offset: uint) -> Metrics {
+        match *self {
+            Rope::Leaf(_, ref s) =>
+                Metrics::of(s.as_slice().slice_to(byte_offset)),
+            Rope::Node(_, _, ref left, ref right) => {
+                let m = left.metrics();
+                if byte_offset <= m.bytes {
+                    left.prefix_metrics(byte_offset)
+                } else {
+                    m.add(&right.prefix_metrics(byte_offset - m.bytes))
+                }
+            }
+        }
+    }
+
+    // true if the byte offset is not inside a char
+    pub fn is_char_boundary(&self, byte_offset: uint) -> bool {
+        match *self {
+            Rope::Leaf(_, ref s) =>
+                s.as_slice().is_char_boundary(byte_offset),
+            Rope::Node(_, _, ref left, ref right) => {
+                let m = left.metrics();
+                if byte_offset <= m.bytes {
+                    left.is_char_boundary(byte_offset)
+                } else {
+                    right.is_char_boundary(byte_offset - m.bytes)
+                }
+            }
+        }
+    }
+
+    // byte offset of the k-th (from zero) newline
+    pub fn newline_to_byte(&self, k: uint) -> uint {
+        match *self {
+            Rope::Leaf(_, ref s) =>
+                s.as_slice().bytes().enumerate()
+                                    .filter(|&(_, b)| b == b'\n')
+                                    .nth(k)
+                                    .map(|(i, _)| i)
+                                    .unwrap(),
+            Rope::Node(_, _, ref left, ref right) => {
+                let m = left.metrics();
+                if k < m.newlines {
+                    left.newline_to_byte(k)
+                } else {
+                    m.bytes + right.newline_to_byte(k - m.newlines)
+                }
+            }
+        }
+    }
+
+    pub fn chunks<'a>(&'a self) -> Chunks<'a> {
+        Chunks{stack: vec![self]}
+    }
+}
+
+pub struct Chunks<'a> {
+    stack: Vec<&'a Rope>,
+}
+
+impl<'a> Iterator<&'a str> for Chunks<'a> {
+    fn next(&mut self) -> Option<&'a str> {
+        loop {
+            match self.stack.pop() {
+                None => return None,
+                Some(&Rope::Leaf(_, ref s)) =>
+                    if s.len() > 0 {
+                        return Some(s.as_slice());
+                    },
+                Some(&Rope::Node(_, _, ref left, ref right)) => {
+                    self.stack.push(&**right);
+                    self.stack.push(&**left);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+fn collect(rope: &Rc<Rope>) -> String {
+    let mut s = String::new();
+    for chunk in rope.chunks() {
+        s.push_str(chunk);
+    }
+    s
+}
+
+#[test]
+fn rope_split_join_test() {
+    let mut text = String::new();
+    for _ in range(0u, 50) {
+        text.push_str("persistent ");
+    }
+    let rope = Rope::from_str(text.as_slice());
+    assert_eq!(rope.metrics().bytes, text.len());
+    assert_eq!(collect(&rope), text);
+
+    for at in range(0u, text.len() + 1) {
+        let (a, b) = Rope::split(&rope, at);
+        assert_eq!(collect(&a).as_slice(), text.as_slice().slice_to(at));
+        assert_eq!(collect(&b).as_slice(), text.as_slice().slice_from(at));
+        assert_eq!(collect(&Rope::join(&a, &b)), text);
+    }
+}
+
+#[test]
+fn rope_offsets_test() {
+    let text = "первая строка\nsecond line\n\nчетвёртая";
+    let rope = Rope::from_str(text);
+    assert_eq!(rope.metrics().chars, text.chars().count());
+    assert_eq!(rope.metrics().newlines, 3u);
+    assert_eq!(rope.char_to_byte(7), "первая ".len());
+    assert_eq!(rope.newline_to_byte(0), "первая строка".len());
+    assert_eq!(rope.prefix_metrics(text.len()).newlines, 3u);
+}
@@ -0,0 +1,498 @@
+/*
+ *  This file contains relaxed radix balanced tree (RRB-tree) implementation.
+ *
+ *  Elements are stored in leaves of at most WIDTH elements, and every branch
+ *  has at most WIDTH children and keeps cumulative sizes of its children.
+ *  Lookup guesses the child by radix (i >> BITS * height) and scans forward,
+ *  which is exact for regular subtrees and takes a few extra steps for
+ *  relaxed ones, so indexing takes O(log32(N)) time. Concatenation merges two
+ *  trees along the touching spines and repacks a level only when it has more
+ *  than EXTRA nodes over the optimum, split cuts the tree along one path, so
+ *  both take O(lg(N)) time. The last elements are kept in a tail buffer which
+ *  makes push amortized O(1). Tree is persistent by path-copying.
+ */
+
+use std::cmp::min;
+use std::rc::Rc;
+use std::vec::Vec;
+
+pub const BITS:  uint = 5;
+pub const WIDTH: uint = 1 << BITS; // maximal number of children or elements in a node
+const EXTRA:     uint = 2; // allowed number of nodes over the optimum at one level
+
+pub enum RrbNode<T> {
+    Leaf(Vec<T>),
+    Branch(uint                /* height, leaves have zero */,
+           Vec<uint>           /* cumulative sizes of children */,
+           Vec<Rc<RrbNode<T>>> /* children */,)
+}
+
+// index of the child which contains the element and index of the element in it
+fn position(height: uint, sizes: &[uint], id: uint) -> (uint, uint) {
+    let mut slot = min(id >> (BITS * height), sizes.len() - 1);
+    while sizes[slot] <= id {
+        slot += 1;
+    }
+    let offset = if slot == 0 { id } else { id - sizes[slot - 1] };
+    (slot, offset)
+}
+
+impl<T: Clone> RrbNode<T> {
+    fn leaf(items: Vec<T>) -> Rc<RrbNode<T>> {
+        Rc::new(RrbNode::Leaf(items))
+    }
+
+    fn branch(height: uint, children: Vec<Rc<RrbNode<T>>>) -> Rc<RrbNode<T>> {
+        let mut sizes = Vec::with_capacity(children.len());
+        let mut total = 0;
+        for child in children.iter() {
+            total += child.len();
+            sizes.push(total);
+        }
+        Rc::new(RrbNode::Branch(height, sizes, children))
+    }
+
+    pub fn len(&self) -> uint {
+        match *self {
+            RrbNode::Leaf(ref items)             => items.len(),
+            RrbNode::Branch(_, ref sizes, _)     => *sizes.last().unwrap(),
+        }
+    }
+
+    fn height(&self) -> uint {
+        match *self {
+            RrbNode::Leaf(_)            => 0,
+            RrbNode::Branch(h, _, _)    => h,
+        }
+    }
+
+    // number of elements of a leaf or children of a branch
+    fn width(&self) -> uint {
+        match *self {
+            RrbNode::Leaf(ref items)             => items.len(),
+            RrbNode::Branch(_, _, ref children)  => children.len(),
+        }
+    }
+
+    fn children<'a>(&'a self) -> &'a [Rc<RrbNode<T>>] {
+        match *self {
+            RrbNode::Branch(_, _, ref children)  => children.as_slice(),
+            RrbNode::Leaf(_)                     => panic!("leaf has no children"),
+        }
+    }
+
+    pub fn lookup<'a>(&'a self, id: uint) -> &'a T {
+        let mut node = self;
+        let mut id = id;
+        loop {
+            match *node {
+                RrbNode::Leaf(ref items) => return &items[id],
+                RrbNode::Branch(height, ref sizes, ref children) => {
+                    let (slot, offset) = position(height, sizes.as_slice(), id);
+                    node = &*children[slot];
+                    id = offset;
+                }
+            }
+        }
+    }
+
+    pub fn update(node: &Rc<RrbNode<T>>, id: uint, value: T) -> Rc<RrbNode<T>> {
+        match **node {
+            RrbNode::Leaf(ref items) => {
+                let mut items = items.clone();
+                items[id] = value;
+                RrbNode::leaf(items)
+            },
+            RrbNode::Branch(height, ref sizes, ref children) => {
+                let (slot, offset) = position(height, sizes.as_slice(), id);
+                let mut children = children.clone();
+                children[slot] = RrbNode::update(&children[slot], offset, value);
+                Rc::new(RrbNode::Branch(height, sizes.clone(), children))
+            }
+        }
+    }
+
+    // rightmost leaf of the tree
+    fn last_leaf<'a>(&'a self) -> &'a [T] {
+        let mut node = self;
+        loop {
+            match *node {
+                RrbNode::Leaf(ref items)            => return items.as_slice(),
+                RrbNode::Branch(_, _, ref children) => node = &**children.last().unwrap(),
+            }
+        }
+    }
+
+    // return nodes with elements [0; at) and [at; len), None for an empty part
+    pub fn split(node: &Rc<RrbNode<T>>, at: uint) -> (Option<Rc<RrbNode<T>>>, Option<Rc<RrbNode<T>>>) {
+        if at == 0 {
+            return (None, Some(node.clone()));
+        }
+        if at >= node.len() {
+            return (Some(node.clone()), None);
+        }
+        match **node {
+            RrbNode::Leaf(ref items) =>
+                (Some(RrbNode::leaf(items.slice_to(at).to_vec())),
+                 Some(RrbNode::leaf(items.slice_from(at).to_vec()))),
+            RrbNode::Branch(height, ref sizes, ref children) => {
+                let (slot, offset) = position(height, sizes.as_slice(), at);
+                let (left, right) = RrbNode::split(&children[slot], offset);
+
+                let mut left_children = children.slice_to(slot).to_vec();
+                left_children.extend(left.into_iter());
+                let mut right_children: Vec<Rc<RrbNode<T>>> = right.into_iter().collect();
+                right_children.push_all(children.slice_from(slot + 1));
+
+                (Some(RrbNode::branch(height, left_children)),
+                 Some(RrbNode::branch(height, right_children)))
+            }
+        }
+    }
+
+    // nodes at height h holding all given children of height h - 1, merged if there are too many
+    fn rebalance(height: uint, children: Vec<Rc<RrbNode<T>>>) -> Vec<Rc<RrbNode<T>>> {
+        let slots = children.iter().fold(0, |acc, child| acc + child.width());
+        let optimal = (slots + WIDTH - 1) / WIDTH;
+
+        let children = if children.len() <= optimal + EXTRA {
+            children
+        } else if height == 1 {
+            let mut items = Vec::with_capacity(slots);
+            for child in children.iter() {
+                match **child {
+                    RrbNode::Leaf(ref elements) => items.push_all(elements.as_slice()),
+                    RrbNode::Branch(_, _, _)    => panic!("branch at the leaf level"),
+                }
+            }
+            items.as_slice().chunks(WIDTH).map(|chunk| RrbNode::leaf(chunk.to_vec())).collect()
+        } else {
+            let mut grandchildren = Vec::with_capacity(slots);
+            for child in children.iter() {
+                grandchildren.push_all(child.children());
+            }
+            grandchildren.as_slice().chunks(WIDTH).map(|chunk| RrbNode::branch(height - 1, chunk.to_vec())).collect()
+        };
+
+        children.as_slice().chunks(WIDTH).map(|chunk| RrbNode::branch(height, chunk.to_vec())).collect()
+    }
+
+    // one or two nodes at height max(left.height, right.height) holding both trees
+    fn concat_nodes(left: &Rc<RrbNode<T>>, right: &Rc<RrbNode<T>>) -> Vec<Rc<RrbNode<T>>> {
+        let (hl, hr) = (left.height(), right.height());
+        if hl == 0 && hr == 0 {
+            if left.len() + right.len() <= WIDTH {
+                let mut items = Vec::with_capacity(left.len() + right.len());
+                items.push_all(left.last_leaf());
+                items.push_all(right.last_leaf());
+                return vec![RrbNode::leaf(items)];
+            }
+            return vec![left.clone(), right.clone()];
+        }
+
+        let mut children = Vec::new();
+        let middle = if hl > hr {
+            let lc = left.children();
+            children.push_all(lc.slice_to(lc.len() - 1));
+            RrbNode::concat_nodes(lc.last().unwrap(), right)
+        } else if hl < hr {
+            RrbNode::concat_nodes(left, &right.children()[0])
+        } else {
+            let lc = left.children();
+            children.push_all(lc.slice_to(lc.len() - 1));
+            RrbNode::concat_nodes(lc.last().unwrap(), &right.children()[0])
+        };
+        children.push_all(middle.as_slice());
+        if hl <= hr {
+            children.push_all(right.children().slice_from(1));
+        }
+        RrbNode::rebalance(if hl > hr { hl } else { hr }, children)
+    }
+
+    pub fn concat(left: &Rc<RrbNode<T>>, right: &Rc<RrbNode<T>>) -> Rc<RrbNode<T>> {
+        let nodes = RrbNode::concat_nodes(left, right);
+        if nodes.len() == 1 {
+            nodes[0].clone()
+        } else {
+            RrbNode::branch(nodes[0].height() + 1, nodes)
+        }
+    }
+}
+
+/*
+ *  RRB-tree with a tail buffer. The tree holds elements [0; len - tail.len())
+ *  and the tail holds the rest, at most WIDTH elements.
+ */
+pub struct RrbTree<T> {
+    root: Option<Rc<RrbNode<T>>>,
+    tail: Rc<Vec<T>>,
+    len:  uint,
+}
+
+impl<T: Clone> RrbTree<T> {
+    pub fn new_empty() -> RrbTree<T> {
+        RrbTree{root: None, tail: Rc::new(Vec::new()), len: 0}
+    }
+
+    pub fn from_vec(items: Vec<T>) -> RrbTree<T> {
+        let mut tree = RrbTree::new_empty();
+        for item in items.into_iter() {
+            tree = tree.push(item);
+        }
+        tree
+    }
+
+    pub fn len(&self) -> uint {
+        self.len
+    }
+
+    fn tree_len(&self) -> uint {
+        self.len - self.tail.len()
+    }
+
+    // strip branches with a single child off the top of the tree
+    fn normalize(root: Option<Rc<RrbNode<T>>>) -> Option<Rc<RrbNode<T>>> {
+        let mut root = match root {
+            None       => return None,
+            Some(node) => node,
+        };
+        loop {
+            let child = match *root {
+                RrbNode::Branch(_, _, ref children) if children.len() == 1 => children[0].clone(),
+                _                                                           => return Some(root),
+            };
+            root = child;
+        }
+    }
+
+    fn join_roots(left: &Option<Rc<RrbNode<T>>>, right: &Option<Rc<RrbNode<T>>>) -> Option<Rc<RrbNode<T>>> {
+        match (left, right) {
+            (&None, _)                 => right.clone(),
+            (_, &None)                 => left.clone(),
+            (&Some(ref l), &Some(ref r)) => Some(RrbNode::concat(l, r)),
+        }
+    }
+
+    // tree without tail buffer
+    fn flush(&self) -> Option<Rc<RrbNode<T>>> {
+        if self.tail.is_empty() {
+            return self.root.clone();
+        }
+        RrbTree::join_roots(&self.root, &Some(RrbNode::leaf((*self.tail).clone())))
+    }
+
+    pub fn lookup<'a>(&'a self, id: uint) -> &'a T {
+        assert!(id < self.len);
+
+        let tree_len = self.tree_len();
+        if id >= tree_len {
+            &self.tail[id - tree_len]
+        } else {
+            self.root.as_ref().unwrap().lookup(id)
+        }
+    }
+
+    pub fn update(&self, id: uint, value: T) -> RrbTree<T> {
+        assert!(id < self.len);
+
+        let tree_len = self.tree_len();
+        if id >= tree_len {
+            let mut tail = (*self.tail).clone();
+            tail[id - tree_len] = value;
+            RrbTree{root: self.root.clone(), tail: Rc::new(tail), len: self.len}
+        } else {
+            let root = RrbNode::update(self.root.as_ref().unwrap(), id, value);
+            RrbTree{root: Some(root), tail: self.tail.clone(), len: self.len}
+        }
+    }
+
+    pub fn push(&self, value: T) -> RrbTree<T> {
+        if self.tail.len() < WIDTH {
+            let mut tail = Vec::with_capacity(self.tail.len() + 1);
+            tail.push_all(self.tail.as_slice());
+            tail.push(value);
+            RrbTree{root: self.root.clone(), tail: Rc::new(tail), len: self.len + 1}
+        } else {
+            RrbTree{root: self.flush(), tail: Rc::new(vec![value]), len: self.len + 1}
+        }
+    }
+
+    pub fn pop(&self) -> RrbTree<T> {
+        assert!(self.len > 0);
+
+        if self.tail.is_empty() {
+            // move the last leaf of the tree to the tail
+            let tree_len = self.tree_len();
+            let last = self.root.as_ref().unwrap().last_leaf().to_vec();
+            let (rest, _) = RrbNode::split(self.root.as_ref().unwrap(), tree_len - last.len());
+            let moved = RrbTree{root: RrbTree::normalize(rest), tail: Rc::new(last), len: self.len};
+            return moved.pop();
+        }
+        let tail = self.tail.slice_to(self.tail.len() - 1).to_vec();
+        RrbTree{root: self.root.clone(), tail: Rc::new(tail), len: self.len - 1}
+    }
+
+    pub fn concat(&self, other: &RrbTree<T>) -> RrbTree<T> {
+        if other.len == 0 {
+            return self.clone();
+        }
+        RrbTree{root: RrbTree::join_roots(&self.flush(), &other.root),
+                tail: other.tail.clone(),
+                len:  self.len + other.len}
+    }
+
+    // return trees with elements [0; at) and [at; len)
+    pub fn split(&self, at: uint) -> (RrbTree<T>, RrbTree<T>) {
+        assert!(at <= self.len);
+
+        let tree_len = self.tree_len();
+        if at >= tree_len {
+            let offset = at - tree_len;
+            (RrbTree{root: self.root.clone(),
+                     tail: Rc::new(self.tail.slice_to(offset).to_vec()),
+                     len:  at},
+             RrbTree{root: None,
+                     tail: Rc::new(self.tail.slice_from(offset).to_vec()),
+                     len:  self.len - at})
+        } else {
+            let (left, right) = RrbNode::split(self.root.as_ref().unwrap(), at);
+            (RrbTree{root: RrbTree::normalize(left), tail: Rc::new(Vec::new()), len: at},
+             RrbTree{root: RrbTree::normalize(right), tail: self.tail.clone(), len: self.len - at})
+        }
+    }
+
+    pub fn slice(&self, from: uint, to: uint) -> RrbTree<T> {
+        assert!(from <= to && to <= self.len);
+
+        let (head, _) = self.split(to);
+        let (_, middle) = head.split(from);
+        middle
+    }
+
+    // iterator which starts at the element
+    pub fn iter_from<'a>(&'a self, id: uint) -> RrbIterator<'a, T> {
+        assert!(id <= self.len);
+
+        let tree_len = self.tree_len();
+        if id >= tree_len {
+            return RrbIterator{stack:   Vec::new(),
+                               current: self.tail.slice_from(id - tree_len),
+                               tail:    None};
+        }
+
+        // right siblings of the path to the element are visited after it
+        let mut stack = Vec::new();
+        let mut node = &**self.root.as_ref().unwrap();
+        let mut id = id;
+        loop {
+            match *node {
+                RrbNode::Leaf(ref items) =>
+                    return RrbIterator{stack:   stack,
+                                       current: items.slice_from(id),
+                                       tail:    Some(self.tail.as_slice())},
+                RrbNode::Branch(height, ref sizes, ref children) => {
+                    let (slot, offset) = position(height, sizes.as_slice(), id);
+                    for child in children.slice_from(slot + 1).iter().rev() {
+                        stack.push(&**child);
+                    }
+                    node = &*children[slot];
+                    id = offset;
+                }
+            }
+        }
+    }
+
+    pub fn iter<'a>(&'a self) -> RrbIterator<'a, T> {
+        RrbIterator{stack:   self.root.iter().map(|node| &**node).collect(),
+                    current: &[],
+                    tail:    Some(self.tail.as_slice())}
+    }
+}
+
+impl<T: Clone> Clone for RrbTree<T> {
+    fn clone(&self) -> RrbTree<T> {
+        RrbTree{root: self.root.clone(), tail: self.tail.clone(), len: self.len}
+    }
+}
+
+pub struct RrbIterator<'a, T: 'a> {
+    stack:   Vec<&'a RrbNode<T>>,
+    current: &'a [T],
+    tail:    Option<&'a [T]>,
+}
+
+impl<'a, T: 'a> Iterator<&'a T> for RrbIterator<'a, T> {
+    fn next(&mut self) -> Option<&'a T> {
+        loop {
+            if !self.current.is_empty() {
+                let item = &self.current[0];
+                self.current = self.current.slice_from(1);
+                return Some(item);
+            }
+            match self.stack.pop() {
+                Some(&RrbNode::Leaf(ref items)) => self.current = items.as_slice(),
+                Some(&RrbNode::Branch(_, _, ref children)) =>
+                    for child in children.iter().rev() {
+                        self.stack.push(&**child);
+                    },
+                None => match self.tail.take() {
+                    Some(tail) => self.current = tail,
+                    None       => return None,
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+fn check(tree: &RrbTree<uint>, expected: &[uint]) {
+    assert_eq!(tree.len(), expected.len());
+    for (i, v) in expected.iter().enumerate() {
+        assert_eq!(tree.lookup(i), v);
+    }
+    assert_eq!(tree.iter().map(|&v| v).collect::<Vec<uint>>().as_slice(), expected);
+}
+
+#[test]
+fn rrb_push_pop_test() {
+    let items: Vec<uint> = range(0u, 5000).collect();
+    let mut tree = RrbTree::new_empty();
+    for &i in items.iter() {
+        tree = tree.push(i);
+    }
+    check(&tree, items.as_slice());
+
+    let updated = tree.update(1234, 0).update(4999, 1);
+    assert_eq!(*updated.lookup(1234), 0u);
+    assert_eq!(*updated.lookup(4999), 1u);
+    assert_eq!(*tree.lookup(1234), 1234u);
+
+    for _ in range(0u, 3000) {
+        tree = tree.pop();
+    }
+    check(&tree, items.slice_to(2000));
+}
+
+#[test]
+fn rrb_concat_split_test() {
+    let mut expected = Vec::new();
+    let mut tree = RrbTree::new_empty();
+    for n in range(1u, 60) {
+        let part: Vec<uint> = range(0u, n * 7).map(|i| i + n * 1000).collect();
+        expected.push_all(part.as_slice());
+        tree = tree.concat(&RrbTree::from_vec(part));
+    }
+    check(&tree, expected.as_slice());
+
+    for &at in [0u, 1, 31, 32, 33, 1000, 5000, expected.len() - 1, expected.len()].iter() {
+        let (left, right) = tree.split(at);
+        check(&left, expected.slice_to(at));
+        check(&right, expected.slice_from(at));
+        check(&right.concat(&left).slice(expected.len() - at, expected.len()), expected.slice_to(at));
+    }
+
+    for &from in [0u, 5, 32, 100, 4000, expected.len()].iter() {
+        let rest: Vec<uint> = tree.iter_from(from).map(|&v| v).collect();
+        assert_eq!(rest.as_slice(), expected.slice_from(from));
+    }
+}
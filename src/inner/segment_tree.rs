@@ -0,0 +1,224 @@
+/*
+ *  This file contains generic segment tree implementation.
+ *
+ *  Every node stores the aggregate of its segment under a user monoid and
+ *  a lazy tag (add or assign) which is not yet pushed to its children.
+ *  Updates push tags down by copying children, so the tree is persistent
+ *  and every update creates O(lg(N)) new nodes. Queries never modify the
+ *  tree: they carry ancestors' tags down instead of pushing them.
+ */
+
+use std::cmp;
+use std::num;
+use std::num::Int;
+use std::rc::Rc;
+
+pub trait Monoid<T> {
+    fn identity(&self) -> T;
+    fn combine(&self, left: &T, right: &T) -> T;
+    // aggregate of len elements equal to value
+    fn repeat(&self, value: &T, len: uint) -> T;
+}
+
+pub struct Sum;
+pub struct Min;
+pub struct Max;
+
+impl Copy for Sum {}
+impl Copy for Min {}
+impl Copy for Max {}
+
+impl<T: Int> Monoid<T> for Sum {
+    fn identity(&self) -> T { Int::zero() }
+    fn combine(&self, left: &T, right: &T) -> T { *left + *right }
+    fn repeat(&self, value: &T, len: uint) -> T { *value * num::cast(len).unwrap() }
+}
+
+impl<T: Int> Monoid<T> for Min {
+    fn identity(&self) -> T { Int::max_value() }
+    fn combine(&self, left: &T, right: &T) -> T { cmp::min(*left, *right) }
+    fn repeat(&self, value: &T, _len: uint) -> T { *value }
+}
+
+impl<T: Int> Monoid<T> for Max {
+    fn identity(&self) -> T { Int::min_value() }
+    fn combine(&self, left: &T, right: &T) -> T { cmp::max(*left, *right) }
+    fn repeat(&self, value: &T, _len: uint) -> T { *value }
+}
+
+#[deriving(Clone)]
+pub enum Lazy<T> {
+    Keep,
+    Add(T),
+    Assign(T),
+}
+
+impl<T: Clone + Add<T, T>> Lazy<T> {
+    // tag equal to applying self and then newer
+    fn then(&self, newer: &Lazy<T>) -> Lazy<T> {
+        match (self, newer) {
+            (_, &Lazy::Keep)                          => self.clone(),
+            (_, &Lazy::Assign(ref v))                 => Lazy::Assign(v.clone()),
+            (&Lazy::Keep, &Lazy::Add(ref d))          => Lazy::Add(d.clone()),
+            (&Lazy::Add(ref a), &Lazy::Add(ref d))    => Lazy::Add(a.clone() + d.clone()),
+            (&Lazy::Assign(ref v), &Lazy::Add(ref d)) => Lazy::Assign(v.clone() + d.clone()),
+        }
+    }
+
+    fn apply<M: Monoid<T>>(&self, monoid: &M, aggregate: &T, len: uint) -> T {
+        match *self {
+            Lazy::Keep          => aggregate.clone(),
+            Lazy::Add(ref d)    => aggregate.clone() + monoid.repeat(d, len),
+            Lazy::Assign(ref v) => monoid.repeat(v, len),
+        }
+    }
+}
+
+pub struct SegNode<T> {
+    value: T,
+    lazy:  Lazy<T>,
+    left:  Option<Rc<SegNode<T>>>,
+    right: Option<Rc<SegNode<T>>>,
+}
+
+// Node covers segment [0; len) of its own, left child covers [0; len / 2).
+impl<T: Clone + Add<T, T>> SegNode<T> {
+    pub fn build<M: Monoid<T>>(monoid: &M, values: &[T]) -> Rc<SegNode<T>> {
+        assert!(values.len() > 0);
+
+        if values.len() == 1 {
+            return Rc::new(SegNode{value: values[0].clone(), lazy: Lazy::Keep, left: None, right: None});
+        }
+        let mid = values.len() / 2;
+        SegNode::join(monoid,
+                      SegNode::build(monoid, values.slice_to(mid)),
+                      SegNode::build(monoid, values.slice_from(mid)))
+    }
+
+    fn join<M: Monoid<T>>(monoid: &M, left: Rc<SegNode<T>>, right: Rc<SegNode<T>>) -> Rc<SegNode<T>> {
+        Rc::new(SegNode{value: monoid.combine(&left.value, &right.value),
+                        lazy:  Lazy::Keep,
+                        left:  Some(left),
+                        right: Some(right)})
+    }
+
+    fn with_tag<M: Monoid<T>>(&self, monoid: &M, tag: &Lazy<T>, len: uint) -> Rc<SegNode<T>> {
+        Rc::new(SegNode{value: tag.apply(monoid, &self.value, len),
+                        lazy:  self.lazy.then(tag),
+                        left:  self.left.clone(),
+                        right: self.right.clone()})
+    }
+
+    // children with the tag of this node pushed into them
+    fn children<M: Monoid<T>>(&self, monoid: &M, len: uint) -> (Rc<SegNode<T>>, Rc<SegNode<T>>) {
+        let left = self.left.as_ref().unwrap();
+        let right = self.right.as_ref().unwrap();
+        match self.lazy {
+            Lazy::Keep => (left.clone(), right.clone()),
+            _          => (left.with_tag(monoid, &self.lazy, len / 2),
+                           right.with_tag(monoid, &self.lazy, len - len / 2)),
+        }
+    }
+
+    pub fn value(&self) -> &T {
+        &self.value
+    }
+
+    // apply tag to [from; to), return new root
+    pub fn update<M: Monoid<T>>(node: &Rc<SegNode<T>>, monoid: &M, len: uint,
+                                from: uint, to: uint, tag: &Lazy<T>) -> Rc<SegNode<T>> {
+        if from == 0 && to == len {
+            return node.with_tag(monoid, tag, len);
+        }
+        let mid = len / 2;
+        let (left, right) = node.children(monoid, len);
+        let new_left = if from < mid {
+            SegNode::update(&left, monoid, mid, from, cmp::min(to, mid), tag)
+        } else {
+            left
+        };
+        let new_right = if to > mid {
+            SegNode::update(&right, monoid, len - mid, cmp::max(from, mid) - mid, to - mid, tag)
+        } else {
+            right
+        };
+        SegNode::join(monoid, new_left, new_right)
+    }
+
+    // aggregate of non-empty [from; to), pending is the tag of ancestors not applied to node
+    pub fn query<M: Monoid<T>>(&self, monoid: &M, len: uint,
+                               from: uint, to: uint, pending: &Lazy<T>) -> T {
+        if from == 0 && to == len {
+            return pending.apply(monoid, &self.value, len);
+        }
+        let mid = len / 2;
+        let tag = self.lazy.then(pending);
+        let left = if from < mid {
+            Some(self.left.as_ref().unwrap().query(monoid, mid, from, cmp::min(to, mid), &tag))
+        } else {
+            None
+        };
+        let right = if to > mid {
+            Some(self.right.as_ref().unwrap().query(monoid, len - mid, cmp::max(from, mid) - mid, to - mid, &tag))
+        } else {
+            None
+        };
+        match (left, right) {
+            (Some(l), Some(r)) => monoid.combine(&l, &r),
+            (Some(l), None)    => l,
+            (None, Some(r))    => r,
+            (None, None)       => monoid.identity(),
+        }
+    }
+}
+
+impl<T: Int> SegNode<T> {
+    // position p such that sum(newer[0; p]) - sum(older[0; p]) > k first time
+    pub fn kth_between(newer: &SegNode<T>, older: &SegNode<T>, len: uint, k: T,
+                       newer_pending: &Lazy<T>, older_pending: &Lazy<T>) -> uint {
+        if len == 1 {
+            return 0;
+        }
+        let mid = len / 2;
+        let newer_tag = newer.lazy.then(newer_pending);
+        let older_tag = older.lazy.then(older_pending);
+        let newer_left = newer.left.as_ref().unwrap();
+        let older_left = older.left.as_ref().unwrap();
+        let count = newer_tag.apply(&Sum, &newer_left.value, mid) - older_tag.apply(&Sum, &older_left.value, mid);
+        if k < count {
+            SegNode::kth_between(&**newer_left, &**older_left, mid, k, &newer_tag, &older_tag)
+        } else {
+            mid + SegNode::kth_between(&**newer.right.as_ref().unwrap(), &**older.right.as_ref().unwrap(),
+                                       len - mid, k - count, &newer_tag, &older_tag)
+        }
+    }
+}
+
+#[test]
+fn segment_tree_query_update_test() {
+    let values = [5i, 3, 8, 1, 9, 2, 7];
+    let root = SegNode::build(&Sum, &values);
+    assert_eq!(root.query(&Sum, 7, 0, 7, &Lazy::Keep), 35);
+    assert_eq!(root.query(&Sum, 7, 2, 5, &Lazy::Keep), 18);
+
+    let added = SegNode::update(&root, &Sum, 7, 1, 4, &Lazy::Add(10));
+    let assigned = SegNode::update(&added, &Sum, 7, 3, 6, &Lazy::Assign(0));
+    assert_eq!(added.query(&Sum, 7, 0, 7, &Lazy::Keep), 65);
+    assert_eq!(assigned.query(&Sum, 7, 0, 7, &Lazy::Keep), 5 + 13 + 18 + 7);
+    assert_eq!(assigned.query(&Sum, 7, 2, 3, &Lazy::Keep), 18);
+    assert_eq!(root.query(&Sum, 7, 2, 3, &Lazy::Keep), 8);
+}
+
+#[test]
+fn segment_tree_min_max_test() {
+    let values = [5i, 3, 8, 1, 9, 2, 7];
+    let min = SegNode::build(&Min, &values);
+    let max = SegNode::build(&Max, &values);
+    assert_eq!(min.query(&Min, 7, 0, 3, &Lazy::Keep), 3);
+    assert_eq!(max.query(&Max, 7, 3, 7, &Lazy::Keep), 9);
+
+    let min = SegNode::update(&min, &Min, 7, 0, 7, &Lazy::Add(-1));
+    let min = SegNode::update(&min, &Min, 7, 3, 4, &Lazy::Assign(4));
+    assert_eq!(min.query(&Min, 7, 2, 5, &Lazy::Keep), 4);
+    assert_eq!(min.query(&Min, 7, 0, 7, &Lazy::Keep), 1);
+}
@@ -1,26 +1,137 @@
 /*
  *  This file contains generic FatNode implementation for structures, which
  *  supports undo-redo.
+ *
+ *  Version tree keeps the version list: Euler tour of the tree where every
+ *  revision is represented by enter and exit elements, and the subtree of a
+ *  revision lies between them. New revision is always a leaf and is placed
+ *  right after the enter element of its parent. Elements carry integer labels
+ *  which are maintained by the order-maintenance algorithm of Bender et al.:
+ *  when there is no gap for a new label, the smallest sparse enough aligned
+ *  label range around it is spread evenly, which takes amortized O(lg(N)).
+ *
+ *  Fat node stores values at elements of the version list, in list order:
+ *  a value at the enter element of its revision and the value which was in
+ *  effect at the parent revision at the exit element. So the value for a
+ *  revision is the last one not after its enter element, which is found by
+ *  binary search in O(lg(K)) time for K values in the node (Driscoll et al.).
+ *  Every value also keeps the revision which wrote it, so the writer and the
+ *  history of the node along any branch are read without walking the branch.
  */
 
 use std::cell::RefCell as RCell;
 use std::collections::BTreeMap;
 use std::collections::HashMap as HMap;
+use std::num::Float;
 use std::rc::Rc;
+use std::uint;
 use std::vec::Vec;
+use error::{PersError, PersResult};
+use inner::ancestry::Ancestry;
 use inner::persistent::Revision;
 
+const NIL: uint = uint::MAX; // no element
+const LABEL_BITS: uint = 62;
+const DENSITY: f64 = 1.4; // range of 2^i labels is sparse if holds less than (2 / DENSITY)^i elements
+
 pub struct VersionTree {
-    parent: BTreeMap<Revision, Revision>,
+    parent:   BTreeMap<Revision, Revision>,
+    elements: HMap<Revision, (uint, uint)>, // enter and exit elements of the revision
+    ancestry: Ancestry,
+
+    // version list
+    label:    Vec<u64>,
+    prev:     Vec<uint>,
+    next:     Vec<uint>,
+    last:     uint,
 }
 
 impl VersionTree {
     pub fn new(initial_revision: Revision) -> VersionTree {
         assert!(initial_revision > 0);
 
-        let mut p = BTreeMap::new();
-        p.insert(initial_revision, -1);
-        VersionTree{parent: p}
+        let mut tree = VersionTree{parent: BTreeMap::new(),
+                                   elements: HMap::new(),
+                                   ancestry: Ancestry::new(),
+                                   label: vec![0],
+                                   prev: vec![NIL],
+                                   next: vec![NIL],
+                                   last: 0};
+        let exit = tree.insert_after(0);
+        tree.parent.insert(initial_revision, -1);
+        tree.elements.insert(initial_revision, (0, exit));
+        tree.ancestry.insert(initial_revision, None);
+        tree
+    }
+
+    // new element of the version list right after the given one
+    fn insert_after(&mut self, element: uint) -> uint {
+        if self.upper_label(element) - self.label[element] < 2 {
+            self.relabel(element);
+        }
+        let label = self.label[element] + (self.upper_label(element) - self.label[element]) / 2;
+
+        let id = self.label.len();
+        let next = self.next[element];
+        self.label.push(label);
+        self.prev.push(element);
+        self.next.push(next);
+        self.next[element] = id;
+        if next == NIL {
+            self.last = id;
+        } else {
+            self.prev[next] = id;
+        }
+        id
+    }
+
+    fn upper_label(&self, element: uint) -> u64 {
+        match self.next[element] {
+            NIL  => 1u64 << LABEL_BITS,
+            next => self.label[next],
+        }
+    }
+
+    // spread evenly labels of the smallest sparse enough aligned range around the element
+    fn relabel(&mut self, element: uint) {
+        for bits in range(1u, LABEL_BITS + 1) {
+            let base = self.label[element] >> bits << bits;
+            let end = base + (1u64 << bits);
+
+            let mut first = element;
+            while self.prev[first] != NIL && self.label[self.prev[first]] >= base {
+                first = self.prev[first];
+            }
+            let mut count = 0u;
+            let mut cur = first;
+            while cur != NIL && self.label[cur] < end {
+                count += 1;
+                cur = self.next[cur];
+            }
+
+            if ((count + 1) as f64) < (2.0 / DENSITY).powi(bits as i32) {
+                let gap = (1u64 << bits) / (count as u64 + 1);
+                let mut cur = first;
+                for k in range(0u, count) {
+                    self.label[cur] = base + gap * k as u64;
+                    cur = self.next[cur];
+                }
+                return;
+            }
+        }
+        panic!("version list overflow");
+    }
+
+    // enter and exit elements of the revision
+    pub fn elements(&self, revision: Revision) -> (uint, uint) {
+        assert!(self.elements.contains_key(&revision));
+
+        self.elements[revision]
+    }
+
+    // position of the element in the version list, valid until the next insert
+    pub fn label(&self, element: uint) -> u64 {
+        self.label[element]
     }
 
     pub fn parent_revision(&self, revision: Revision) -> Revision {
@@ -30,18 +141,38 @@ impl VersionTree {
         self.parent[revision]
     }
 
+    // revision and all its ancestors, allocated once by the depth
     pub fn parent_branch(&self, revision: Revision) -> Vec<Revision> {
-        let mut branch = vec![revision];
-        loop {
-            let cur_rev = branch[branch.len() - 1];
-            if self.is_initial(cur_rev) {
-                break;
-            }
-            branch.push(self.parent_revision(cur_rev));
+        let mut branch = Vec::with_capacity(self.depth(revision) + 1);
+        let mut cur_rev = revision;
+        branch.push(cur_rev);
+        while !self.is_initial(cur_rev) {
+            cur_rev = self.parent[cur_rev];
+            branch.push(cur_rev);
         }
         branch
     }
 
+    pub fn depth(&self, revision: Revision) -> uint {
+        self.ancestry.depth(revision)
+    }
+
+    // true if a is r or an ancestor of r, by the version list in O(1)
+    pub fn is_ancestor(&self, a: Revision, r: Revision) -> bool {
+        let (enter_a, exit_a) = self.elements(a);
+        let (enter_r, _) = self.elements(r);
+        self.label[enter_a] <= self.label[enter_r] && self.label[enter_r] < self.label[exit_a]
+    }
+
+    // None if the revisions have different initial revisions
+    pub fn lca(&self, a: Revision, b: Revision) -> Option<Revision> {
+        self.ancestry.lca(a, b)
+    }
+
+    pub fn ancestor_at_depth(&self, revision: Revision, depth: uint) -> Revision {
+        self.ancestry.ancestor_at_depth(revision, depth)
+    }
+
     pub fn is_initial(&self, revision: Revision) -> bool {
         assert!(revision > 0);
         assert!(self.parent.contains_key(&revision));
@@ -49,12 +180,32 @@ impl VersionTree {
         self.parent[revision] == -1
     }
 
+    // like insert, but returns error instead of panic; a non-positive new id is not a valid
+    // revision and is reported as unknown
+    pub fn try_insert(&mut self, new_revision: Revision, old_revision: Revision) -> PersResult<()> {
+        if new_revision <= 0 {
+            return Err(PersError::UnknownRevision(new_revision));
+        }
+        if self.parent.contains_key(&new_revision) {
+            return Err(PersError::DuplicateRevision(new_revision));
+        }
+        if old_revision != -1 && !self.parent.contains_key(&old_revision) {
+            return Err(PersError::UnknownRevision(old_revision));
+        }
+        Ok(self.insert(new_revision, old_revision))
+    }
+
     pub fn insert(&mut self, new_revision: Revision, old_revision: Revision) {
         assert!(new_revision > 0);
         assert!(!self.parent.contains_key(&new_revision));
         assert!(old_revision == -1 || self.parent.contains_key(&old_revision));
 
         self.parent.insert(new_revision, old_revision);
+        let after = if old_revision == -1 { self.last } else { self.elements[old_revision].0 };
+        let enter = self.insert_after(after);
+        let exit = self.insert_after(enter);
+        self.elements.insert(new_revision, (enter, exit));
+        self.ancestry.insert(new_revision, if old_revision == -1 { None } else { Some(old_revision) });
     }
 }
 
@@ -95,32 +246,118 @@ fn version_tree_test() {
     assert!(vt.is_initial(1));
     assert!(!vt.is_initial(2));
     assert!(!vt.is_initial(5));
+
+    assert_eq!(vt.try_insert(8, 1), Err(PersError::DuplicateRevision(8)));
+    assert_eq!(vt.try_insert(9, 10), Err(PersError::UnknownRevision(10)));
+    assert_eq!(vt.try_insert(0, 1), Err(PersError::UnknownRevision(0)));
+    assert_eq!(vt.try_insert(9, 8), Ok(()));
+
+    assert_eq!(vt.depth(1), 0u);
+    assert_eq!(vt.depth(9), 5u);
+    assert_eq!(vt.ancestor_at_depth(9, 2), 3);
+    assert!(vt.is_ancestor(2, 8));
+    assert!(vt.is_ancestor(4, 4));
+    assert!(!vt.is_ancestor(4, 8));
+    assert!(!vt.is_ancestor(8, 3));
+    assert_eq!(vt.lca(7, 6), Some(4));
+    assert_eq!(vt.lca(9, 7), Some(2));
+    vt.insert(10, -1);
+    assert_eq!(vt.lca(10, 7), None);
 }
 
 pub struct VersionedFatNode<T: Clone> {
-    values: HMap<Revision, T>,
+    values: Vec<(uint /* element */, Option<T>, Revision /* writer */)>, // in version list order
     revisions: Rc<RCell<VersionTree>>
 }
 
 impl<T: Clone> VersionedFatNode<T> {
     pub fn new(rev: Rc<RCell<VersionTree>>) -> VersionedFatNode<T> {
-        let map = HMap::new();
-        VersionedFatNode{values: map, revisions: rev}
+        VersionedFatNode{values: Vec::new(), revisions: rev}
     }
 
-    pub fn value(&self, revision: Revision) -> Option<T> {
-        for cur_rev in self.revisions.borrow().parent_branch(revision).iter() {
-            match self.values.get(cur_rev) {
-                None             => continue,
-                Some(&ref value) => return Some(value.clone())
+    // number of values which elements are not after the element
+    fn upper_bound(&self, tree: &VersionTree, element: uint) -> uint {
+        let key = tree.label(element);
+        let (mut lo, mut hi) = (0u, self.values.len());
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            if tree.label(self.values[mid].0) <= key {
+                lo = mid + 1;
+            } else {
+                hi = mid;
             }
         }
-        None
+        lo
+    }
+
+    // position of the value in effect at the revision
+    fn find(&self, tree: &VersionTree, revision: Revision) -> Option<uint> {
+        let (enter, _) = tree.elements(revision);
+        match self.upper_bound(tree, enter) {
+            0   => None,
+            pos => Some(pos - 1),
+        }
+    }
+
+    pub fn value(&self, revision: Revision) -> Option<T> {
+        let tree = self.revisions.borrow();
+        match self.find(&*tree, revision) {
+            None      => None,
+            Some(pos) => self.values[pos].1.clone(),
+        }
+    }
+
+    // revision which wrote the value in effect at the revision
+    pub fn writer(&self, revision: Revision) -> Option<Revision> {
+        let tree = self.revisions.borrow();
+        match self.find(&*tree, revision) {
+            None      => None,
+            Some(pos) => match self.values[pos] {
+                (_, Some(_), writer) => Some(writer),
+                (_, None, _)         => None,
+            },
+        }
+    }
+
+    // values written by the revision and its ancestors, from the initial revision
+    pub fn history(&self, revision: Revision) -> Vec<(Revision, T)> {
+        let tree = self.revisions.borrow();
+        let (enter, _) = tree.elements(revision);
+        let end = self.upper_bound(&*tree, enter);
+        self.values.slice_to(end).iter()
+                   .filter(|&&(element, _, writer)| writer != -1
+                                                    && tree.elements(writer).0 == element
+                                                    && tree.is_ancestor(writer, revision))
+                   .map(|&(_, ref value, writer)| (writer, value.clone().unwrap()))
+                   .collect()
     }
 
     pub fn add_value(&mut self, new_revision: Revision, value: T, old_revision: Revision) {
         self.revisions.borrow_mut().insert(new_revision, old_revision);
-        self.values.insert(new_revision, value.clone());
+        self.set_value(new_revision, value);
+    }
+
+    // value of the revision which is already in the version tree; the revision
+    // must have no descendants yet, as they would not see the value
+    pub fn set_value(&mut self, revision: Revision, value: T) {
+        let tree = self.revisions.clone();
+        let tree = tree.borrow();
+        let (enter, exit) = tree.elements(revision);
+        let pos = self.upper_bound(&*tree, enter);
+        if pos > 0 && self.values[pos - 1].0 == enter {
+            self.values[pos - 1].1 = Some(value);
+            return;
+        }
+
+        let parent = tree.parent_revision(revision);
+        let found = if parent == -1 { None } else { self.find(&*tree, parent) };
+        let restored = match found {
+            None    => (exit, None, -1),
+            Some(p) => (exit, self.values[p].1.clone(), self.values[p].2),
+        };
+        let exit_pos = self.upper_bound(&*tree, exit);
+        self.values.insert(exit_pos, restored);
+        self.values.insert(pos, (enter, Some(value), revision));
     }
 }
 
@@ -192,3 +429,58 @@ fn three_fatnodes_test() {
     assert_eq!(vfb.value(6).unwrap(), "six");
     assert_eq!(vfc.value(6).unwrap(), "four");
 }
+
+#[test]
+fn deep_history_fatnode_test() {
+    use inner::lcg_random::*;
+
+    let vs = new_vtree(1);
+    let mut vf = VersionedFatNode::new(vs.clone());
+    let mut expected = HMap::new();
+    let mut rnd: CoolLCG = LCG::new();
+
+    // long chains with random branching, every third revision stores a value
+    for rev in range(2i, 3000) {
+        let parent = if rnd.next() % 8 == 0 { rnd.next() % (rev - 1) + 1 } else { rev - 1 };
+        if rev % 3 == 0 {
+            vf.add_value(rev, rev, parent);
+            expected.insert(rev, rev);
+        } else {
+            vs.borrow_mut().insert(rev, parent);
+        }
+    }
+
+    for rev in range(1i, 3000) {
+        let naive = vs.borrow().parent_branch(rev).iter()
+                                                  .filter_map(|r| expected.get(r).map(|&v| v))
+                                                  .next();
+        assert_eq!(vf.value(rev), naive);
+    }
+}
+
+#[test]
+fn fatnode_writers_test() {
+    let vs = new_vtree(1);
+    let mut vf = VersionedFatNode::new(vs.clone());
+
+    /*
+     *  1--2a--3--5b
+     *          \
+     *           4c
+     */
+    vf.add_value(2, "a", 1);
+    vs.borrow_mut().insert(3, 2);
+    vf.add_value(4, "c", 3);
+    vs.borrow_mut().insert(5, 3);
+    vf.set_value(5, "x");
+    vf.set_value(5, "b");
+
+    assert_eq!(vf.writer(1), None);
+    assert_eq!(vf.writer(3), Some(2));
+    assert_eq!(vf.writer(4), Some(4));
+    assert_eq!(vf.value(5).unwrap(), "b");
+    assert_eq!(vf.history(5), vec![(2, "a"), (5, "b")]);
+    assert_eq!(vf.history(4), vec![(2, "a"), (4, "c")]);
+    assert_eq!(vf.history(3), vec![(2, "a")]);
+    assert!(vf.history(1).is_empty());
+}
@@ -1,6 +1,16 @@
-#![feature(globs)]
+#![feature(globs, default_type_params)]
 
 pub mod vector;
 pub mod map;
+pub mod stack;
+pub mod queue;
+pub mod deque;
+pub mod bitset;
+pub mod union_find;
+pub mod segment_tree;
+pub mod rope;
+pub mod graph;
+pub mod trie;
 pub mod dlist;
+pub mod error;
 pub mod inner;
@@ -1,2 +1,3 @@
 pub mod map_iterator;
 pub mod pers_map;
+pub mod pers_hash_map;
@@ -0,0 +1,241 @@
+use inner::hamt::*;
+use inner::persistent::*;
+use std::cell::RefCell;
+use std::collections::BTreeMap as TreeMap;
+use std::hash::Hash;
+use std::rc::Rc;
+use std::vec::Vec;
+
+pub type Node<K, V> = Rc<Hamt<K, V>>;
+pub type SharedData<K, V> = Rc<RefCell<SharedHashMapData<K, V>>>;
+
+
+
+pub struct SharedHashMapData<K, V> {
+    pub last_revision:    Revision, // revision counter
+    pub roots:            TreeMap<Revision, (Node<K, V>, uint)>, // root trie node and length for each revision
+}
+
+pub struct PersHashMap<K, V> {
+    line_history:        Vec<Revision>, // branch of history for undo-redo
+    head_revision_id:    uint, // id of the current verision in line_history vector
+    root:                Node<K, V>, // root node for current revision
+    len:                 uint, // number of keys in current revision
+    shared_data:         SharedData<K, V>, // pointer to above structure
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> Persistent<PersHashMap<K, V>> for PersHashMap<K, V> {
+    fn get_by_revision(&self, revision : Revision) -> PersHashMap<K, V> {
+        assert!(revision <= self.shared_data.borrow().last_revision);
+        assert!(self.shared_data.borrow().roots.contains_key(&revision));
+
+        let (root, len) = self.shared_data.borrow().roots[revision].clone();
+        PersHashMap{line_history: vec![revision],
+                    head_revision_id: 0,
+                    root: root,
+                    len: len,
+                    shared_data: self.shared_data.clone()}
+    }
+
+    fn current_revision_id(&self) -> Revision {
+        assert!(self.line_history.len() > self.head_revision_id);
+
+        self.line_history[self.head_revision_id]
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> TryPersistent<PersHashMap<K, V>> for PersHashMap<K, V> {
+    fn contains_revision(&self, revision : Revision) -> bool {
+        self.shared_data.borrow().roots.contains_key(&revision)
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> Recall for PersHashMap<K, V> {
+    fn undo(&mut self) -> Revision {
+        assert!(self.head_revision_id > 0u);
+
+        self.head_revision_id -= 1;
+        self.restore_head();
+        self.line_history[self.head_revision_id]
+    }
+
+    fn redo(&mut self) -> Revision {
+        assert!(self.head_revision_id + 1u < self.line_history.len());
+
+        self.head_revision_id += 1;
+        self.restore_head();
+        self.line_history[self.head_revision_id]
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> TryRecall for PersHashMap<K, V> {
+    fn can_undo(&self) -> bool {
+        self.head_revision_id > 0u
+    }
+
+    fn can_redo(&self) -> bool {
+        self.head_revision_id + 1u < self.line_history.len()
+    }
+
+    fn line_revision(&self) -> Revision {
+        self.line_history[self.head_revision_id]
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> FullyPersistent<PersHashMap<K, V>> for PersHashMap<K, V> { }
+
+impl<K: Hash + Eq + Clone, V: Clone> Clone for PersHashMap<K, V> {
+    fn clone(&self) -> Self {
+        PersHashMap{line_history: self.line_history.clone(),
+                    head_revision_id: self.head_revision_id,
+                    root: self.root.clone(),
+                    len: self.len,
+                    shared_data: self.shared_data.clone()}
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> PersHashMap<K, V> {
+    pub fn new() -> PersHashMap<K, V> {
+        let mut new_roots = TreeMap::new();
+        new_roots.insert(1, (Hamt::new_empty(), 0u));
+        let shdata = Rc::new(RefCell::new(SharedHashMapData::<K, V>{last_revision: 1,
+                                                                    roots: new_roots}));
+        PersHashMap{line_history: vec![1],
+                    head_revision_id: 0,
+                    root: Hamt::new_empty(),
+                    len: 0,
+                    shared_data: shdata}
+    }
+
+    fn restore_head(&mut self) {
+        let rev = self.line_history[self.head_revision_id];
+        let (root, len) = self.shared_data.borrow().roots[rev].clone();
+        self.root = root;
+        self.len = len;
+    }
+
+    fn commit(&mut self, new_root: Node<K, V>, new_len: uint) -> Revision {
+        let mut data = self.shared_data.borrow_mut();
+        let revision = data.last_revision + 1;
+        data.roots.insert(revision, (new_root.clone(), new_len));
+
+        self.head_revision_id += 1;
+        self.line_history.truncate(self.head_revision_id);
+        self.line_history.push(revision);
+        self.root = new_root;
+        self.len = new_len;
+
+        data.last_revision = revision;
+        data.last_revision
+    }
+
+    pub fn len(&self) -> uint {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn insert(&mut self, key: K, value: V) -> Revision {
+        let hash = hash_key(&key);
+        let (new_root, added) = Hamt::insert(&self.root, 0, hash, key, value);
+        let new_len = if added { self.len + 1 } else { self.len };
+        self.commit(new_root, new_len)
+    }
+
+    pub fn remove(&mut self, key: &K) -> Revision {
+        let (new_root, removed) = Hamt::remove(&self.root, 0, hash_key(key), key);
+        let new_len = if removed { self.len - 1 } else { self.len };
+        self.commit(new_root.unwrap(), new_len)
+    }
+
+    pub fn get<'a>(&'a self, key: &K) -> Option<&'a V> {
+        self.root.get(hash_key(key), key)
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn iter<'a>(&'a self) -> HamtIterator<'a, K, V> {
+        self.root.iter()
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone + PartialEq> PersHashMap<K, V> {
+    // changes which turn this map into other one, subtrees shared by both revisions are skipped
+    pub fn diff<'a>(&'a self, other: &'a PersHashMap<K, V>) -> Vec<Change<'a, K, V>> {
+        let mut changes = Vec::new();
+        Hamt::diff(&self.root, &other.root, &mut changes);
+        changes
+    }
+}
+
+#[test]
+fn hash_map_insert_remove_test() {
+    let mut m = PersHashMap::<String, int>::new();
+    m.insert("ten".to_string(), 10);
+    m.insert("twenty".to_string(), 20);
+    m.insert("thirty".to_string(), 30);
+    let map_before = m.current();
+    m.remove(&"thirty".to_string());
+    let map_after = m.current();
+    m.remove(&"twenty five".to_string());
+    m.insert("ten".to_string(), 11);
+
+    assert_eq!(map_before.get(&"thirty".to_string()), Some(&30));
+    assert_eq!(map_after.get(&"thirty".to_string()), None);
+    assert_eq!(m.get(&"ten".to_string()), Some(&11));
+    assert_eq!(m.len(), 2u);
+    assert_eq!(map_before.len(), 3u);
+}
+
+#[test]
+fn hash_map_iterator_test() {
+    let mut map = PersHashMap::<int, int>::new();
+    for i in range(0i, 500) {
+        map.insert(i, i * i);
+    }
+    let mut keys: Vec<int> = map.iter().map(|(k, v)| { assert_eq!(*v, *k * *k); *k }).collect();
+    keys.sort();
+    assert_eq!(keys, range(0i, 500).collect::<Vec<int>>());
+}
+
+#[test]
+fn hash_map_undoredo_test() {
+    let mut map = PersHashMap::<int, &str>::new();
+
+    map.insert(1, "one");
+    map.insert(2, "two");
+    map.undo();
+    assert!(!map.contains_key(&2));
+    assert!(map.contains_key(&1));
+    assert_eq!(map.len(), 1u);
+
+    map.redo();
+    assert!(map.contains_key(&2));
+    assert_eq!(map.len(), 2u);
+}
+
+#[test]
+fn hash_map_diff_test() {
+    let mut map = PersHashMap::<int, int>::new();
+    for i in range(0i, 100) {
+        map.insert(i, i);
+    }
+    let before = map.current();
+    map.remove(&7);
+    map.insert(8, 80);
+    map.insert(100, 100);
+
+    let changes = before.diff(&map);
+    assert_eq!(changes.len(), 3u);
+    for change in changes.iter() {
+        match *change {
+            Change::Added(k, _)       => assert_eq!(*k, 100),
+            Change::Removed(k, _)     => assert_eq!(*k, 7),
+            Change::Modified(k, a, b) => assert_eq!((*k, *a, *b), (8, 8, 80)),
+        }
+    }
+}
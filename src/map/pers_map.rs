@@ -43,6 +43,12 @@ impl<K: Ord + Clone, V: Clone> Persistent<PersMap<K, V>> for PersMap<K, V> {
     }
 }
 
+impl<K: Ord + Clone, V: Clone> TryPersistent<PersMap<K, V>> for PersMap<K, V> {
+    fn contains_revision(&self, revision : Revision) -> bool {
+        self.shared_data.borrow().roots.contains_key(&revision)
+    }
+}
+
 impl<K: Clone + Ord, V: Clone> Recall for PersMap<K, V> {
     fn undo(&mut self) -> Revision {
         assert!(self.head_revision_id > 0u);
@@ -61,6 +67,20 @@ impl<K: Clone + Ord, V: Clone> Recall for PersMap<K, V> {
     }
 }
 
+impl<K: Clone + Ord, V: Clone> TryRecall for PersMap<K, V> {
+    fn can_undo(&self) -> bool {
+        self.head_revision_id > 0u
+    }
+
+    fn can_redo(&self) -> bool {
+        self.head_revision_id + 1u < self.line_history.len()
+    }
+
+    fn line_revision(&self) -> Revision {
+        self.line_history[self.head_revision_id]
+    }
+}
+
 impl<K: Clone + Ord, V: Clone> FullyPersistent<PersMap<K, V>> for PersMap<K, V> { }
 
 impl<K: Ord + Clone, V: Clone> Clone for PersMap<K, V> {
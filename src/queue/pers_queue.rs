@@ -0,0 +1,381 @@
+//! Persistent queue.
+//!
+//! Queue provides O(1) worst-case enqueue, dequeue and access to the front element.
+//!
+//! Implementation is the Hood-Melville real-time queue (see C. Okasaki, "Purely Functional
+//! Data Structures", section 8.2.1): the rear list is reversed onto the front incrementally, a
+//! couple of steps per operation, so no single operation ever pays for the whole reversal.
+
+use inner::cons_list::*;
+use error::{PersError, PersResult};
+use inner::persistent::*;
+use std::cell::RefCell;
+use std::collections::BTreeMap as TreeMap;
+use std::rc::Rc;
+use std::vec::Vec;
+
+type List<T> = Rc<ConsList<T>>;
+type Node<T> = Rc<QueueNode<T>>;
+type SharedData<T> = Rc<RefCell<SharedQueueData<T>>>;
+
+
+
+#[deriving(Clone)]
+enum Rotation<T> {
+    Idle,
+    Reversing(uint /* valid */, List<T> /* front */, List<T> /* reversed front */,
+              List<T> /* rear */, List<T> /* reversed rear */),
+    Appending(uint /* valid */, List<T> /* reversed front */, List<T> /* result */),
+    Done(List<T> /* new front */),
+}
+
+// one immutable state of the queue
+struct QueueNode<T> {
+    front_len: uint,
+    front:     List<T>,
+    rotation:  Rotation<T>,
+    rear_len:  uint,
+    rear:      List<T>,
+}
+
+impl<T: Clone> Rotation<T> {
+    fn exec(self) -> Rotation<T> {
+        match self {
+            Rotation::Reversing(ok, f, rf, r, rr) => match (&*f, &*r) {
+                (&ConsList::Cons(ref x, ref f_tail), &ConsList::Cons(ref y, ref r_tail)) =>
+                    Rotation::Reversing(ok + 1,
+                                        f_tail.clone(),
+                                        ConsList::push(&rf, x.clone()),
+                                        r_tail.clone(),
+                                        ConsList::push(&rr, y.clone())),
+                (&ConsList::Nil, &ConsList::Cons(ref y, ref r_tail)) => {
+                    assert!(r_tail.is_nil());
+                    Rotation::Appending(ok, rf.clone(), ConsList::push(&rr, y.clone()))
+                },
+                _ => panic!("rear list should be one element longer than front"),
+            },
+            Rotation::Appending(0, _, res) =>
+                Rotation::Done(res),
+            Rotation::Appending(ok, rf, res) => match *rf {
+                ConsList::Cons(ref x, ref rf_tail) =>
+                    Rotation::Appending(ok - 1, rf_tail.clone(), ConsList::push(&res, x.clone())),
+                ConsList::Nil =>
+                    panic!("reversed front is shorter than valid counter"),
+            },
+            state => state,
+        }
+    }
+
+    // one element was taken from the front, so one less should be copied back
+    fn invalidate(self) -> Rotation<T> {
+        match self {
+            Rotation::Reversing(ok, f, rf, r, rr) =>
+                Rotation::Reversing(ok - 1, f, rf, r, rr),
+            Rotation::Appending(0, _, res) =>
+                Rotation::Done(ConsList::tail(&res)),
+            Rotation::Appending(ok, rf, res) =>
+                Rotation::Appending(ok - 1, rf, res),
+            state => state,
+        }
+    }
+}
+
+impl<T: Clone> QueueNode<T> {
+    fn new_empty() -> Node<T> {
+        Rc::new(QueueNode{front_len: 0,
+                          front:     ConsList::new_empty(),
+                          rotation:  Rotation::Idle,
+                          rear_len:  0,
+                          rear:      ConsList::new_empty()})
+    }
+
+    fn exec2(front_len: uint, front: List<T>, rotation: Rotation<T>, rear_len: uint, rear: List<T>) -> Node<T> {
+        let node = match rotation.exec().exec() {
+            Rotation::Done(new_front) =>
+                QueueNode{front_len: front_len, front: new_front, rotation: Rotation::Idle,
+                          rear_len: rear_len, rear: rear},
+            new_rotation =>
+                QueueNode{front_len: front_len, front: front, rotation: new_rotation,
+                          rear_len: rear_len, rear: rear},
+        };
+        Rc::new(node)
+    }
+
+    fn check(front_len: uint, front: List<T>, rotation: Rotation<T>, rear_len: uint, rear: List<T>) -> Node<T> {
+        if rear_len <= front_len {
+            QueueNode::exec2(front_len, front, rotation, rear_len, rear)
+        } else {
+            let rotation = Rotation::Reversing(0, front.clone(), ConsList::new_empty(),
+                                               rear, ConsList::new_empty());
+            QueueNode::exec2(front_len + rear_len, front, rotation, 0, ConsList::new_empty())
+        }
+    }
+
+    fn len(&self) -> uint {
+        self.front_len + self.rear_len
+    }
+
+    fn front<'a>(&'a self) -> Option<&'a T> {
+        self.front.head()
+    }
+
+    fn snoc(&self, value: T) -> Node<T> {
+        QueueNode::check(self.front_len,
+                         self.front.clone(),
+                         self.rotation.clone(),
+                         self.rear_len + 1,
+                         ConsList::push(&self.rear, value))
+    }
+
+    fn tail(&self) -> Node<T> {
+        assert!(self.front_len > 0);
+
+        QueueNode::check(self.front_len - 1,
+                         ConsList::tail(&self.front),
+                         self.rotation.clone().invalidate(),
+                         self.rear_len,
+                         self.rear.clone())
+    }
+}
+
+
+
+struct SharedQueueData<T> {
+    last_revision: Revision, // revision counter
+    roots:         TreeMap<Revision, Node<T>>, // queue state for each revision
+}
+
+/// Persistent FIFO queue implementation.
+///
+/// # Examples
+///
+/// ```
+/// let mut queue = PersQueue::<int>::new();
+/// queue.enqueue(1807);
+/// let rev = queue.enqueue(2609);
+/// queue.dequeue();
+/// assert_eq!(queue.front(), Some(&2609));
+/// assert_eq!(queue.get_by_revision(rev).front(), Some(&1807));
+/// ```
+pub struct PersQueue<T> {
+    line_history:     Vec<Revision>, // branch of history for undo-redo
+    head_revision_id: uint, // id of the current verision in line_history vector
+    root:             Node<T>, // queue state for the current revision
+    shared_data:      SharedData<T>, // shared data between all revision
+}
+
+impl<T: Clone> PersQueue<T> {
+    /// Constructs a new, empty persistent queue.
+    pub fn new() -> PersQueue<T> {
+        let mut roots = TreeMap::new();
+        roots.insert(1, QueueNode::new_empty());
+        let shdata = Rc::new(RefCell::new(SharedQueueData::<T>{last_revision: 1,
+                                                               roots: roots}));
+        PersQueue{line_history: vec![1],
+                  head_revision_id: 0,
+                  root: QueueNode::new_empty(),
+                  shared_data: shdata}
+    }
+
+    fn commit(&mut self, root: Node<T>) -> Revision {
+        let mut data = self.shared_data.borrow_mut();
+        let revision = data.last_revision + 1;
+        data.roots.insert(revision, root.clone());
+        data.last_revision = revision;
+
+        self.head_revision_id += 1;
+        self.line_history.truncate(self.head_revision_id);
+        self.line_history.push(revision);
+        self.root = root;
+
+        revision
+    }
+
+    /// Returns the number of elements in the current queue revision.
+    pub fn len(&self) -> uint {
+        self.root.len()
+    }
+
+    /// Returns ```true``` if the queue contains no elements and false otherwise.
+    pub fn is_empty(&self) -> bool {
+        self.root.len() == 0
+    }
+
+    /// Returns the oldest element of the current queue revision.
+    pub fn front<'a>(&'a self) -> Option<&'a T> {
+        self.root.front()
+    }
+
+    /// Append an element to the back of the queue.
+    ///
+    /// Returns new revision id.
+    pub fn enqueue(&mut self, value: T) -> Revision {
+        let new_root = self.root.snoc(value);
+        self.commit(new_root)
+    }
+
+    /// Remove an element from the front of the queue.
+    ///
+    /// Returns new revision id.
+    ///
+    /// # Panics
+    /// Panics if the queue is empty.
+    pub fn dequeue(&mut self) -> Revision {
+        assert!(!self.is_empty());
+        let new_root = self.root.tail();
+        self.commit(new_root)
+    }
+
+    /// Like ```dequeue```, but returns ```Empty``` error instead of panic.
+    pub fn try_dequeue(&mut self) -> PersResult<Revision> {
+        if self.is_empty() {
+            return Err(PersError::Empty);
+        }
+        Ok(self.dequeue())
+    }
+
+    /// Returns iterator from the front to the back of the current queue revision.
+    ///
+    /// Each step costs O(1) worst-case time.
+    pub fn iter(&self) -> Items<T> {
+        Items{node: self.root.clone()}
+    }
+}
+
+impl<T: Clone> Persistent<PersQueue<T>> for PersQueue<T> {
+    fn get_by_revision(&self, revision : Revision) -> PersQueue<T> {
+        assert!(self.shared_data.borrow().roots.contains_key(&revision));
+
+        PersQueue{line_history: vec![revision],
+                  head_revision_id: 0,
+                  root: self.shared_data.borrow().roots[revision].clone(),
+                  shared_data: self.shared_data.clone()}
+    }
+
+    fn current_revision_id(&self) -> Revision {
+        self.line_history[self.head_revision_id]
+    }
+}
+
+impl<T: Clone> TryPersistent<PersQueue<T>> for PersQueue<T> {
+    fn contains_revision(&self, revision : Revision) -> bool {
+        self.shared_data.borrow().roots.contains_key(&revision)
+    }
+}
+
+impl<T: Clone> Recall for PersQueue<T> {
+    fn undo(&mut self) -> Revision {
+        assert!(self.head_revision_id > 0u);
+
+        self.head_revision_id -= 1;
+        let revision = self.line_history[self.head_revision_id];
+        self.root = self.shared_data.borrow().roots[revision].clone();
+        revision
+    }
+
+    fn redo(&mut self) -> Revision {
+        assert!(self.head_revision_id + 1u < self.line_history.len());
+
+        self.head_revision_id += 1;
+        let revision = self.line_history[self.head_revision_id];
+        self.root = self.shared_data.borrow().roots[revision].clone();
+        revision
+    }
+}
+
+impl<T: Clone> TryRecall for PersQueue<T> {
+    fn can_undo(&self) -> bool {
+        self.head_revision_id > 0u
+    }
+
+    fn can_redo(&self) -> bool {
+        self.head_revision_id + 1u < self.line_history.len()
+    }
+
+    fn line_revision(&self) -> Revision {
+        self.line_history[self.head_revision_id]
+    }
+}
+
+impl<T: Clone> FullyPersistent<PersQueue<T>> for PersQueue<T> { }
+
+impl<T: Clone> Clone for PersQueue<T> {
+    fn clone(&self) -> Self {
+        PersQueue{line_history: self.line_history.clone(),
+                  head_revision_id: self.head_revision_id,
+                  root: self.root.clone(),
+                  shared_data: self.shared_data.clone()}
+    }
+}
+
+pub struct Items<T> {
+    node: Node<T>,
+}
+
+impl<T: Clone> Iterator<T> for Items<T> {
+    fn next(&mut self) -> Option<T> {
+        let value = match self.node.front() {
+            None        => return None,
+            Some(value) => value.clone(),
+        };
+        self.node = self.node.tail();
+        Some(value)
+    }
+
+    fn size_hint(&self) -> (uint, Option<uint>) {
+        (self.node.len(), Some(self.node.len()))
+    }
+}
+
+#[test]
+fn queue_fifo_test() {
+    let mut queue = PersQueue::<int>::new();
+    for i in range(0i, 100) {
+        queue.enqueue(i);
+        if i % 3 == 0 {
+            queue.dequeue();
+        }
+    }
+    assert_eq!(queue.len(), 66u);
+    assert_eq!(queue.iter().collect::<Vec<int>>(), range(34i, 100).collect::<Vec<int>>());
+
+    let mut expected = 34i;
+    while !queue.is_empty() {
+        assert_eq!(queue.front(), Some(&expected));
+        queue.dequeue();
+        expected += 1;
+    }
+    assert_eq!(expected, 100);
+}
+
+#[test]
+fn queue_undoredo_test() {
+    let mut queue = PersQueue::<&str>::new();
+    queue.enqueue("one");
+    queue.enqueue("two");
+    queue.dequeue();
+    assert_eq!(queue.front(), Some(&"two"));
+
+    queue.undo();
+    assert_eq!(queue.front(), Some(&"one"));
+    assert_eq!(queue.len(), 2u);
+    queue.redo();
+    assert_eq!(queue.front(), Some(&"two"));
+    assert_eq!(queue.len(), 1u);
+}
+
+#[test]
+fn queue_fully_persistent_test() {
+    let mut queue = PersQueue::<int>::new();
+    for i in range(0i, 10) {
+        queue.enqueue(i);
+    }
+    let rev_a = queue.dequeue();
+    queue.undo();
+    let rev_b = queue.enqueue(10);
+
+    let queue_a = queue.get_by_revision(rev_a);
+    let queue_b = queue.get_by_revision(rev_b);
+    assert_eq!(queue_a.iter().collect::<Vec<int>>(), range(1i, 10).collect::<Vec<int>>());
+    assert_eq!(queue_b.iter().collect::<Vec<int>>(), range(0i, 11).collect::<Vec<int>>());
+}
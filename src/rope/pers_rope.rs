@@ -0,0 +1,396 @@
+//! Persistent rope.
+//!
+//! Rope is a text buffer for editors: insert and remove at any byte or char offset take
+//! O(lg(N)) time, and so do conversions between byte, char and line offsets. Every edit is a
+//! revision with undo-redo support, and old revisions share all unchanged chunks.
+
+use error::{check_range, PersError, PersResult};
+use inner::persistent::*;
+use inner::rope::*;
+use std::cell::RefCell;
+use std::collections::BTreeMap as TreeMap;
+use std::fmt;
+use std::rc::Rc;
+use std::string::String;
+use std::vec::Vec;
+
+type Node = Rc<Rope>;
+type SharedData = Rc<RefCell<SharedRopeData>>;
+
+
+
+struct SharedRopeData {
+    last_revision: Revision, // revision counter
+    roots:         TreeMap<Revision, Node>, // root tree node for each revision
+}
+
+/// Persistent rope implementation.
+///
+/// # Examples
+///
+/// ```
+/// let mut text = PersRope::from_str("hello world");
+/// text.insert(5, ",");
+/// let rev = text.insert(text.len(), "!");
+/// text.remove(0, 7);
+///
+/// assert_eq!(text.to_string().as_slice(), "world!");
+/// assert_eq!(text.get_by_revision(rev).to_string().as_slice(), "hello, world!");
+///
+/// text.undo();
+/// assert_eq!(text.slice(0, 5).as_slice(), "hello");
+/// ```
+///
+/// Offsets may be given in chars and converted to lines:
+///
+/// ```
+/// let mut text = PersRope::from_str("один\nдва\n");
+/// text.insert_at_char(5, "ноль\n");
+/// assert_eq!(text.line(1).as_slice(), "ноль\n");
+/// assert_eq!(text.byte_to_line(text.line_to_byte(2)), 2);
+/// ```
+pub struct PersRope {
+    line_history:     Vec<Revision>, // branch of history for undo-redo
+    head_revision_id: uint, // id of the current verision in line_history vector
+    root:             Node, // root tree node for the current revision
+    shared_data:      SharedData, // shared data between all revision
+}
+
+impl PersRope {
+    /// Constructs a new, empty persistent rope.
+    pub fn new() -> PersRope {
+        PersRope::from_str("")
+    }
+
+    /// Constructs a new persistent rope which initial revision holds the given text.
+    pub fn from_str(text: &str) -> PersRope {
+        let root = Rope::from_str(text);
+        let mut roots = TreeMap::new();
+        roots.insert(1, root.clone());
+        let shdata = Rc::new(RefCell::new(SharedRopeData{last_revision: 1,
+                                                         roots: roots}));
+        PersRope{line_history: vec![1],
+                 head_revision_id: 0,
+                 root: root,
+                 shared_data: shdata}
+    }
+
+    fn commit(&mut self, root: Node) -> Revision {
+        let mut data = self.shared_data.borrow_mut();
+        let revision = data.last_revision + 1;
+        data.roots.insert(revision, root.clone());
+        data.last_revision = revision;
+
+        self.head_revision_id += 1;
+        self.line_history.truncate(self.head_revision_id);
+        self.line_history.push(revision);
+        self.root = root;
+
+        revision
+    }
+
+    /// Returns the length of the text in bytes.
+    pub fn len(&self) -> uint {
+        self.root.metrics().bytes
+    }
+
+    /// Returns the length of the text in chars.
+    pub fn char_len(&self) -> uint {
+        self.root.metrics().chars
+    }
+
+    /// Returns ```true``` if the text is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of lines, which is the number of newlines plus one.
+    pub fn line_count(&self) -> uint {
+        self.root.metrics().newlines + 1
+    }
+
+    /// Converts char offset to byte offset.
+    ///
+    /// # Panics
+    /// Panics if the offset is out of bounds.
+    pub fn char_to_byte(&self, char_offset: uint) -> uint {
+        assert!(char_offset <= self.char_len());
+
+        self.root.char_to_byte(char_offset)
+    }
+
+    /// Converts byte offset to char offset.
+    ///
+    /// # Panics
+    /// Panics if the offset is out of bounds or not on a char boundary.
+    pub fn byte_to_char(&self, byte_offset: uint) -> uint {
+        assert!(byte_offset <= self.len());
+
+        self.root.prefix_metrics(byte_offset).chars
+    }
+
+    /// Returns byte offset of the first char of the line, lines are counted from zero.
+    ///
+    /// # Panics
+    /// Panics if there is no such line.
+    pub fn line_to_byte(&self, line: uint) -> uint {
+        assert!(line < self.line_count());
+
+        if line == 0 {
+            0
+        } else {
+            self.root.newline_to_byte(line - 1) + 1
+        }
+    }
+
+    /// Like ```line_to_byte```, but returns ```IndexOutOfBounds``` error instead of panic.
+    pub fn try_line_to_byte(&self, line: uint) -> PersResult<uint> {
+        if line >= self.line_count() {
+            return Err(PersError::IndexOutOfBounds(line, self.line_count()));
+        }
+        Ok(self.line_to_byte(line))
+    }
+
+    // bytes [from, to) are in bounds and the ends are on char boundaries
+    fn check_bytes(&self, from: uint, to: uint) -> PersResult<()> {
+        try!(check_range(from, to, self.len()));
+        for &offset in [from, to].iter() {
+            if !self.root.is_char_boundary(offset) {
+                return Err(PersError::NotCharBoundary(offset));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the number of the line which contains the byte.
+    pub fn byte_to_line(&self, byte_offset: uint) -> uint {
+        assert!(byte_offset <= self.len());
+
+        self.root.prefix_metrics(byte_offset).newlines
+    }
+
+    /// Returns a copy of bytes ```[from, to)```.
+    ///
+    /// # Panics
+    /// Panics if the range is out of bounds or not on char boundaries.
+    pub fn slice(&self, from: uint, to: uint) -> String {
+        assert!(from <= to && to <= self.len());
+
+        let (_, tail) = Rope::split(&self.root, from);
+        let (middle, _) = Rope::split(&tail, to - from);
+        let mut result = String::with_capacity(to - from);
+        for chunk in middle.chunks() {
+            result.push_str(chunk);
+        }
+        result
+    }
+
+    /// Like ```slice```, but returns ```IndexOutOfBounds``` or ```NotCharBoundary``` error
+    /// instead of panic.
+    pub fn try_slice(&self, from: uint, to: uint) -> PersResult<String> {
+        try!(self.check_bytes(from, to));
+        Ok(self.slice(from, to))
+    }
+
+    /// Returns a copy of the line including its newline.
+    pub fn line(&self, line: uint) -> String {
+        let from = self.line_to_byte(line);
+        let to = if line + 1 < self.line_count() { self.line_to_byte(line + 1) } else { self.len() };
+        self.slice(from, to)
+    }
+
+    /// Insert text at the byte offset.
+    ///
+    /// Returns new revision id.
+    ///
+    /// # Panics
+    /// Panics if the offset is out of bounds or not on a char boundary.
+    pub fn insert(&mut self, byte_offset: uint, text: &str) -> Revision {
+        assert!(byte_offset <= self.len());
+
+        let (left, right) = Rope::split(&self.root, byte_offset);
+        let new_root = Rope::join(&Rope::join(&left, &Rope::from_str(text)), &right);
+        self.commit(new_root)
+    }
+
+    /// Like ```insert```, but returns ```IndexOutOfBounds``` or ```NotCharBoundary``` error
+    /// instead of panic.
+    pub fn try_insert(&mut self, byte_offset: uint, text: &str) -> PersResult<Revision> {
+        try!(self.check_bytes(byte_offset, byte_offset));
+        Ok(self.insert(byte_offset, text))
+    }
+
+    /// Insert text at the char offset.
+    ///
+    /// Returns new revision id.
+    pub fn insert_at_char(&mut self, char_offset: uint, text: &str) -> Revision {
+        let byte_offset = self.char_to_byte(char_offset);
+        self.insert(byte_offset, text)
+    }
+
+    /// Remove bytes ```[from, to)```.
+    ///
+    /// Returns new revision id.
+    ///
+    /// # Panics
+    /// Panics if the range is out of bounds or not on char boundaries.
+    pub fn remove(&mut self, from: uint, to: uint) -> Revision {
+        assert!(from <= to && to <= self.len());
+
+        let (left, tail) = Rope::split(&self.root, from);
+        let (_, right) = Rope::split(&tail, to - from);
+        let new_root = Rope::join(&left, &right);
+        self.commit(new_root)
+    }
+
+    /// Like ```remove```, but returns ```IndexOutOfBounds``` or ```NotCharBoundary``` error
+    /// instead of panic.
+    pub fn try_remove(&mut self, from: uint, to: uint) -> PersResult<Revision> {
+        try!(self.check_bytes(from, to));
+        Ok(self.remove(from, to))
+    }
+
+    /// Remove chars ```[from, to)```.
+    ///
+    /// Returns new revision id.
+    pub fn remove_chars(&mut self, from: uint, to: uint) -> Revision {
+        let (byte_from, byte_to) = (self.char_to_byte(from), self.char_to_byte(to));
+        self.remove(byte_from, byte_to)
+    }
+
+    /// Returns iterator over text chunks of the current revision.
+    pub fn chunks<'a>(&'a self) -> Chunks<'a> {
+        self.root.chunks()
+    }
+}
+
+impl fmt::Show for PersRope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for chunk in self.chunks() {
+            try!(write!(f, "{}", chunk));
+        }
+        Ok(())
+    }
+}
+
+impl Persistent<PersRope> for PersRope {
+    fn get_by_revision(&self, revision : Revision) -> PersRope {
+        assert!(self.shared_data.borrow().roots.contains_key(&revision));
+
+        PersRope{line_history: vec![revision],
+                 head_revision_id: 0,
+                 root: self.shared_data.borrow().roots[revision].clone(),
+                 shared_data: self.shared_data.clone()}
+    }
+
+    fn current_revision_id(&self) -> Revision {
+        self.line_history[self.head_revision_id]
+    }
+}
+
+impl TryPersistent<PersRope> for PersRope {
+    fn contains_revision(&self, revision : Revision) -> bool {
+        self.shared_data.borrow().roots.contains_key(&revision)
+    }
+}
+
+impl Recall for PersRope {
+    fn undo(&mut self) -> Revision {
+        assert!(self.head_revision_id > 0u);
+
+        self.head_revision_id -= 1;
+        let revision = self.line_history[self.head_revision_id];
+        self.root = self.shared_data.borrow().roots[revision].clone();
+        revision
+    }
+
+    fn redo(&mut self) -> Revision {
+        assert!(self.head_revision_id + 1u < self.line_history.len());
+
+        self.head_revision_id += 1;
+        let revision = self.line_history[self.head_revision_id];
+        self.root = self.shared_data.borrow().roots[revision].clone();
+        revision
+    }
+}
+
+impl TryRecall for PersRope {
+    fn can_undo(&self) -> bool {
+        self.head_revision_id > 0u
+    }
+
+    fn can_redo(&self) -> bool {
+        self.head_revision_id + 1u < self.line_history.len()
+    }
+
+    fn line_revision(&self) -> Revision {
+        self.line_history[self.head_revision_id]
+    }
+}
+
+impl FullyPersistent<PersRope> for PersRope { }
+
+impl Clone for PersRope {
+    fn clone(&self) -> Self {
+        PersRope{line_history: self.line_history.clone(),
+                 head_revision_id: self.head_revision_id,
+                 root: self.root.clone(),
+                 shared_data: self.shared_data.clone()}
+    }
+}
+
+#[test]
+fn rope_edit_test() {
+    let mut text = PersRope::new();
+    for i in range(0u, 200) {
+        text.insert(0, format!("{}\n", i).as_slice());
+    }
+    assert_eq!(text.line_count(), 201u);
+    assert_eq!(text.line(0).as_slice(), "199\n");
+    assert_eq!(text.line(199).as_slice(), "0\n");
+    assert_eq!(text.line(200).as_slice(), "");
+
+    let from = text.line_to_byte(10);
+    let to = text.line_to_byte(190);
+    let rev = text.remove(from, to);
+    assert_eq!(text.line_count(), 21u);
+    assert_eq!(text.line(10).as_slice(), "9\n");
+    assert_eq!(text.byte_to_line(text.len()), 20u);
+    assert_eq!(text.get_by_revision(rev - 1).line(10).as_slice(), "189\n");
+}
+
+#[test]
+fn rope_utf8_test() {
+    let mut text = PersRope::from_str("über straße");
+    assert_eq!(text.len(), 13u);
+    assert_eq!(text.char_len(), 11u);
+
+    text.insert_at_char(5, "große ");
+    assert_eq!(text.to_string().as_slice(), "über große straße");
+    text.remove_chars(0, 5);
+    assert_eq!(text.to_string().as_slice(), "große straße");
+    assert_eq!(text.byte_to_char(text.char_to_byte(9)), 9u);
+    assert_eq!(text.slice(0, text.char_to_byte(5)).as_slice(), "große");
+
+    let len = text.len();
+    assert_eq!(text.try_slice(0, 4), Err(PersError::NotCharBoundary(4)));
+    assert_eq!(text.try_insert(len + 1, "!"), Err(PersError::IndexOutOfBounds(len + 1, len)));
+    assert_eq!(text.try_line_to_byte(1), Err(PersError::IndexOutOfBounds(1, 1)));
+    assert!(text.try_remove(0, 6).is_ok());
+    assert_eq!(text.to_string().as_slice(), " straße");
+}
+
+#[test]
+fn rope_undoredo_test() {
+    let mut text = PersRope::from_str("abc");
+    text.insert(3, "def");
+    text.remove(0, 2);
+    assert_eq!(text.to_string().as_slice(), "cdef");
+
+    text.undo();
+    assert_eq!(text.to_string().as_slice(), "abcdef");
+    text.undo();
+    assert_eq!(text.to_string().as_slice(), "abc");
+    text.redo_ntimes(2);
+    assert_eq!(text.to_string().as_slice(), "cdef");
+}
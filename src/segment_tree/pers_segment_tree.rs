@@ -0,0 +1,346 @@
+//! Persistent segment tree.
+//!
+//! Segment tree over a fixed index range ```[0, len)``` with point updates, lazy range add and
+//! range assign, and range queries under a monoid (```Sum```, ```Min```, ```Max``` or a user
+//! one). Every update copies O(lg(N)) nodes and creates a new revision, so a query against an
+//! old revision costs the same as against the current one.
+
+use error::{check_range, PersError, PersResult};
+use inner::persistent::*;
+use inner::segment_tree::*;
+use std::cell::RefCell;
+use std::collections::BTreeMap as TreeMap;
+use std::num::Int;
+use std::rc::Rc;
+use std::vec::Vec;
+
+type Node<T> = Rc<SegNode<T>>;
+type SharedData<T> = Rc<RefCell<SharedSegmentTreeData<T>>>;
+
+
+
+struct SharedSegmentTreeData<T> {
+    last_revision: Revision, // revision counter
+    roots:         TreeMap<Revision, Node<T>>, // root tree node for each revision
+}
+
+/// Persistent segment tree implementation.
+///
+/// # Examples
+///
+/// ```
+/// let mut tree = PersSegmentTree::new(vec![5i, 3, 8, 1], Sum);
+/// let rev = tree.add_range(0, 2, 10);
+/// tree.assign_range(1, 4, 0);
+///
+/// assert_eq!(tree.query(0, 4), 15);
+/// assert_eq!(tree.get_by_revision(rev).query(0, 4), 37);
+/// ```
+///
+/// K-th smallest element of a subarray: insert elements one by one into a tree over the value
+/// domain and compare two revisions:
+///
+/// ```
+/// let values = [3u, 0, 2, 1];
+/// let mut counts = PersSegmentTree::new(Vec::from_elem(4, 0i), Sum);
+/// let revs: Vec<Revision> = values.iter().map(|&v| counts.add(v, 1)).collect();
+///
+/// // the smallest value among values[1..4)
+/// let before = counts.get_by_revision(revs[0]);
+/// let after = counts.get_by_revision(revs[3]);
+/// assert_eq!(after.kth_smallest_since(&before, 0), Some(0));
+/// ```
+pub struct PersSegmentTree<T, M> {
+    line_history:     Vec<Revision>, // branch of history for undo-redo
+    head_revision_id: uint, // id of the current verision in line_history vector
+    root:             Node<T>, // root tree node for the current revision
+    len:              uint, // number of elements, the same for all revisions
+    monoid:           M, // aggregate operation
+    shared_data:      SharedData<T>, // shared data between all revision
+}
+
+impl<T: Clone + Add<T, T>, M: Monoid<T> + Copy> PersSegmentTree<T, M> {
+    /// Constructs a new persistent segment tree over the given values.
+    ///
+    /// # Panics
+    /// Panics if ```values``` is empty.
+    pub fn new(values: Vec<T>, monoid: M) -> PersSegmentTree<T, M> {
+        let root = SegNode::build(&monoid, values.as_slice());
+        let mut roots = TreeMap::new();
+        roots.insert(1, root.clone());
+        let shdata = Rc::new(RefCell::new(SharedSegmentTreeData::<T>{last_revision: 1,
+                                                                     roots: roots}));
+        PersSegmentTree{line_history: vec![1],
+                        head_revision_id: 0,
+                        root: root,
+                        len: values.len(),
+                        monoid: monoid,
+                        shared_data: shdata}
+    }
+
+    fn commit(&mut self, root: Node<T>) -> Revision {
+        let mut data = self.shared_data.borrow_mut();
+        let revision = data.last_revision + 1;
+        data.roots.insert(revision, root.clone());
+        data.last_revision = revision;
+
+        self.head_revision_id += 1;
+        self.line_history.truncate(self.head_revision_id);
+        self.line_history.push(revision);
+        self.root = root;
+
+        revision
+    }
+
+    fn update(&mut self, from: uint, to: uint, tag: Lazy<T>) -> Revision {
+        assert!(from < to && to <= self.len);
+
+        let new_root = SegNode::update(&self.root, &self.monoid, self.len, from, to, &tag);
+        self.commit(new_root)
+    }
+
+    /// Returns the number of elements.
+    pub fn len(&self) -> uint {
+        self.len
+    }
+
+    /// Returns the aggregate of elements ```[from, to)``` in the current revision.
+    ///
+    /// Aggregate of an empty range is the monoid identity.
+    ///
+    /// # Panics
+    /// Panics if the range is out of bounds.
+    pub fn query(&self, from: uint, to: uint) -> T {
+        assert!(from <= to && to <= self.len);
+
+        if from == to {
+            return self.monoid.identity();
+        }
+        self.root.query(&self.monoid, self.len, from, to, &Lazy::Keep)
+    }
+
+    /// Like ```query```, but returns ```IndexOutOfBounds``` error instead of panic.
+    pub fn try_query(&self, from: uint, to: uint) -> PersResult<T> {
+        try!(check_range(from, to, self.len));
+        Ok(self.query(from, to))
+    }
+
+    /// Returns the element by its index.
+    pub fn get(&self, id: uint) -> T {
+        self.query(id, id + 1)
+    }
+
+    /// Replace the element by its index.
+    ///
+    /// Returns new revision id.
+    pub fn set(&mut self, id: uint, value: T) -> Revision {
+        self.update(id, id + 1, Lazy::Assign(value))
+    }
+
+    /// Add ```delta``` to the element by its index.
+    ///
+    /// Returns new revision id.
+    pub fn add(&mut self, id: uint, delta: T) -> Revision {
+        self.update(id, id + 1, Lazy::Add(delta))
+    }
+
+    /// Replace every element of non-empty ```[from, to)``` with ```value```.
+    ///
+    /// Returns new revision id.
+    pub fn assign_range(&mut self, from: uint, to: uint, value: T) -> Revision {
+        self.update(from, to, Lazy::Assign(value))
+    }
+
+    /// Add ```delta``` to every element of non-empty ```[from, to)```.
+    ///
+    /// Returns new revision id.
+    pub fn add_range(&mut self, from: uint, to: uint, delta: T) -> Revision {
+        self.update(from, to, Lazy::Add(delta))
+    }
+
+    // like update, but returns error instead of panic
+    fn try_update(&mut self, from: uint, to: uint, tag: Lazy<T>) -> PersResult<Revision> {
+        try!(check_range(from, to, self.len));
+        if from == to {
+            return Err(PersError::Empty);
+        }
+        Ok(self.update(from, to, tag))
+    }
+
+    /// Like ```set```, but returns ```IndexOutOfBounds``` error instead of panic.
+    pub fn try_set(&mut self, id: uint, value: T) -> PersResult<Revision> {
+        if id >= self.len {
+            return Err(PersError::IndexOutOfBounds(id, self.len));
+        }
+        Ok(self.set(id, value))
+    }
+
+    /// Like ```add```, but returns ```IndexOutOfBounds``` error instead of panic.
+    pub fn try_add(&mut self, id: uint, delta: T) -> PersResult<Revision> {
+        if id >= self.len {
+            return Err(PersError::IndexOutOfBounds(id, self.len));
+        }
+        Ok(self.add(id, delta))
+    }
+
+    /// Like ```assign_range```, but returns ```IndexOutOfBounds``` or ```Empty``` error instead
+    /// of panic.
+    pub fn try_assign_range(&mut self, from: uint, to: uint, value: T) -> PersResult<Revision> {
+        self.try_update(from, to, Lazy::Assign(value))
+    }
+
+    /// Like ```add_range```, but returns ```IndexOutOfBounds``` or ```Empty``` error instead of
+    /// panic.
+    pub fn try_add_range(&mut self, from: uint, to: uint, delta: T) -> PersResult<Revision> {
+        self.try_update(from, to, Lazy::Add(delta))
+    }
+}
+
+impl<T: Int> PersSegmentTree<T, Sum> {
+    /// Treat the tree as element counters over a value domain and return the index of the
+    /// ```k```-th (from zero) smallest value counted in this revision but not in ```older```.
+    ///
+    /// Returns ```None``` if there are not enough such values.
+    pub fn kth_smallest_since(&self, older: &PersSegmentTree<T, Sum>, k: T) -> Option<uint> {
+        assert_eq!(self.len, older.len);
+
+        let total = self.query(0, self.len) - older.query(0, older.len);
+        if k < Int::zero() || k >= total {
+            return None;
+        }
+        Some(SegNode::kth_between(&*self.root, &*older.root, self.len, k, &Lazy::Keep, &Lazy::Keep))
+    }
+}
+
+impl<T: Clone + Add<T, T>, M: Monoid<T> + Copy> Persistent<PersSegmentTree<T, M>> for PersSegmentTree<T, M> {
+    fn get_by_revision(&self, revision : Revision) -> PersSegmentTree<T, M> {
+        assert!(self.shared_data.borrow().roots.contains_key(&revision));
+
+        PersSegmentTree{line_history: vec![revision],
+                        head_revision_id: 0,
+                        root: self.shared_data.borrow().roots[revision].clone(),
+                        len: self.len,
+                        monoid: self.monoid,
+                        shared_data: self.shared_data.clone()}
+    }
+
+    fn current_revision_id(&self) -> Revision {
+        self.line_history[self.head_revision_id]
+    }
+}
+
+impl<T: Clone + Add<T, T>, M: Monoid<T> + Copy> TryPersistent<PersSegmentTree<T, M>> for PersSegmentTree<T, M> {
+    fn contains_revision(&self, revision : Revision) -> bool {
+        self.shared_data.borrow().roots.contains_key(&revision)
+    }
+}
+
+impl<T: Clone + Add<T, T>, M: Monoid<T> + Copy> Recall for PersSegmentTree<T, M> {
+    fn undo(&mut self) -> Revision {
+        assert!(self.head_revision_id > 0u);
+
+        self.head_revision_id -= 1;
+        let revision = self.line_history[self.head_revision_id];
+        self.root = self.shared_data.borrow().roots[revision].clone();
+        revision
+    }
+
+    fn redo(&mut self) -> Revision {
+        assert!(self.head_revision_id + 1u < self.line_history.len());
+
+        self.head_revision_id += 1;
+        let revision = self.line_history[self.head_revision_id];
+        self.root = self.shared_data.borrow().roots[revision].clone();
+        revision
+    }
+}
+
+impl<T: Clone + Add<T, T>, M: Monoid<T> + Copy> TryRecall for PersSegmentTree<T, M> {
+    fn can_undo(&self) -> bool {
+        self.head_revision_id > 0u
+    }
+
+    fn can_redo(&self) -> bool {
+        self.head_revision_id + 1u < self.line_history.len()
+    }
+
+    fn line_revision(&self) -> Revision {
+        self.line_history[self.head_revision_id]
+    }
+}
+
+impl<T: Clone + Add<T, T>, M: Monoid<T> + Copy> FullyPersistent<PersSegmentTree<T, M>> for PersSegmentTree<T, M> { }
+
+impl<T: Clone + Add<T, T>, M: Monoid<T> + Copy> Clone for PersSegmentTree<T, M> {
+    fn clone(&self) -> Self {
+        PersSegmentTree{line_history: self.line_history.clone(),
+                        head_revision_id: self.head_revision_id,
+                        root: self.root.clone(),
+                        len: self.len,
+                        monoid: self.monoid,
+                        shared_data: self.shared_data.clone()}
+    }
+}
+
+#[test]
+fn segment_tree_revisions_test() {
+    let mut tree = PersSegmentTree::new(range(0i, 10).collect(), Sum);
+    let rev_a = tree.set(3, 100);
+    let rev_b = tree.add_range(0, 10, 1);
+    tree.assign_range(5, 10, 0);
+
+    assert_eq!(tree.query(0, 10), 1 + 2 + 3 + 101 + 5);
+    assert_eq!(tree.get(3), 101);
+    assert_eq!(tree.query(4, 4), 0);
+
+    assert_eq!(tree.get_by_revision(rev_a).query(0, 10), 45 - 3 + 100);
+    assert_eq!(tree.get_by_revision(rev_b).query(0, 10), 45 - 3 + 100 + 10);
+    assert_eq!(tree.get_by_revision(1).get(3), 3);
+
+    let rev = tree.current_revision_id();
+    assert_eq!(tree.try_query(4, 11), Err(PersError::IndexOutOfBounds(11, 10)));
+    assert_eq!(tree.try_set(10, 0), Err(PersError::IndexOutOfBounds(10, 10)));
+    assert_eq!(tree.try_add_range(3, 3, 1), Err(PersError::Empty));
+    assert_eq!(tree.current_revision_id(), rev);
+    assert!(tree.try_add(9, 1).is_ok());
+    assert_eq!(tree.try_query(9, 10), Ok(1));
+}
+
+#[test]
+fn segment_tree_min_undoredo_test() {
+    let mut tree = PersSegmentTree::new(vec![4i, 7, 1, 9], Min);
+    tree.set(2, 8);
+    assert_eq!(tree.query(0, 4), 4);
+    tree.add_range(0, 2, 10);
+    assert_eq!(tree.query(0, 4), 8);
+
+    tree.undo();
+    assert_eq!(tree.query(0, 4), 4);
+    tree.undo();
+    assert_eq!(tree.query(1, 4), 1);
+    tree.redo_ntimes(2);
+    assert_eq!(tree.query(0, 2), 14);
+}
+
+#[test]
+fn segment_tree_kth_smallest_test() {
+    let values = [5u, 1, 4, 1, 3, 0, 2];
+    let mut counts = PersSegmentTree::new(Vec::from_elem(6, 0i), Sum);
+    let mut revs = vec![counts.current_revision_id()];
+    for &v in values.iter() {
+        revs.push(counts.add(v, 1));
+    }
+
+    for from in range(0u, values.len()) {
+        for to in range(from + 1, values.len() + 1) {
+            let mut sorted = values.slice(from, to).to_vec();
+            sorted.sort();
+            let older = counts.get_by_revision(revs[from]);
+            let newer = counts.get_by_revision(revs[to]);
+            for (k, &v) in sorted.iter().enumerate() {
+                assert_eq!(newer.kth_smallest_since(&older, k as int), Some(v));
+            }
+            assert_eq!(newer.kth_smallest_since(&older, (to - from) as int), None);
+        }
+    }
+}
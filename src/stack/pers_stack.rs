@@ -0,0 +1,252 @@
+//! Persistent stack.
+//!
+//! Stack provides O(1) worst-case push, pop and access to the top element. Every revision is a
+//! pointer to the head of an immutable linked list, so taking a snapshot costs nothing.
+
+use inner::cons_list::*;
+use error::{PersError, PersResult};
+use inner::persistent::*;
+use std::cell::RefCell;
+use std::collections::BTreeMap as TreeMap;
+use std::rc::Rc;
+use std::vec::Vec;
+
+type Node<T> = Rc<ConsList<T>>;
+type SharedData<T> = Rc<RefCell<SharedStackData<T>>>;
+
+
+
+struct SharedStackData<T> {
+    last_revision: Revision, // revision counter
+    roots:         TreeMap<Revision, (Node<T>, uint)>, // top node and length for each revision
+}
+
+/// Persistent stack implementation.
+///
+/// # Examples
+///
+/// ```
+/// let mut stack = PersStack::<int>::new();
+/// stack.push(1807);
+/// let rev = stack.push(2609);
+/// stack.pop();
+/// assert_eq!(stack.peek(), Some(&1807));
+/// assert_eq!(stack.get_by_revision(rev).peek(), Some(&2609));
+/// ```
+pub struct PersStack<T> {
+    line_history:     Vec<Revision>, // branch of history for undo-redo
+    head_revision_id: uint, // id of the current verision in line_history vector
+    root:             Node<T>, // top node for the current revision
+    len:              uint, // number of elements in the current revision
+    shared_data:      SharedData<T>, // shared data between all revision
+}
+
+impl<T: Clone> PersStack<T> {
+    /// Constructs a new, empty persistent stack.
+    pub fn new() -> PersStack<T> {
+        let mut roots = TreeMap::new();
+        roots.insert(1, (ConsList::new_empty(), 0u));
+        let shdata = Rc::new(RefCell::new(SharedStackData::<T>{last_revision: 1,
+                                                               roots: roots}));
+        PersStack{line_history: vec![1],
+                  head_revision_id: 0,
+                  root: ConsList::new_empty(),
+                  len: 0,
+                  shared_data: shdata}
+    }
+
+    fn commit(&mut self, root: Node<T>, len: uint) -> Revision {
+        let mut data = self.shared_data.borrow_mut();
+        let revision = data.last_revision + 1;
+        data.roots.insert(revision, (root.clone(), len));
+        data.last_revision = revision;
+
+        self.head_revision_id += 1;
+        self.line_history.truncate(self.head_revision_id);
+        self.line_history.push(revision);
+        self.root = root;
+        self.len = len;
+
+        revision
+    }
+
+    /// Returns the number of elements in the current stack revision.
+    pub fn len(&self) -> uint {
+        self.len
+    }
+
+    /// Returns ```true``` if the stack contains no elements and false otherwise.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the top element of the current stack revision.
+    ///
+    /// # Examples
+    /// ```
+    /// let mut stack = PersStack::<int>::new();
+    /// assert_eq!(stack.peek(), None);
+    /// stack.push(1);
+    /// assert_eq!(stack.peek(), Some(&1));
+    /// ```
+    pub fn peek<'a>(&'a self) -> Option<&'a T> {
+        self.root.head()
+    }
+
+    /// Put an element on the top of the stack.
+    ///
+    /// Returns new revision id.
+    pub fn push(&mut self, value: T) -> Revision {
+        let new_root = ConsList::push(&self.root, value);
+        let new_len = self.len + 1;
+        self.commit(new_root, new_len)
+    }
+
+    /// Remove an element from the top of the stack.
+    ///
+    /// Returns new revision id.
+    ///
+    /// # Panics
+    /// Panics if the stack is empty.
+    pub fn pop(&mut self) -> Revision {
+        assert!(self.len > 0);
+        let new_root = ConsList::tail(&self.root);
+        let new_len = self.len - 1;
+        self.commit(new_root, new_len)
+    }
+
+    /// Like ```pop```, but returns ```Empty``` error instead of panic.
+    pub fn try_pop(&mut self) -> PersResult<Revision> {
+        if self.is_empty() {
+            return Err(PersError::Empty);
+        }
+        Ok(self.pop())
+    }
+
+    /// Returns iterator from the top to the bottom of the current stack revision.
+    pub fn iter<'a>(&'a self) -> ConsListIterator<'a, T> {
+        self.root.iter()
+    }
+
+    fn restore(&mut self) {
+        let rev = self.line_history[self.head_revision_id];
+        let (root, len) = self.shared_data.borrow().roots[rev].clone();
+        self.root = root;
+        self.len = len;
+    }
+}
+
+impl<T: Clone> Persistent<PersStack<T>> for PersStack<T> {
+    fn get_by_revision(&self, revision : Revision) -> PersStack<T> {
+        assert!(self.shared_data.borrow().roots.contains_key(&revision));
+
+        let (root, len) = self.shared_data.borrow().roots[revision].clone();
+        PersStack{line_history: vec![revision],
+                  head_revision_id: 0,
+                  root: root,
+                  len: len,
+                  shared_data: self.shared_data.clone()}
+    }
+
+    fn current_revision_id(&self) -> Revision {
+        self.line_history[self.head_revision_id]
+    }
+}
+
+impl<T: Clone> TryPersistent<PersStack<T>> for PersStack<T> {
+    fn contains_revision(&self, revision : Revision) -> bool {
+        self.shared_data.borrow().roots.contains_key(&revision)
+    }
+}
+
+impl<T: Clone> Recall for PersStack<T> {
+    fn undo(&mut self) -> Revision {
+        assert!(self.head_revision_id > 0u);
+
+        self.head_revision_id -= 1;
+        self.restore();
+        self.line_history[self.head_revision_id]
+    }
+
+    fn redo(&mut self) -> Revision {
+        assert!(self.head_revision_id + 1u < self.line_history.len());
+
+        self.head_revision_id += 1;
+        self.restore();
+        self.line_history[self.head_revision_id]
+    }
+}
+
+impl<T: Clone> TryRecall for PersStack<T> {
+    fn can_undo(&self) -> bool {
+        self.head_revision_id > 0u
+    }
+
+    fn can_redo(&self) -> bool {
+        self.head_revision_id + 1u < self.line_history.len()
+    }
+
+    fn line_revision(&self) -> Revision {
+        self.line_history[self.head_revision_id]
+    }
+}
+
+impl<T: Clone> FullyPersistent<PersStack<T>> for PersStack<T> { }
+
+impl<T: Clone> Clone for PersStack<T> {
+    fn clone(&self) -> Self {
+        PersStack{line_history: self.line_history.clone(),
+                  head_revision_id: self.head_revision_id,
+                  root: self.root.clone(),
+                  len: self.len,
+                  shared_data: self.shared_data.clone()}
+    }
+}
+
+#[test]
+fn stack_push_pop_test() {
+    let mut stack = PersStack::<int>::new();
+    stack.push(1);
+    stack.push(2);
+    let rev_full = stack.push(3);
+    assert_eq!(stack.len(), 3u);
+    assert_eq!(stack.peek(), Some(&3));
+
+    stack.pop();
+    let rev_popped = stack.pop();
+    assert_eq!(stack.len(), 1u);
+    assert_eq!(stack.peek(), Some(&1));
+
+    let full = stack.get_by_revision(rev_full);
+    assert_eq!(full.iter().map(|x| *x).collect::<Vec<int>>(), vec![3, 2, 1]);
+    assert_eq!(stack.get_by_revision(rev_popped).len(), 1u);
+}
+
+#[test]
+fn stack_undoredo_test() {
+    let mut stack = PersStack::<&str>::new();
+    stack.push("one");
+    stack.push("two");
+
+    stack.undo();
+    assert_eq!(stack.peek(), Some(&"one"));
+    stack.undo();
+    assert!(stack.is_empty());
+    stack.redo_ntimes(2);
+    assert_eq!(stack.peek(), Some(&"two"));
+    assert_eq!(stack.len(), 2u);
+}
+
+#[test]
+fn stack_fully_persistent_test() {
+    let mut stack = PersStack::<int>::new();
+    stack.push(1807);
+    let rev_a = stack.push(2609);
+    stack.undo();
+    let rev_b = stack.push(1008);
+
+    let stack_a = stack.get_by_revision(rev_a);
+    let stack_b = stack.get_by_revision(rev_b);
+    assert_eq!(stack_a.iter().map(|x| *x).collect::<Vec<int>>(), vec![2609, 1807]);
+    assert_eq!(stack_b.iter().map(|x| *x).collect::<Vec<int>>(), vec![1008, 1807]);
+}
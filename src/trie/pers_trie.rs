@@ -0,0 +1,289 @@
+//! Persistent trie.
+//!
+//! Radix tree over byte-string keys. Lookup, insert and remove take O(L) time for a key of
+//! length L, independent of the number of keys, and so do prefix counting and longest-prefix
+//! match. Prefix iteration yields keys in lexicographic order. Every change is a revision
+//! with undo-redo support, and revisions share all untouched subtrees.
+
+use error::{PersError, PersResult};
+use inner::persistent::*;
+use inner::radix_tree::*;
+use std::cell::RefCell;
+use std::collections::BTreeMap as TreeMap;
+use std::rc::Rc;
+use std::vec::Vec;
+
+type Node<V> = Rc<RadixNode<V>>;
+type SharedData<V> = Rc<RefCell<SharedTrieData<V>>>;
+
+
+
+struct SharedTrieData<V> {
+    last_revision: Revision, // revision counter
+    roots:         TreeMap<Revision, Node<V>>, // root tree node for each revision
+}
+
+/// Persistent trie implementation.
+///
+/// # Examples
+///
+/// ```
+/// let mut routes = PersTrie::new();
+/// routes.insert(b"/api", 1u);
+/// let rev = routes.insert(b"/api/users", 2u);
+/// routes.remove(b"/api");
+///
+/// assert_eq!(routes.longest_prefix(b"/api/users/42"), Some((10, &2)));
+/// assert_eq!(routes.longest_prefix(b"/api/orders"), None);
+/// assert_eq!(routes.get_by_revision(rev).longest_prefix(b"/api/orders"), Some((4, &1)));
+/// ```
+///
+/// Autocomplete with prefix counting and iteration:
+///
+/// ```
+/// let mut words = PersTrie::new();
+/// for w in ["car", "cart", "care", "dog"].iter() {
+///     words.insert(w.as_bytes(), ());
+/// }
+/// assert_eq!(words.count_prefix(b"car"), 3);
+/// let found: Vec<Vec<u8>> = words.iter_prefix(b"car").map(|(k, _)| k).collect();
+/// assert_eq!(found, vec![b"car".to_vec(), b"care".to_vec(), b"cart".to_vec()]);
+/// ```
+pub struct PersTrie<V> {
+    line_history:     Vec<Revision>, // branch of history for undo-redo
+    head_revision_id: uint, // id of the current verision in line_history vector
+    root:             Node<V>, // root tree node for the current revision
+    shared_data:      SharedData<V>, // shared data between all revision
+}
+
+impl<V: Clone> PersTrie<V> {
+    /// Constructs a new, empty persistent trie.
+    pub fn new() -> PersTrie<V> {
+        let root = RadixNode::new_empty();
+        let mut roots = TreeMap::new();
+        roots.insert(1, root.clone());
+        let shdata = Rc::new(RefCell::new(SharedTrieData::<V>{last_revision: 1,
+                                                              roots: roots}));
+        PersTrie{line_history: vec![1],
+                 head_revision_id: 0,
+                 root: root,
+                 shared_data: shdata}
+    }
+
+    fn commit(&mut self, root: Node<V>) -> Revision {
+        let mut data = self.shared_data.borrow_mut();
+        let revision = data.last_revision + 1;
+        data.roots.insert(revision, root.clone());
+        data.last_revision = revision;
+
+        self.head_revision_id += 1;
+        self.line_history.truncate(self.head_revision_id);
+        self.line_history.push(revision);
+        self.root = root;
+
+        revision
+    }
+
+    /// Returns the number of keys.
+    pub fn len(&self) -> uint {
+        self.root.count()
+    }
+
+    /// Returns ```true``` if the trie contains no keys.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns a reference to the value corresponding to the key.
+    pub fn get<'a>(&'a self, key: &[u8]) -> Option<&'a V> {
+        self.root.get(key)
+    }
+
+    /// Returns ```true``` if the trie contains the key.
+    pub fn contains_key(&self, key: &[u8]) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Insert a key-value pair, replacing the old value if the key is present.
+    ///
+    /// Returns new revision id.
+    pub fn insert(&mut self, key: &[u8], value: V) -> Revision {
+        let (new_root, _) = RadixNode::insert(&self.root, key, value);
+        self.commit(new_root)
+    }
+
+    /// Remove the key. If the key is not present, the new revision has the same keys.
+    ///
+    /// Returns new revision id.
+    pub fn remove(&mut self, key: &[u8]) -> Revision {
+        let (new_root, _) = RadixNode::remove(&self.root, key);
+        self.commit(new_root)
+    }
+
+    /// Like ```remove```, but returns ```KeyNotFound``` error if there is no such key, and no
+    /// revision is created then.
+    pub fn try_remove(&mut self, key: &[u8]) -> PersResult<Revision> {
+        if !self.contains_key(key) {
+            return Err(PersError::KeyNotFound);
+        }
+        Ok(self.remove(key))
+    }
+
+    /// Returns the number of keys which start with the prefix.
+    pub fn count_prefix(&self, prefix: &[u8]) -> uint {
+        match self.root.find_prefix(prefix) {
+            Some((node, _)) => node.count(),
+            None            => 0,
+        }
+    }
+
+    /// Returns the length and the value of the longest key which is a prefix of ```key```.
+    pub fn longest_prefix<'a>(&'a self, key: &[u8]) -> Option<(uint, &'a V)> {
+        self.root.longest_prefix(key)
+    }
+
+    /// Returns iterator over all key-value pairs in lexicographic order of keys.
+    pub fn iter<'a>(&'a self) -> RadixIterator<'a, V> {
+        self.root.iter(Vec::new())
+    }
+
+    /// Returns iterator over key-value pairs which keys start with the prefix,
+    /// in lexicographic order of keys.
+    pub fn iter_prefix<'a>(&'a self, prefix: &[u8]) -> RadixIterator<'a, V> {
+        match self.root.find_prefix(prefix) {
+            Some((node, path)) => node.iter(path),
+            None               => RadixIterator::empty(),
+        }
+    }
+}
+
+impl<V: Clone> Persistent<PersTrie<V>> for PersTrie<V> {
+    fn get_by_revision(&self, revision : Revision) -> PersTrie<V> {
+        assert!(self.shared_data.borrow().roots.contains_key(&revision));
+
+        PersTrie{line_history: vec![revision],
+                 head_revision_id: 0,
+                 root: self.shared_data.borrow().roots[revision].clone(),
+                 shared_data: self.shared_data.clone()}
+    }
+
+    fn current_revision_id(&self) -> Revision {
+        self.line_history[self.head_revision_id]
+    }
+}
+
+impl<V: Clone> TryPersistent<PersTrie<V>> for PersTrie<V> {
+    fn contains_revision(&self, revision : Revision) -> bool {
+        self.shared_data.borrow().roots.contains_key(&revision)
+    }
+}
+
+impl<V: Clone> Recall for PersTrie<V> {
+    fn undo(&mut self) -> Revision {
+        assert!(self.head_revision_id > 0u);
+
+        self.head_revision_id -= 1;
+        let revision = self.line_history[self.head_revision_id];
+        self.root = self.shared_data.borrow().roots[revision].clone();
+        revision
+    }
+
+    fn redo(&mut self) -> Revision {
+        assert!(self.head_revision_id + 1u < self.line_history.len());
+
+        self.head_revision_id += 1;
+        let revision = self.line_history[self.head_revision_id];
+        self.root = self.shared_data.borrow().roots[revision].clone();
+        revision
+    }
+}
+
+impl<V: Clone> TryRecall for PersTrie<V> {
+    fn can_undo(&self) -> bool {
+        self.head_revision_id > 0u
+    }
+
+    fn can_redo(&self) -> bool {
+        self.head_revision_id + 1u < self.line_history.len()
+    }
+
+    fn line_revision(&self) -> Revision {
+        self.line_history[self.head_revision_id]
+    }
+}
+
+impl<V: Clone> FullyPersistent<PersTrie<V>> for PersTrie<V> { }
+
+impl<V: Clone> Clone for PersTrie<V> {
+    fn clone(&self) -> Self {
+        PersTrie{line_history: self.line_history.clone(),
+                 head_revision_id: self.head_revision_id,
+                 root: self.root.clone(),
+                 shared_data: self.shared_data.clone()}
+    }
+}
+
+#[test]
+fn trie_revisions_test() {
+    let mut trie = PersTrie::new();
+    let rev_a = trie.insert(b"apple", 1i);
+    trie.insert(b"app", 2);
+    let rev_b = trie.insert(b"apply", 3);
+    trie.remove(b"apple");
+    trie.insert(b"app", 4);
+
+    assert_eq!(trie.len(), 2u);
+    assert_eq!(trie.get(b"app"), Some(&4));
+    assert!(!trie.contains_key(b"apple"));
+
+    let old = trie.get_by_revision(rev_b);
+    assert_eq!(old.len(), 3u);
+    assert_eq!(old.get(b"app"), Some(&2));
+    assert_eq!(old.get(b"apple"), Some(&1));
+    assert_eq!(trie.get_by_revision(rev_a).count_prefix(b"app"), 1u);
+}
+
+#[test]
+fn trie_prefix_test() {
+    let mut trie = PersTrie::new();
+    for (i, w) in ["10.0.0", "10.0.1", "10.1", "192.168", "10"].iter().enumerate() {
+        trie.insert(w.as_bytes(), i);
+    }
+    assert_eq!(trie.count_prefix(b"10"), 4u);
+    assert_eq!(trie.count_prefix(b"10.0."), 2u);
+    assert_eq!(trie.count_prefix(b"11"), 0u);
+    assert_eq!(trie.count_prefix(b""), 5u);
+
+    let keys: Vec<Vec<u8>> = trie.iter_prefix(b"10.").map(|(k, _)| k).collect();
+    assert_eq!(keys, vec![b"10.0.0".to_vec(), b"10.0.1".to_vec(), b"10.1".to_vec()]);
+    assert_eq!(trie.iter_prefix(b"2").count(), 0u);
+
+    assert_eq!(trie.longest_prefix(b"10.0.1.5"), Some((6, &1)));
+    assert_eq!(trie.longest_prefix(b"10.2"), Some((2, &4)));
+    assert_eq!(trie.longest_prefix(b"1"), None);
+}
+
+#[test]
+fn trie_undoredo_test() {
+    let mut trie = PersTrie::new();
+    trie.insert(b"a", 'a');
+    trie.insert(b"b", 'b');
+    trie.remove(b"a");
+    assert_eq!(trie.iter().count(), 1u);
+
+    trie.undo();
+    assert_eq!(trie.iter().map(|(_, &v)| v).collect::<Vec<char>>(), vec!['a', 'b']);
+    trie.undo_ntimes(2);
+    assert!(trie.is_empty());
+    trie.redo();
+    assert_eq!(trie.get(b"a"), Some(&'a'));
+
+    let before = trie.current_revision_id();
+    assert_eq!(trie.try_remove(b"c"), Err(PersError::KeyNotFound));
+    assert_eq!(trie.current_revision_id(), before);
+    let rev = trie.remove(b"c");
+    assert_eq!(trie.current_revision_id(), rev);
+    assert_eq!(trie.iter().count(), 1u);
+    trie.undo();
+    assert_eq!(trie.get(b"a"), Some(&'a'));
+}
@@ -0,0 +1,296 @@
+//! Persistent union-find.
+//!
+//! Disjoint-set forest in the style of Conchon and Filliatre: parents and ranks are kept in
+//! persistent arrays, ```union``` by rank creates a new revision, and ```find``` compresses
+//! paths of the revision it is called on without changing what any revision means. Going back
+//! to a recent revision, which is what backtracking solvers do, costs only the number of array
+//! writes made since then.
+
+use error::{PersError, PersResult};
+use inner::pers_array::PArray;
+use inner::persistent::*;
+use std::cell::RefCell;
+use std::collections::BTreeMap as TreeMap;
+use std::rc::Rc;
+use std::vec::Vec;
+
+type SharedData = Rc<RefCell<SharedUnionFindData>>;
+
+
+
+struct SharedUnionFindData {
+    last_revision: Revision, // revision counter
+    roots:         TreeMap<Revision, (PArray<uint>, PArray<uint>)>, // parents and ranks for each revision
+}
+
+/// Persistent union-find implementation.
+///
+/// # Examples
+///
+/// ```
+/// let mut sets = PersUnionFind::new(4);
+/// let rev = sets.union(0, 1);
+/// sets.union(2, 3);
+/// sets.union(1, 2);
+/// assert!(sets.same_set(0, 3));
+///
+/// sets.undo();
+/// assert!(!sets.same_set(0, 3));
+/// assert!(!sets.get_by_revision(rev).same_set(2, 3));
+/// ```
+pub struct PersUnionFind {
+    line_history:     Vec<Revision>, // branch of history for undo-redo
+    head_revision_id: uint, // id of the current verision in line_history vector
+    parent:           RefCell<PArray<uint>>, // parents for the current revision, compressed by find
+    rank:             PArray<uint>, // ranks for the current revision
+    shared_data:      SharedData, // shared data between all revision
+}
+
+impl PersUnionFind {
+    /// Constructs a new persistent union-find of ```size``` singleton sets.
+    pub fn new(size: uint) -> PersUnionFind {
+        let parent = PArray::from_vec(range(0, size).collect());
+        let rank = PArray::from_vec(Vec::from_elem(size, 0u));
+        let mut roots = TreeMap::new();
+        roots.insert(1, (parent.clone(), rank.clone()));
+        let shdata = Rc::new(RefCell::new(SharedUnionFindData{last_revision: 1,
+                                                              roots: roots}));
+        PersUnionFind{line_history: vec![1],
+                      head_revision_id: 0,
+                      parent: RefCell::new(parent),
+                      rank: rank,
+                      shared_data: shdata}
+    }
+
+    fn commit(&mut self, parent: PArray<uint>, rank: PArray<uint>) -> Revision {
+        let mut data = self.shared_data.borrow_mut();
+        let revision = data.last_revision + 1;
+        data.roots.insert(revision, (parent.clone(), rank.clone()));
+        data.last_revision = revision;
+
+        self.head_revision_id += 1;
+        self.line_history.truncate(self.head_revision_id);
+        self.line_history.push(revision);
+        self.parent = RefCell::new(parent);
+        self.rank = rank;
+
+        revision
+    }
+
+    fn restore_head(&mut self) {
+        let rev = self.line_history[self.head_revision_id];
+        let (parent, rank) = self.shared_data.borrow().roots[rev].clone();
+        self.parent = RefCell::new(parent);
+        self.rank = rank;
+    }
+
+    /// Returns the number of elements.
+    pub fn len(&self) -> uint {
+        self.rank.len()
+    }
+
+    /// Returns the representative of the set which contains ```x``` in the current revision.
+    ///
+    /// # Panics
+    /// Panics if ```x``` is out of bounds.
+    pub fn find(&self, x: uint) -> uint {
+        assert!(x < self.len());
+
+        let mut parent = self.parent.borrow_mut();
+        let mut path = Vec::new();
+        let mut root = x;
+        loop {
+            let p = parent.get(root);
+            if p == root {
+                break;
+            }
+            path.push(root);
+            root = p;
+        }
+
+        // path compression is invisible for users, so it does not create a revision
+        for &node in path.iter() {
+            if parent.get(node) != root {
+                let compressed = parent.set(node, root);
+                *parent = compressed;
+            }
+        }
+        root
+    }
+
+    /// Like ```find```, but returns ```IndexOutOfBounds``` error instead of panic.
+    pub fn try_find(&self, x: uint) -> PersResult<uint> {
+        if x >= self.len() {
+            return Err(PersError::IndexOutOfBounds(x, self.len()));
+        }
+        Ok(self.find(x))
+    }
+
+    /// Returns ```true``` if ```x``` and ```y``` are in the same set in the current revision.
+    pub fn same_set(&self, x: uint, y: uint) -> bool {
+        self.find(x) == self.find(y)
+    }
+
+    /// Merge sets which contain ```x``` and ```y```.
+    ///
+    /// Returns new revision id. A revision is created even if the elements are already in one
+    /// set, so every call is exactly one ```undo``` step.
+    ///
+    /// # Panics
+    /// Panics if ```x``` or ```y``` is out of bounds.
+    pub fn union(&mut self, x: uint, y: uint) -> Revision {
+        let rx = self.find(x);
+        let ry = self.find(y);
+        let parent = self.parent.borrow().clone();
+        if rx == ry {
+            let rank = self.rank.clone();
+            return self.commit(parent, rank);
+        }
+
+        let (rank_x, rank_y) = (self.rank.get(rx), self.rank.get(ry));
+        if rank_x < rank_y {
+            let rank = self.rank.clone();
+            self.commit(parent.set(rx, ry), rank)
+        } else if rank_x > rank_y {
+            let rank = self.rank.clone();
+            self.commit(parent.set(ry, rx), rank)
+        } else {
+            let rank = self.rank.set(rx, rank_x + 1);
+            self.commit(parent.set(ry, rx), rank)
+        }
+    }
+
+    /// Like ```union```, but returns ```IndexOutOfBounds``` error instead of panic.
+    pub fn try_union(&mut self, x: uint, y: uint) -> PersResult<Revision> {
+        for &id in [x, y].iter() {
+            if id >= self.len() {
+                return Err(PersError::IndexOutOfBounds(id, self.len()));
+            }
+        }
+        Ok(self.union(x, y))
+    }
+}
+
+impl Persistent<PersUnionFind> for PersUnionFind {
+    fn get_by_revision(&self, revision : Revision) -> PersUnionFind {
+        assert!(self.shared_data.borrow().roots.contains_key(&revision));
+
+        let (parent, rank) = self.shared_data.borrow().roots[revision].clone();
+        PersUnionFind{line_history: vec![revision],
+                      head_revision_id: 0,
+                      parent: RefCell::new(parent),
+                      rank: rank,
+                      shared_data: self.shared_data.clone()}
+    }
+
+    fn current_revision_id(&self) -> Revision {
+        self.line_history[self.head_revision_id]
+    }
+}
+
+impl TryPersistent<PersUnionFind> for PersUnionFind {
+    fn contains_revision(&self, revision : Revision) -> bool {
+        self.shared_data.borrow().roots.contains_key(&revision)
+    }
+}
+
+impl Recall for PersUnionFind {
+    fn undo(&mut self) -> Revision {
+        assert!(self.head_revision_id > 0u);
+
+        self.head_revision_id -= 1;
+        self.restore_head();
+        self.line_history[self.head_revision_id]
+    }
+
+    fn redo(&mut self) -> Revision {
+        assert!(self.head_revision_id + 1u < self.line_history.len());
+
+        self.head_revision_id += 1;
+        self.restore_head();
+        self.line_history[self.head_revision_id]
+    }
+}
+
+impl TryRecall for PersUnionFind {
+    fn can_undo(&self) -> bool {
+        self.head_revision_id > 0u
+    }
+
+    fn can_redo(&self) -> bool {
+        self.head_revision_id + 1u < self.line_history.len()
+    }
+
+    fn line_revision(&self) -> Revision {
+        self.line_history[self.head_revision_id]
+    }
+}
+
+impl FullyPersistent<PersUnionFind> for PersUnionFind { }
+
+impl Clone for PersUnionFind {
+    fn clone(&self) -> Self {
+        PersUnionFind{line_history: self.line_history.clone(),
+                      head_revision_id: self.head_revision_id,
+                      parent: RefCell::new(self.parent.borrow().clone()),
+                      rank: self.rank.clone(),
+                      shared_data: self.shared_data.clone()}
+    }
+}
+
+#[test]
+fn union_find_test() {
+    let mut sets = PersUnionFind::new(10);
+    for i in range(0u, 10) {
+        if i % 2 == 0 && i + 2 < 10 {
+            sets.union(i, i + 2);
+        }
+    }
+    // even elements are joined into one set, odd ones stay single
+    for i in range(0u, 10) {
+        for j in range(0u, 10) {
+            let both_even = i % 2 == 0 && j % 2 == 0;
+            assert_eq!(sets.same_set(i, j), both_even || i == j);
+        }
+    }
+}
+
+#[test]
+fn union_find_undoredo_test() {
+    let mut sets = PersUnionFind::new(5);
+    sets.union(0, 1);
+    sets.union(3, 4);
+    sets.union(1, 4);
+    assert!(sets.same_set(0, 3));
+
+    sets.undo();
+    assert!(!sets.same_set(0, 3));
+    assert!(sets.same_set(3, 4));
+    sets.undo_ntimes(2);
+    assert!(!sets.same_set(0, 1));
+
+    sets.redo_ntimes(3);
+    assert!(sets.same_set(0, 4));
+
+    assert_eq!(sets.try_find(5), Err(PersError::IndexOutOfBounds(5, 5)));
+    assert_eq!(sets.try_union(2, 7), Err(PersError::IndexOutOfBounds(7, 5)));
+    assert!(sets.try_union(2, 3).is_ok());
+    assert_eq!(sets.try_find(2), sets.try_find(4));
+}
+
+#[test]
+fn union_find_fully_persistent_test() {
+    let mut sets = PersUnionFind::new(4);
+    sets.union(0, 1);
+    let rev_a = sets.union(1, 2);
+    sets.undo();
+    let rev_b = sets.union(1, 3);
+
+    let sets_a = sets.get_by_revision(rev_a);
+    let sets_b = sets.get_by_revision(rev_b);
+    assert!(sets_a.same_set(0, 2));
+    assert!(!sets_a.same_set(0, 3));
+    assert!(sets_b.same_set(0, 3));
+    assert!(!sets_b.same_set(0, 2));
+    assert!(sets.same_set(0, 3));
+}
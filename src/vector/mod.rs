@@ -1 +1,2 @@
 pub mod pers_vector;
+pub mod pers_rrb_vector;
@@ -0,0 +1,361 @@
+//! Persistent RRB-vector.
+//!
+//! Indexable sequence on a relaxed radix balanced tree. Indexed access and update take
+//! O(log32(N)) time, push and pop are amortized O(1) thanks to the tail buffer, and
+//! concatenation, split and slice take O(lg(N)) time. Every change is a revision with
+//! undo-redo support; a snapshot is a root pointer, so checkout and clone are O(1).
+
+use error::{check_range, PersError, PersResult};
+use inner::persistent::*;
+use inner::rrb_tree::*;
+use std::cell::RefCell;
+use std::collections::BTreeMap as TreeMap;
+use std::rc::Rc;
+use std::vec::Vec;
+
+type SharedData<T> = Rc<RefCell<SharedRrbVectorData<T>>>;
+
+
+
+struct SharedRrbVectorData<T> {
+    last_revision: Revision, // revision counter
+    roots:         TreeMap<Revision, RrbTree<T>>, // tree for each revision
+}
+
+/// Persistent RRB-vector implementation.
+///
+/// # Examples
+///
+/// ```
+/// let mut vec = PersRrbVector::from_vec(range(0i, 100).collect());
+/// let rev = vec.set(10, -1);
+/// let other = PersRrbVector::from_vec(vec![100i, 101]);
+/// vec.append(&other);
+///
+/// assert_eq!(vec.len(), 102);
+/// assert_eq!(vec[10], -1);
+/// assert_eq!(vec[101], 101);
+/// assert_eq!(vec.get_by_revision(rev).len(), 100);
+///
+/// let tail = vec.split_off(50);
+/// assert_eq!(tail[0], 50);
+/// assert_eq!(vec.len(), 50);
+/// ```
+pub struct PersRrbVector<T> {
+    line_history:     Vec<Revision>, // branch of history for undo-redo
+    head_revision_id: uint, // id of the current verision in line_history vector
+    tree:             RrbTree<T>, // tree for the current revision
+    shared_data:      SharedData<T>, // shared data between all revision
+}
+
+impl<T: Clone> PersRrbVector<T> {
+    /// Constructs a new, empty persistent vector.
+    pub fn new() -> PersRrbVector<T> {
+        PersRrbVector::from_vec(Vec::new())
+    }
+
+    /// Constructs a new persistent vector which initial revision holds the given elements.
+    pub fn from_vec(items: Vec<T>) -> PersRrbVector<T> {
+        let tree = RrbTree::from_vec(items);
+        let mut roots = TreeMap::new();
+        roots.insert(1, tree.clone());
+        let shdata = Rc::new(RefCell::new(SharedRrbVectorData::<T>{last_revision: 1,
+                                                                   roots: roots}));
+        PersRrbVector{line_history: vec![1],
+                      head_revision_id: 0,
+                      tree: tree,
+                      shared_data: shdata}
+    }
+
+    fn register(&self, tree: RrbTree<T>) -> Revision {
+        let mut data = self.shared_data.borrow_mut();
+        let revision = data.last_revision + 1;
+        data.roots.insert(revision, tree);
+        data.last_revision = revision;
+        revision
+    }
+
+    fn commit(&mut self, tree: RrbTree<T>) -> Revision {
+        let revision = self.register(tree.clone());
+
+        self.head_revision_id += 1;
+        self.line_history.truncate(self.head_revision_id);
+        self.line_history.push(revision);
+        self.tree = tree;
+
+        revision
+    }
+
+    /// Returns the number of elements in the current revision.
+    pub fn len(&self) -> uint {
+        self.tree.len()
+    }
+
+    /// Returns ```true``` if the current revision contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns a reference to the element by its index, or ```None``` if out of bounds.
+    pub fn get<'a>(&'a self, id: uint) -> Option<&'a T> {
+        if id < self.len() {
+            Some(self.tree.lookup(id))
+        } else {
+            None
+        }
+    }
+
+    /// Replace the element by its index.
+    ///
+    /// Returns new revision id.
+    ///
+    /// # Panics
+    /// Panics if ```id``` is out of bounds.
+    pub fn set(&mut self, id: uint, value: T) -> Revision {
+        assert!(id < self.len());
+
+        let new_tree = self.tree.update(id, value);
+        self.commit(new_tree)
+    }
+
+    /// Like ```set```, but returns ```IndexOutOfBounds``` error instead of panic.
+    pub fn try_set(&mut self, id: uint, value: T) -> PersResult<Revision> {
+        if id >= self.len() {
+            return Err(PersError::IndexOutOfBounds(id, self.len()));
+        }
+        Ok(self.set(id, value))
+    }
+
+    /// Append an element to the back.
+    ///
+    /// Returns new revision id.
+    pub fn push(&mut self, value: T) -> Revision {
+        let new_tree = self.tree.push(value);
+        self.commit(new_tree)
+    }
+
+    /// Remove the last element.
+    ///
+    /// Returns new revision id.
+    ///
+    /// # Panics
+    /// Panics if the vector is empty.
+    pub fn pop(&mut self) -> Revision {
+        assert!(!self.is_empty());
+
+        let new_tree = self.tree.pop();
+        self.commit(new_tree)
+    }
+
+    /// Like ```pop```, but returns ```Empty``` error instead of panic.
+    pub fn try_pop(&mut self) -> PersResult<Revision> {
+        if self.is_empty() {
+            return Err(PersError::Empty);
+        }
+        Ok(self.pop())
+    }
+
+    /// Append all elements of the ```other``` current revision to the end of the vector.
+    ///
+    /// ```other``` may belong to a different history, its nodes are shared, not copied.
+    ///
+    /// Returns new revision id.
+    pub fn append(&mut self, other: &PersRrbVector<T>) -> Revision {
+        let new_tree = self.tree.concat(&other.tree);
+        self.commit(new_tree)
+    }
+
+    /// Split the vector into two at the given index.
+    ///
+    /// The vector keeps elements ```[0, at)``` in a new revision, and the returned vector holds
+    /// elements ```[at, len)```. Both revisions are stored in the shared history.
+    ///
+    /// # Panics
+    /// Panics if ```at > len```.
+    pub fn split_off(&mut self, at: uint) -> PersRrbVector<T> {
+        assert!(at <= self.len());
+
+        let (left, right) = self.tree.split(at);
+        self.commit(left);
+        let revision = self.register(right.clone());
+        PersRrbVector{line_history: vec![revision],
+                      head_revision_id: 0,
+                      tree: right,
+                      shared_data: self.shared_data.clone()}
+    }
+
+    /// Like ```split_off```, but returns ```IndexOutOfBounds``` error instead of panic.
+    pub fn try_split_off(&mut self, at: uint) -> PersResult<PersRrbVector<T>> {
+        if at > self.len() {
+            return Err(PersError::IndexOutOfBounds(at, self.len()));
+        }
+        Ok(self.split_off(at))
+    }
+
+    /// Keep only elements ```[from, to)```.
+    ///
+    /// Returns new revision id.
+    ///
+    /// # Panics
+    /// Panics if the range is out of bounds.
+    pub fn slice(&mut self, from: uint, to: uint) -> Revision {
+        assert!(from <= to && to <= self.len());
+
+        let new_tree = self.tree.slice(from, to);
+        self.commit(new_tree)
+    }
+
+    /// Like ```slice```, but returns ```IndexOutOfBounds``` error instead of panic.
+    pub fn try_slice(&mut self, from: uint, to: uint) -> PersResult<Revision> {
+        try!(check_range(from, to, self.len()));
+        Ok(self.slice(from, to))
+    }
+
+    /// Returns iterator over elements of the current revision.
+    pub fn iter<'a>(&'a self) -> RrbIterator<'a, T> {
+        self.tree.iter()
+    }
+}
+
+impl<T: Clone> Index<uint, T> for PersRrbVector<T> {
+    fn index<'a>(&'a self, id: &uint) -> &'a T {
+        self.tree.lookup(*id)
+    }
+}
+
+impl<T: Clone> Extend<T> for PersRrbVector<T> {
+    fn extend<I: Iterator<T>>(&mut self, mut iterator: I) {
+        for element in iterator {
+            self.push(element);
+        }
+    }
+}
+
+impl<T: Clone> Persistent<PersRrbVector<T>> for PersRrbVector<T> {
+    fn get_by_revision(&self, revision : Revision) -> PersRrbVector<T> {
+        assert!(self.shared_data.borrow().roots.contains_key(&revision));
+
+        PersRrbVector{line_history: vec![revision],
+                      head_revision_id: 0,
+                      tree: self.shared_data.borrow().roots[revision].clone(),
+                      shared_data: self.shared_data.clone()}
+    }
+
+    fn current_revision_id(&self) -> Revision {
+        self.line_history[self.head_revision_id]
+    }
+}
+
+impl<T: Clone> TryPersistent<PersRrbVector<T>> for PersRrbVector<T> {
+    fn contains_revision(&self, revision : Revision) -> bool {
+        self.shared_data.borrow().roots.contains_key(&revision)
+    }
+}
+
+impl<T: Clone> Recall for PersRrbVector<T> {
+    fn undo(&mut self) -> Revision {
+        assert!(self.head_revision_id > 0u);
+
+        self.head_revision_id -= 1;
+        let revision = self.line_history[self.head_revision_id];
+        self.tree = self.shared_data.borrow().roots[revision].clone();
+        revision
+    }
+
+    fn redo(&mut self) -> Revision {
+        assert!(self.head_revision_id + 1u < self.line_history.len());
+
+        self.head_revision_id += 1;
+        let revision = self.line_history[self.head_revision_id];
+        self.tree = self.shared_data.borrow().roots[revision].clone();
+        revision
+    }
+}
+
+impl<T: Clone> TryRecall for PersRrbVector<T> {
+    fn can_undo(&self) -> bool {
+        self.head_revision_id > 0u
+    }
+
+    fn can_redo(&self) -> bool {
+        self.head_revision_id + 1u < self.line_history.len()
+    }
+
+    fn line_revision(&self) -> Revision {
+        self.line_history[self.head_revision_id]
+    }
+}
+
+impl<T: Clone> FullyPersistent<PersRrbVector<T>> for PersRrbVector<T> { }
+
+impl<T: Clone> Clone for PersRrbVector<T> {
+    fn clone(&self) -> Self {
+        PersRrbVector{line_history: self.line_history.clone(),
+                      head_revision_id: self.head_revision_id,
+                      tree: self.tree.clone(),
+                      shared_data: self.shared_data.clone()}
+    }
+}
+
+#[test]
+fn rrb_vector_revisions_test() {
+    let mut vec = PersRrbVector::new();
+    vec.extend(range(0u, 1000));
+    let rev = vec.current_revision_id();
+    for i in range(0u, 1000) {
+        vec.set(i, i * 2);
+    }
+    vec.slice(100, 900);
+
+    assert_eq!(vec.len(), 800u);
+    assert_eq!(vec[0], 200u);
+    assert_eq!(vec.get(800), None);
+    let old = vec.get_by_revision(rev);
+    assert_eq!(old.iter().map(|&v| v).collect::<Vec<uint>>(), range(0u, 1000).collect::<Vec<uint>>());
+}
+
+#[test]
+fn rrb_vector_concat_split_test() {
+    let mut vec = PersRrbVector::from_vec(range(0u, 10).collect());
+    for n in range(1u, 30) {
+        let part = PersRrbVector::from_vec(range(0u, n * 13).map(|i| i + vec.len()).collect());
+        vec.append(&part);
+    }
+    for (i, &v) in vec.iter().enumerate() {
+        assert_eq!(v, i);
+    }
+
+    let len = vec.len();
+    let mut right = vec.split_off(len / 3);
+    let tail = right.split_off(len / 3);
+    assert_eq!(vec.len() + right.len() + tail.len(), len);
+    assert_eq!(right[0], len / 3);
+    assert_eq!(tail[0], len / 3 * 2);
+    assert_eq!(*tail.iter().last().unwrap(), len - 1);
+}
+
+#[test]
+fn rrb_vector_undoredo_test() {
+    let mut vec = PersRrbVector::new();
+    for i in range(0i, 100) {
+        vec.push(i);
+    }
+    vec.pop();
+    vec.set(0, -1);
+    assert_eq!(vec.len(), 99u);
+
+    vec.undo_ntimes(2);
+    assert_eq!(vec.len(), 100u);
+    assert_eq!(vec[0], 0);
+    vec.redo_ntimes(2);
+    assert_eq!(vec[0], -1);
+    vec.undo_ntimes(102);
+    assert!(vec.is_empty());
+
+    vec.redo_ntimes(100);
+    assert_eq!(vec.try_slice(10, 101), Err(PersError::IndexOutOfBounds(101, 100)));
+    assert!(vec.try_split_off(101).is_err());
+    assert_eq!(vec.len(), 100u);
+    assert!(vec.try_slice(10, 20).is_ok());
+    assert_eq!(vec.try_split_off(5).unwrap()[0], 15);
+}
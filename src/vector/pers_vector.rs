@@ -1,25 +1,34 @@
 //! Persistent vector.
 //!
-//! Vector provides O(1) access to array element by indexes, push to the end and pop from the end.
+//! Every element lives in a slot, a fat node which keeps all values written to the slot, and
+//! every revision keeps the layout of the vector: a path-copying radix tree of slot ids with
+//! the values they hold in the revision. Access to an element takes O(log32(N)) time, the
+//! last writer of an element is read from its fat node. Checkout of any revision, undo, redo and clone
+//! take O(1) time. An update writes only the slots it changes and copies O(log32(N)) layout
+//! nodes, so insert and remove do not touch the shifted elements.
 
-use std::iter::repeat;
+use std::iter::{repeat, Take};
+use error::{check_range, PersError, PersResult};
 use inner::persistent::*;
+use inner::rrb_tree::*;
 use inner::versioned_fat_node::*;
 use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::BTreeMap as TreeMap;
 use std::rc::Rc;
-use std::slice::Iter;
 use std::vec::Vec;
 
+type Layout<T> = RrbTree<(uint, Rc<T>)>;
 type SharedData<T> = Rc<RefCell<VectorSharedData<T>>>;
 
 
 
 struct VectorSharedData<T> {
-    last_revision: Revision,
+    last_revision: Revision, // revision counter
     version_tree:  Rc<RefCell<VersionTree>>,
 
-    ary:           Vec<VersionedFatNode<Option<Rc<T>>>>,
-    len:           uint,
+    ary:           Vec<VersionedFatNode<Rc<T>>>, // slots of all revisions
+    layouts:       TreeMap<Revision, Layout<T>>, // slots and values of each revision
 }
 
 /// Persistent vector implementation.
@@ -91,7 +100,7 @@ struct VectorSharedData<T> {
 pub struct PersVector<T> {
     line_history:     Vec<Revision>, // branch of history for undo-redo
     head_revision_id: uint, // id of the current verision in line_history vector
-    ary:              Vec<Rc<T>>, // array for the current revision
+    layout:           Layout<T>, // slots and values for the current revision
 
     shared_data:      SharedData<T>, // shared data between all revision
 }
@@ -106,18 +115,56 @@ impl<T: Clone> PersVector<T> {
     /// let mut pvec: PersVector<int> = PersVector::new();
     /// ```
     pub fn new() -> PersVector<T> {
-        let vtree = Rc::new(RefCell::new(VersionTree::new(1)));
+        let mut layouts = TreeMap::new();
+        layouts.insert(1, RrbTree::new_empty());
         let shdata = Rc::new(RefCell::new(VectorSharedData::<T>{last_revision: 1,
-                                                                version_tree: vtree,
+                                                                version_tree: new_vtree(1),
                                                                 ary: Vec::new(),
-                                                                len: 0}));
+                                                                layouts: layouts}));
         PersVector{line_history: vec![1],
                    head_revision_id: 0,
-                   ary: Vec::new(),
+                   layout: RrbTree::new_empty(),
                    shared_data: shdata}
     }
 
+    // new revision made from the parent one, writes are slots with their new values
+    fn register(&self, layout: Layout<T>, writes: Vec<(uint, Rc<T>)>,
+                parent: Revision) -> Revision {
+        let mut shdata = self.shared_data.borrow_mut();
+        let new_rev = shdata.last_revision + 1;
+        shdata.version_tree.borrow_mut().insert(new_rev, parent);
+        for (slot, value) in writes.into_iter() {
+            shdata.ary[slot].set_value(new_rev, value);
+        }
+        shdata.layouts.insert(new_rev, layout);
+        shdata.last_revision = new_rev;
+        new_rev
+    }
+
+    fn commit(&mut self, layout: Layout<T>, writes: Vec<(uint, Rc<T>)>) -> Revision {
+        let new_rev = self.register(layout.clone(), writes, self.current_revision_id());
+
+        self.head_revision_id += 1;
+        self.line_history.truncate(self.head_revision_id);
+        self.line_history.push(new_rev);
+        self.layout = layout;
 
+        new_rev
+    }
+
+    fn new_slot(&self) -> uint {
+        let mut shdata = self.shared_data.borrow_mut();
+        let vtree = shdata.version_tree.clone();
+        shdata.ary.push(VersionedFatNode::new(vtree));
+        shdata.ary.len() - 1
+    }
+
+    // layout of new slots for the values and the writes which fill them
+    fn fill(&self, values: Vec<Rc<T>>) -> (Layout<T>, Vec<(uint, Rc<T>)>) {
+        let writes: Vec<(uint, Rc<T>)> = values.into_iter().map(|value| (self.new_slot(), value))
+                                                           .collect();
+        (RrbTree::from_vec(writes.clone()), writes)
+    }
 
     /// Returns the number of elements in the current vector revision.
     ///
@@ -129,7 +176,7 @@ impl<T: Clone> PersVector<T> {
     /// assert_eq!(vec.len(), 2);
     /// ```
     pub fn len(&self) -> uint {
-        self.ary.len()
+        self.layout.len()
     }
 
     /// Returns ```true``` if the vector contains no elements and false otherwise.
@@ -143,7 +190,7 @@ impl<T: Clone> PersVector<T> {
     /// assert!(!vec.empty());
     /// ```
     pub fn is_empty(&self) -> bool {
-        self.ary.is_empty()
+        self.len() == 0
     }
 
     /// Append an element to the end of the vector.
@@ -156,27 +203,9 @@ impl<T: Clone> PersVector<T> {
     /// vec.push("pysch");
     /// ```
     pub fn push(&mut self, value: T) -> Revision {
-        let mut shdata = self.shared_data.deref().borrow_mut();
-        let old_rev = self.current_revision_id();
-        let new_rev = shdata.last_revision + 1;
-
-        // 1. update shared data
-        shdata.last_revision = new_rev;
-        let value_id = self.ary.len();
-        if value_id == shdata.ary.len() {
-            let vtree_pointer = shdata.version_tree.clone();
-            shdata.ary.push(VersionedFatNode::new(vtree_pointer));
-        }
-        shdata.ary[value_id].add_value(new_rev, Some(Rc::new(value)), old_rev);
-        shdata.len += 1;
-
-        // 2. update local data
-        self.head_revision_id += 1;
-        self.line_history.truncate(self.head_revision_id);
-        self.line_history.push(new_rev);
-        self.ary.push(shdata.ary[value_id].value(new_rev).unwrap().unwrap());
-
-        new_rev
+        let (slot, value) = (self.new_slot(), Rc::new(value));
+        let new_layout = self.layout.push((slot, value.clone()));
+        self.commit(new_layout, vec![(slot, value)])
     }
 
     /// Remove an element from the end of the vector.
@@ -193,24 +222,18 @@ impl<T: Clone> PersVector<T> {
     /// vec.pop();
     /// ```
     pub fn pop(&mut self) -> Revision {
-        assert!(self.ary.len() > 0);
-        let mut shdata = self.shared_data.deref().borrow_mut();
-        let old_rev = self.current_revision_id();
-        let new_rev = shdata.last_revision + 1;
-
-        // 1. update shared data
-        shdata.last_revision = new_rev;
-        let value_id = self.ary.len() - 1;
-        shdata.ary[value_id].add_value(new_rev, None, old_rev);
-        shdata.len -= 1;
+        assert!(self.len() > 0);
 
-        // 2. update local data
-        self.head_revision_id += 1;
-        self.line_history.truncate(self.head_revision_id);
-        self.line_history.push(new_rev);
-        self.ary.pop();
+        let new_layout = self.layout.pop();
+        self.commit(new_layout, Vec::new())
+    }
 
-        new_rev
+    /// Like ```pop```, but returns ```Empty``` error instead of panic.
+    pub fn try_pop(&mut self) -> PersResult<Revision> {
+        if self.is_empty() {
+            return Err(PersError::Empty);
+        }
+        Ok(self.pop())
     }
 
     /// Modify element in the vectory by it index.
@@ -227,25 +250,23 @@ impl<T: Clone> PersVector<T> {
     /// vec.modify(0, "pysch");
     /// ```
     pub fn modify(&mut self, id: uint, value: T) -> Revision {
-        assert!(id < self.ary.len());
-        let mut shdata = self.shared_data.deref().borrow_mut();
-        let old_rev = self.current_revision_id();
-        let new_rev = shdata.last_revision + 1;
+        assert!(id < self.len());
 
-        // 1. update shared data
-        shdata.last_revision = new_rev;
-        shdata.ary[id].add_value(new_rev, Some(Rc::new(value)), old_rev);
-
-        // 2. update local data
-        self.head_revision_id += 1;
-        self.line_history.truncate(self.head_revision_id);
-        self.line_history.push(new_rev);
-        self.ary[id] = shdata.ary[id].value(new_rev).unwrap().unwrap();
+        let &(slot, _) = self.layout.lookup(id);
+        let value = Rc::new(value);
+        let new_layout = self.layout.update(id, (slot, value.clone()));
+        self.commit(new_layout, vec![(slot, value)])
+    }
 
-        new_rev
+    /// Like ```modify```, but returns ```IndexOutOfBounds``` error instead of panic.
+    pub fn try_modify(&mut self, id: uint, value: T) -> PersResult<Revision> {
+        if id >= self.len() {
+            return Err(PersError::IndexOutOfBounds(id, self.len()));
+        }
+        Ok(self.modify(id, value))
     }
 
-    /// Returns random-access iterator to the current revision vector.
+    /// Returns iterator over elements of the current revision vector.
     ///
     /// # Exmaples
     /// ```
@@ -255,8 +276,8 @@ impl<T: Clone> PersVector<T> {
     ///     println!("one more vector element is {}", i.deref());
     /// }
     /// ```
-    pub fn iter<'a>(&'a self) -> Iter<'a, Rc<T>> {
-        self.ary.iter()
+    pub fn iter<'a>(&'a self) -> Items<'a, T> {
+        Items{entries: self.layout.iter().take(self.len())}
     }
 
     /// Shorten the vector, dropping excess elements.
@@ -298,11 +319,455 @@ impl<T: Clone> PersVector<T> {
         let old_len = self.len();
         self.extend(repeat(value).take(new_len - old_len));
     }
+
+    // layout with slots [from, to) replaced by the middle layout
+    fn replace_range(&self, from: uint, to: uint, middle: &Layout<T>) -> Layout<T> {
+        let (left, rest) = self.layout.split(from);
+        let (_, right) = rest.split(to - from);
+        left.concat(middle).concat(&right)
+    }
+
+    /// Insert an element at position ```id```, shifting all elements after it to the right.
+    ///
+    /// Returns new revision id.
+    ///
+    /// # Panics
+    /// Panics if ```id > len```.
+    ///
+    /// # Examples
+    /// ```
+    /// let mut vec = PersVector::<int>::new();
+    /// vec.push(1);
+    /// vec.push(3);
+    /// vec.insert(1, 2);
+    /// assert_eq!(vec[1], 2);
+    /// ```
+    pub fn insert(&mut self, id: uint, value: T) -> Revision {
+        self.splice(id, id, vec![value])
+    }
+
+    /// Like ```insert```, but returns ```IndexOutOfBounds``` error instead of panic.
+    pub fn try_insert(&mut self, id: uint, value: T) -> PersResult<Revision> {
+        if id > self.len() {
+            return Err(PersError::IndexOutOfBounds(id, self.len()));
+        }
+        Ok(self.insert(id, value))
+    }
+
+    /// Remove the element at position ```id```, shifting all elements after it to the left.
+    ///
+    /// Returns new revision id.
+    ///
+    /// # Panics
+    /// Panics if the index is out of bounds.
+    pub fn remove(&mut self, id: uint) -> Revision {
+        assert!(id < self.len());
+
+        self.splice(id, id + 1, Vec::new())
+    }
+
+    /// Like ```remove```, but returns ```IndexOutOfBounds``` error instead of panic.
+    pub fn try_remove(&mut self, id: uint) -> PersResult<Revision> {
+        if id >= self.len() {
+            return Err(PersError::IndexOutOfBounds(id, self.len()));
+        }
+        Ok(self.remove(id))
+    }
+
+    /// Swap two elements of the vector.
+    ///
+    /// Returns new revision id.
+    ///
+    /// # Panics
+    /// Panics if any index is out of bounds.
+    pub fn swap(&mut self, a: uint, b: uint) -> Revision {
+        assert!(a < self.len() && b < self.len());
+
+        let (slot_a, value_a) = self.layout.lookup(a).clone();
+        let (slot_b, value_b) = self.layout.lookup(b).clone();
+        let new_layout = self.layout.update(a, (slot_a, value_b.clone()))
+                                    .update(b, (slot_b, value_a.clone()));
+        self.commit(new_layout, vec![(slot_a, value_b), (slot_b, value_a)])
+    }
+
+    /// Like ```swap```, but returns ```IndexOutOfBounds``` error instead of panic.
+    pub fn try_swap(&mut self, a: uint, b: uint) -> PersResult<Revision> {
+        for &id in [a, b].iter() {
+            if id >= self.len() {
+                return Err(PersError::IndexOutOfBounds(id, self.len()));
+            }
+        }
+        Ok(self.swap(a, b))
+    }
+
+    /// Remove elements ```[from, to)``` in one revision and return them.
+    ///
+    /// # Panics
+    /// Panics if the range is out of bounds.
+    ///
+    /// # Examples
+    /// ```
+    /// let mut vec = PersVector::<int>::new();
+    /// vec.extend(range(0i, 5));
+    /// let removed = vec.drain(1, 3);
+    /// assert_eq!(*removed[0], 1);
+    /// assert_eq!(vec.len(), 3);
+    /// ```
+    pub fn drain(&mut self, from: uint, to: uint) -> Vec<Rc<T>> {
+        assert!(from <= to && to <= self.len());
+
+        let removed = self.layout.slice(from, to).iter().map(|&(_, ref value)| value.clone())
+                                                        .collect();
+        let new_layout = self.replace_range(from, to, &RrbTree::new_empty());
+        self.commit(new_layout, Vec::new());
+        removed
+    }
+
+    /// Like ```drain```, but returns ```IndexOutOfBounds``` error instead of panic.
+    pub fn try_drain(&mut self, from: uint, to: uint) -> PersResult<Vec<Rc<T>>> {
+        try!(check_range(from, to, self.len()));
+        Ok(self.drain(from, to))
+    }
+
+    /// Replace elements ```[from, to)``` with ```values``` in one revision.
+    ///
+    /// Only the new elements are written, elements after the range are not touched.
+    ///
+    /// Returns new revision id.
+    ///
+    /// # Panics
+    /// Panics if the range is out of bounds.
+    pub fn splice(&mut self, from: uint, to: uint, values: Vec<T>) -> Revision {
+        assert!(from <= to && to <= self.len());
+
+        let (middle, writes) = self.fill(values.into_iter().map(|value| Rc::new(value)).collect());
+        let new_layout = self.replace_range(from, to, &middle);
+        self.commit(new_layout, writes)
+    }
+
+    /// Like ```splice```, but returns ```IndexOutOfBounds``` error instead of panic.
+    pub fn try_splice(&mut self, from: uint, to: uint, values: Vec<T>) -> PersResult<Revision> {
+        try!(check_range(from, to, self.len()));
+        Ok(self.splice(from, to, values))
+    }
+
+    /// Append all elements of the ```other``` current revision to the end of the vector.
+    ///
+    /// ```other``` may belong to a different history. Its elements are written to new slots
+    /// of this vector, the values are shared, not copied.
+    ///
+    /// Returns new revision id.
+    pub fn append(&mut self, other: &PersVector<T>) -> Revision {
+        let (middle, writes) = self.fill(other.iter().collect());
+        let new_layout = self.layout.concat(&middle);
+        self.commit(new_layout, writes)
+    }
+
+    /// Split the vector into two at the given index.
+    ///
+    /// The vector keeps elements ```[0, at)``` in a new revision, and the returned vector holds
+    /// elements ```[at, len)```. Both revisions are stored in the shared history.
+    ///
+    /// # Panics
+    /// Panics if ```at > len```.
+    ///
+    /// # Examples
+    /// ```
+    /// let mut vec = PersVector::<int>::new();
+    /// vec.extend(range(0i, 10));
+    /// let tail = vec.split_off(4);
+    /// assert_eq!(vec.len(), 4);
+    /// assert_eq!(tail[0], 4);
+    /// ```
+    pub fn split_off(&mut self, at: uint) -> PersVector<T> {
+        assert!(at <= self.len());
+
+        let old_rev = self.current_revision_id();
+        let (left, right) = self.layout.split(at);
+        self.commit(left, Vec::new());
+        let revision = self.register(right.clone(), Vec::new(), old_rev);
+        PersVector{line_history: vec![revision],
+                   head_revision_id: 0,
+                   layout: right,
+                   shared_data: self.shared_data.clone()}
+    }
+
+    /// Like ```split_off```, but returns ```IndexOutOfBounds``` error instead of panic.
+    pub fn try_split_off(&mut self, at: uint) -> PersResult<PersVector<T>> {
+        if at > self.len() {
+            return Err(PersError::IndexOutOfBounds(at, self.len()));
+        }
+        Ok(self.split_off(at))
+    }
+
+    /// Returns read-only view of elements ```[from, to)``` of the current revision.
+    ///
+    /// The view shares the revision and stays valid after the vector is changed.
+    ///
+    /// # Panics
+    /// Panics if the range is out of bounds.
+    ///
+    /// # Examples
+    /// ```
+    /// let mut vec = PersVector::<int>::new();
+    /// vec.extend(range(0i, 10));
+    /// let view = vec.slice(2, 5);
+    /// vec.modify(2, 100);
+    /// assert_eq!(view[0], 2);
+    /// assert_eq!(view.len(), 3);
+    /// ```
+    pub fn slice(&self, from: uint, to: uint) -> VectorSlice<T> {
+        assert!(from <= to && to <= self.len());
+
+        VectorSlice{layout: self.layout.clone(),
+                    from: from,
+                    to: to}
+    }
+
+    /// Like ```slice```, but returns ```IndexOutOfBounds``` error instead of panic.
+    pub fn try_slice(&self, from: uint, to: uint) -> PersResult<VectorSlice<T>> {
+        try!(check_range(from, to, self.len()));
+        Ok(self.slice(from, to))
+    }
+
+    /// Returns changes of the slot ```id``` along the history of ```revision```, from the initial
+    /// revision to the given one.
+    ///
+    /// Every item is the revision which changed the slot and the value it holds after that
+    /// revision, or ```None``` if the slot was removed. Operations which shift elements change
+    /// every shifted slot. Takes O(D log32(N)) time for a revision of depth D.
+    ///
+    /// # Panics
+    /// Panics if the revision does not exist.
+    ///
+    /// # Examples
+    /// ```
+    /// let mut vec = PersVector::<int>::new();
+    /// let rev_a = vec.push(1);
+    /// vec.push(2);
+    /// let rev_b = vec.modify(0, 3);
+    /// assert_eq!(vec.history(0, rev_b), vec![(rev_a, Some(1)), (rev_b, Some(3))]);
+    /// ```
+    pub fn history(&self, id: uint, revision: Revision) -> Vec<(Revision, Option<T>)> {
+        let shdata = self.shared_data.borrow();
+        assert!(shdata.layouts.contains_key(&revision));
+
+        let mut branch = shdata.version_tree.borrow().parent_branch(revision);
+        branch.reverse();
+
+        let mut changes = Vec::new();
+        let mut last: Option<&(uint, Rc<T>)> = None;
+        for rev in branch.into_iter() {
+            let layout = &shdata.layouts[rev];
+            let current = if id < layout.len() { Some(layout.lookup(id)) } else { None };
+            let changed = match (last, current) {
+                (None, None)                       => false,
+                (Some(&(old_slot, ref old_value)),
+                 Some(&(slot, ref value)))         => old_slot != slot || !ptr_eq(old_value, value),
+                _                                  => true,
+            };
+            if changed {
+                changes.push((rev, current.map(|&(_, ref value)| value.deref().clone())));
+            }
+            last = current;
+        }
+        changes
+    }
+
+    /// Returns for every element of ```revision``` the revision which last wrote it.
+    ///
+    /// # Panics
+    /// Panics if the revision does not exist.
+    pub fn blame(&self, revision: Revision) -> Vec<Revision> {
+        let shdata = self.shared_data.borrow();
+        assert!(shdata.layouts.contains_key(&revision));
+
+        shdata.layouts[revision].iter()
+                                .map(|&(slot, _)| shdata.ary[slot].writer(revision).unwrap())
+                                .collect()
+    }
+
+    // commit the new values of the elements, only changed slots are written
+    fn rewrite(&mut self, values: Vec<Rc<T>>) -> Revision {
+        assert_eq!(values.len(), self.len());
+
+        let mut entries = Vec::with_capacity(values.len());
+        let mut writes = Vec::new();
+        for (&(slot, ref old), value) in self.layout.iter().zip(values.into_iter()) {
+            if !ptr_eq(old, &value) {
+                writes.push((slot, value.clone()));
+            }
+            entries.push((slot, value));
+        }
+        self.commit(RrbTree::from_vec(entries), writes)
+    }
+
+    /// Sort the vector with a comparator function in one revision.
+    ///
+    /// The sort is stable.
+    ///
+    /// Returns new revision id.
+    pub fn sort_by(&mut self, compare: |&T, &T| -> Ordering) -> Revision {
+        let mut values: Vec<Rc<T>> = self.iter().collect();
+        values.sort_by(|a, b| compare(a.deref(), b.deref()));
+        self.rewrite(values)
+    }
+
+    /// Reverse the order of elements in one revision.
+    ///
+    /// Returns new revision id.
+    pub fn reverse(&mut self) -> Revision {
+        let mut values: Vec<Rc<T>> = self.iter().collect();
+        values.reverse();
+        self.rewrite(values)
+    }
+
+    /// Retain only the elements for which the predicate returns ```true```, in one revision.
+    ///
+    /// Returns new revision id.
+    ///
+    /// # Examples
+    /// ```
+    /// let mut vec = PersVector::<int>::new();
+    /// vec.extend(range(0i, 10));
+    /// vec.retain(|&x| x % 2 == 0);
+    /// assert_eq!(vec.len(), 5);
+    /// vec.undo();
+    /// assert_eq!(vec.len(), 10);
+    /// ```
+    pub fn retain(&mut self, predicate: |&T| -> bool) -> Revision {
+        let entries = self.layout.iter().take(self.len())
+                                        .filter(|&&(_, ref value)| predicate(value.deref()))
+                                        .map(|entry| entry.clone())
+                                        .collect();
+        self.commit(RrbTree::from_vec(entries), Vec::new())
+    }
+
+    /// Replace every element with the result of the function, in one revision.
+    ///
+    /// Returns new revision id.
+    pub fn map_in_place(&mut self, f: |&T| -> T) -> Revision {
+        let values: Vec<Rc<T>> = self.iter().map(|value| Rc::new(f(value.deref()))).collect();
+        self.rewrite(values)
+    }
+}
+
+impl<T: Clone + Ord> PersVector<T> {
+    /// Sort the vector in one revision.
+    ///
+    /// Returns new revision id.
+    ///
+    /// # Examples
+    /// ```
+    /// let mut vec = PersVector::<int>::new();
+    /// vec.extend(vec![3i, 1, 2].into_iter());
+    /// vec.sort();
+    /// assert_eq!(vec[0], 1);
+    /// vec.undo();
+    /// assert_eq!(vec[0], 3);
+    /// ```
+    pub fn sort(&mut self) -> Revision {
+        self.sort_by(|a, b| a.cmp(b))
+    }
+}
+
+impl<T: Clone + PartialEq> PersVector<T> {
+    /// Remove consecutive repeated elements in one revision.
+    ///
+    /// Returns new revision id.
+    pub fn dedup(&mut self) -> Revision {
+        let mut entries: Vec<(uint, Rc<T>)> = Vec::with_capacity(self.len());
+        for &(slot, ref value) in self.layout.iter().take(self.len()) {
+            let repeated = match entries.last() {
+                Some(&(_, ref last)) => last == value,
+                None                 => false,
+            };
+            if !repeated {
+                entries.push((slot, value.clone()));
+            }
+        }
+        self.commit(RrbTree::from_vec(entries), Vec::new())
+    }
+}
+
+fn ptr_eq<T>(a: &Rc<T>, b: &Rc<T>) -> bool {
+    a.deref() as *const T == b.deref() as *const T
+}
+
+/// Iterator over elements of a ```PersVector``` revision.
+pub struct Items<'a, T: 'a> {
+    entries: Take<RrbIterator<'a, (uint, Rc<T>)>>,
+}
+
+impl<'a, T: Clone + 'a> Iterator<Rc<T>> for Items<'a, T> {
+    fn next(&mut self) -> Option<Rc<T>> {
+        self.entries.next().map(|&(_, ref value)| value.clone())
+    }
+
+    fn size_hint(&self) -> (uint, Option<uint>) {
+        self.entries.size_hint()
+    }
+}
+
+/// Read-only view of a range of ```PersVector``` revision.
+pub struct VectorSlice<T> {
+    layout: Layout<T>, // slots and values of the viewed revision
+    from:   uint,
+    to:     uint,
+}
+
+impl<T: Clone> VectorSlice<T> {
+    /// Returns the number of elements in the view.
+    pub fn len(&self) -> uint {
+        self.to - self.from
+    }
+
+    /// Returns ```true``` if the view contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns a reference to the element by its index in the view, or ```None``` if out of bounds.
+    pub fn get<'a>(&'a self, id: uint) -> Option<&'a T> {
+        if id < self.len() {
+            let &(_, ref value) = self.layout.lookup(self.from + id);
+            Some(value.deref())
+        } else {
+            None
+        }
+    }
+
+    /// Returns view of elements ```[from, to)``` of this view.
+    ///
+    /// # Panics
+    /// Panics if the range is out of bounds.
+    pub fn slice(&self, from: uint, to: uint) -> VectorSlice<T> {
+        assert!(from <= to && to <= self.len());
+
+        VectorSlice{layout: self.layout.clone(),
+                    from: self.from + from,
+                    to: self.from + to}
+    }
+
+    /// Returns iterator over elements of the view.
+    pub fn iter<'a>(&'a self) -> Items<'a, T> {
+        Items{entries: self.layout.iter_from(self.from).take(self.len())}
+    }
+}
+
+impl<T: Clone> Index<uint, T> for VectorSlice<T> {
+    fn index<'a>(&'a self, id: &uint) -> &'a T {
+        assert!(*id < self.len());
+
+        let &(_, ref value) = self.layout.lookup(self.from + *id);
+        value.deref()
+    }
 }
 
 impl<T: Clone> Index<uint, T> for PersVector<T> {
     fn index<'a>(&'a self, id: &uint) -> &'a T {
-        self.ary[*id].deref()
+        let &(_, ref value) = self.layout.lookup(*id);
+        value.deref()
     }
 }
 
@@ -316,21 +781,11 @@ impl<T: Clone> Extend<T> for PersVector<T> {
 
 impl<T: Clone> Persistent<PersVector<T>> for PersVector<T> {
     fn get_by_revision(&self, revision : Revision) -> PersVector<T> {
-        assert!(revision <= self.shared_data.deref().borrow().last_revision);
-
-        let mut result_vector = Vec::<Rc<T>>::new();
-        {
-            let origin_ary = &self.shared_data.deref().borrow().ary;
-            for it in origin_ary.iter() {
-                match it.value(revision).unwrap_or(None) {
-                    Some(ref rct) => result_vector.push(rct.clone()),
-                    None          => break,
-                };
-            }
-        }
+        assert!(self.shared_data.borrow().layouts.contains_key(&revision));
+
         PersVector{line_history: vec![revision],
                    head_revision_id: 0,
-                   ary: result_vector,
+                   layout: self.shared_data.borrow().layouts[revision].clone(),
                    shared_data: self.shared_data.clone()}
     }
 
@@ -339,13 +794,19 @@ impl<T: Clone> Persistent<PersVector<T>> for PersVector<T> {
     }
 }
 
+impl<T: Clone> TryPersistent<PersVector<T>> for PersVector<T> {
+    fn contains_revision(&self, revision : Revision) -> bool {
+        self.shared_data.borrow().layouts.contains_key(&revision)
+    }
+}
+
 impl<T: Clone> Recall for PersVector<T> {
     fn undo(&mut self) -> Revision {
         assert!(self.head_revision_id > 0u);
 
         self.head_revision_id -= 1;
         let revision = self.line_history[self.head_revision_id];
-        self.ary = self.get_by_revision(revision).ary;
+        self.layout = self.shared_data.borrow().layouts[revision].clone();
         revision
     }   
 
@@ -354,18 +815,32 @@ impl<T: Clone> Recall for PersVector<T> {
 
         self.head_revision_id += 1;
         let revision = self.line_history[self.head_revision_id];
-        self.ary = self.get_by_revision(revision).ary;
+        self.layout = self.shared_data.borrow().layouts[revision].clone();
         revision
     }
 }
 
+impl<T: Clone> TryRecall for PersVector<T> {
+    fn can_undo(&self) -> bool {
+        self.head_revision_id > 0u
+    }
+
+    fn can_redo(&self) -> bool {
+        self.head_revision_id + 1u < self.line_history.len()
+    }
+
+    fn line_revision(&self) -> Revision {
+        self.line_history[self.head_revision_id]
+    }
+}
+
 impl<T: Clone> FullyPersistent<PersVector<T>> for PersVector<T> { }
 
 impl<T: Clone> Clone for PersVector<T> {
     fn clone(&self) -> Self {
         PersVector{line_history: self.line_history.clone(),
                    head_revision_id: self.head_revision_id,
-                   ary: self.ary.clone(),
+                   layout: self.layout.clone(),
                    shared_data: self.shared_data.clone() }
     }
 }
@@ -503,3 +978,212 @@ fn vec_iterator_test() {
     }
     assert_eq!(expected_value, 10);
 }
+
+#[test]
+fn vec_deep_history_test() {
+    let mut vector = PersVector::<uint>::new();
+    let mut revisions = Vec::new();
+    for i in range(0u, 3000) {
+        revisions.push(vector.push(i));
+    }
+    for i in range(0u, 3000) {
+        vector.modify(i, i * 2);
+    }
+
+    let snapshot = vector.get_by_revision(revisions[1999]);
+    assert_eq!(snapshot.len(), 2000u);
+    assert_eq!(snapshot[1999], 1999u);
+    assert_eq!(vector[1999], 3998u);
+
+    vector.undo_ntimes(3000);
+    assert_eq!(vector.len(), 3000u);
+    assert_eq!(vector[2999], 2999u);
+    let copy = vector.clone();
+    vector.redo();
+    assert_eq!(vector[0], 0u);
+    assert_eq!(copy[0], 0u);
+}
+
+#[test]
+fn vec_insert_remove_test() {
+    let mut vector = PersVector::<int>::new();
+    vector.extend(range(0i, 100));
+    let rev_before = vector.current_revision_id();
+
+    vector.insert(0, -1);
+    vector.insert(101, 100);
+    vector.remove(50);
+    let rev_swap = vector.swap(0, 100);
+    assert_eq!(vector.len(), 101u);
+    assert_eq!(vector[0], 100);
+    assert_eq!(vector[49], 48);
+    assert_eq!(vector[50], 50);
+    assert_eq!(vector[100], -1);
+
+    let removed = vector.drain(10, 20);
+    assert_eq!(removed.iter().map(|v| **v).collect::<Vec<int>>(), range(9i, 19).collect::<Vec<int>>());
+    vector.splice(0, 1, vec![7, 8, 9]);
+    assert_eq!(vector.len(), 93u);
+    assert_eq!(vector[2], 9);
+    assert_eq!(vector[3], 0);
+
+    let before = vector.get_by_revision(rev_before);
+    assert_eq!(before.len(), 100u);
+    assert_eq!(before.iter().map(|v| *v).collect::<Vec<int>>(), range(0i, 100).collect::<Vec<int>>());
+    assert_eq!(vector.get_by_revision(rev_swap)[0], 100);
+
+    vector.undo_ntimes(2);
+    assert_eq!(vector.current_revision_id(), rev_swap);
+}
+
+#[test]
+fn vec_split_append_test() {
+    let mut vector = PersVector::<int>::new();
+    vector.extend(range(0i, 1000));
+    let rev_full = vector.current_revision_id();
+
+    let mut tail = vector.split_off(600);
+    assert_eq!(vector.len(), 600u);
+    assert_eq!(tail.len(), 400u);
+    assert_eq!(tail[0], 600);
+
+    tail.append(&vector);
+    assert_eq!(tail.len(), 1000u);
+    assert_eq!(tail[399], 999);
+    assert_eq!(tail[400], 0);
+    assert_eq!(vector.get_by_revision(rev_full)[700], 700);
+
+    tail.undo();
+    assert_eq!(tail.len(), 400u);
+}
+
+#[test]
+fn vec_slice_test() {
+    let mut vector = PersVector::<int>::new();
+    vector.extend(range(0i, 500));
+    let view = vector.slice(100, 400);
+    vector.truncate(0);
+    let first = &view[0];
+    vector.extend(range(0i, 3));
+    assert_eq!(*first, 100);
+
+    assert_eq!(view.len(), 300u);
+    assert_eq!(view[0], 100);
+    assert_eq!(view.get(300), None);
+    assert_eq!(view.iter().map(|v| *v).collect::<Vec<int>>(), range(100i, 400).collect::<Vec<int>>());
+
+    let inner = view.slice(50, 60);
+    assert_eq!(inner.iter().map(|v| *v).collect::<Vec<int>>(), range(150i, 160).collect::<Vec<int>>());
+    assert!(view.slice(10, 10).is_empty());
+}
+
+#[test]
+fn vec_algorithms_test() {
+    let mut vector = PersVector::<int>::new();
+    vector.extend(vec![5i, 3, 3, 8, 1, 1, 1, 9].into_iter());
+    let rev_start = vector.current_revision_id();
+
+    vector.dedup();
+    assert_eq!(vector.iter().map(|v| *v).collect::<Vec<int>>(), vec![5i, 3, 8, 1, 9]);
+    vector.sort();
+    assert_eq!(vector.iter().map(|v| *v).collect::<Vec<int>>(), vec![1i, 3, 5, 8, 9]);
+    vector.reverse();
+    vector.retain(|&x| x != 5);
+    vector.map_in_place(|&x| x * 10);
+    assert_eq!(vector.iter().map(|v| *v).collect::<Vec<int>>(), vec![90i, 80, 30, 10]);
+    vector.sort_by(|a, b| (a % 30).cmp(&(b % 30)));
+    assert_eq!(vector.iter().map(|v| *v).collect::<Vec<int>>(), vec![90i, 30, 10, 80]);
+
+    vector.undo_ntimes(6);
+    assert_eq!(vector.current_revision_id(), rev_start);
+    assert_eq!(vector.len(), 8u);
+}
+
+#[test]
+fn vec_rewrite_sharing_test() {
+    let mut vector = PersVector::<int>::new();
+    vector.extend(range(0i, 100));
+    vector.modify(10, -1);
+    let before = vector.current();
+    vector.sort();
+
+    assert_eq!(vector[0], -1);
+    assert_eq!(vector[10], 9);
+    assert!(ptr_eq(&vector.iter().last().unwrap(), &before.iter().last().unwrap()));
+}
+
+#[test]
+fn vec_rewrite_writes_test() {
+    let mut vector = PersVector::<int>::new();
+    let rev_a = vector.push(1);
+    let rev_b = vector.push(2);
+    let rev_c = vector.push(3);
+    let rev_d = vector.push(4);
+
+    let rev_e = vector.retain(|&x| x != 2);
+    assert_eq!(vector.blame(rev_e), vec![rev_a, rev_c, rev_d]);
+    let rev_f = vector.map_in_place(|&x| if x == 3 { 30 } else { x });
+    assert_eq!(vector.blame(rev_f), vec![rev_a, rev_f, rev_d]);
+    vector.push(4);
+    let rev_h = vector.dedup();
+    assert_eq!(vector.blame(rev_h), vec![rev_a, rev_f, rev_d]);
+    let rev_i = vector.reverse();
+    assert_eq!(vector.blame(rev_i), vec![rev_i, rev_f, rev_i]);
+    assert_eq!(vector.blame(rev_d), vec![rev_a, rev_b, rev_c, rev_d]);
+}
+
+#[test]
+fn vec_history_blame_test() {
+    let mut vector = PersVector::<int>::new();
+    let rev_a = vector.push(10);
+    let rev_b = vector.push(20);
+    let rev_c = vector.modify(0, 11);
+    let rev_d = vector.pop();
+    vector.undo();
+    let rev_e = vector.modify(1, 21);
+    let rev_f = vector.insert(0, 0);
+
+    assert_eq!(vector.history(0, rev_c), vec![(rev_a, Some(10)), (rev_c, Some(11))]);
+    assert_eq!(vector.history(1, rev_d), vec![(rev_b, Some(20)), (rev_d, None)]);
+    assert_eq!(vector.history(1, rev_e), vec![(rev_b, Some(20)), (rev_e, Some(21))]);
+    assert_eq!(vector.history(2, rev_f), vec![(rev_f, Some(21))]);
+    assert_eq!(vector.history(0, rev_f), vec![(rev_a, Some(10)), (rev_c, Some(11)), (rev_f, Some(0))]);
+
+    assert_eq!(vector.blame(rev_c), vec![rev_c, rev_b]);
+    assert_eq!(vector.blame(rev_e), vec![rev_c, rev_e]);
+    assert_eq!(vector.blame(rev_f), vec![rev_f, rev_c, rev_e]);
+}
+
+#[test]
+fn vec_try_test() {
+    let mut vector = PersVector::<int>::new();
+    assert_eq!(vector.try_pop(), Err(PersError::Empty));
+    assert_eq!(vector.try_undo(), Err(PersError::NothingToUndo));
+    assert!(vector.try_get_by_revision(2).is_err());
+
+    let rev = vector.push(1);
+    assert_eq!(vector.try_modify(1, 2), Err(PersError::IndexOutOfBounds(1, 1)));
+    assert_eq!(vector.try_insert(2, 2), Err(PersError::IndexOutOfBounds(2, 1)));
+    assert_eq!(vector.try_redo(), Err(PersError::NothingToRedo));
+    assert_eq!(vector.try_get_by_revision(rev).unwrap()[0], 1);
+
+    vector.push(2);
+    assert_eq!(vector.try_undo_ntimes(3), Err(PersError::NothingToUndo));
+    assert_eq!(vector.len(), 2u);
+    assert_eq!(vector.try_undo_ntimes(2), Ok(1));
+    assert!(vector.is_empty());
+    assert_eq!(vector.try_undo_ntimes(0), Ok(1));
+
+    vector.extend(range(0i, 5));
+    let rev = vector.current_revision_id();
+    assert_eq!(vector.try_swap(1, 5), Err(PersError::IndexOutOfBounds(5, 5)));
+    assert_eq!(vector.try_drain(3, 2), Err(PersError::IndexOutOfBounds(3, 2)));
+    assert_eq!(vector.try_splice(0, 6, vec![]), Err(PersError::IndexOutOfBounds(6, 5)));
+    assert!(vector.try_split_off(6).is_err());
+    assert!(vector.try_slice(2, 7).is_err());
+    assert_eq!(vector.current_revision_id(), rev);
+
+    assert_eq!(vector.try_slice(1, 3).unwrap()[1], 2);
+    assert_eq!(vector.try_split_off(4).unwrap()[0], 4);
+    assert_eq!(vector.len(), 4u);
+}